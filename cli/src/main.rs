@@ -1,17 +1,189 @@
 use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
+use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use idm_core::config::EngineConfig;
+use idm_core::checksum::ChecksumRequest;
+use idm_core::config::{EngineConfig, OnExisting};
+use idm_core::cookies::{cookies_for_url, parse_netscape_cookies};
+use idm_core::engine::EngineStats;
+use idm_core::ipc::{send_request, IpcRequest, IpcResponse};
 use idm_core::storage::SqliteStorage;
-use idm_core::{DownloadEngine, TaskId, TaskStatus};
+use idm_core::template::OutputTemplate;
+use idm_core::throttle::parse_rate;
+use idm_core::torrent::is_torrent;
+use idm_core::task::{format_age, format_rfc3339, TaskExport};
+use idm_core::{DownloadEngine, Task, TaskBuilder, TaskFilter, TaskId, TaskStatus};
 
 fn main() {
-    let engine = match build_engine() {
+    let mut args: Vec<String> = env::args().collect();
+    let mut config = EngineConfig::default();
+
+    let limit = match take_flag(&mut args, "--limit") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+    if let Some(value) = limit {
+        match parse_rate(&value) {
+            Some(limit) if limit > 0 => config.global_speed_limit_bytes_per_sec = Some(limit),
+            Some(_) => config.global_speed_limit_bytes_per_sec = None,
+            None => {
+                eprintln!("invalid --limit value: {}", value);
+                return;
+            }
+        }
+    }
+    let bearer = match take_flag(&mut args, "--bearer") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+
+    let user_agent = match take_flag(&mut args, "--user-agent") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+
+    let output_template = match take_flag(&mut args, "--output-template") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+    if let Some(value) = output_template {
+        match OutputTemplate::parse(&value) {
+            Ok(template) => config.output_template = Some(template),
+            Err(err) => {
+                eprintln!("invalid --output-template: {}", err);
+                return;
+            }
+        }
+    }
+
+    let on_existing = match take_flag(&mut args, "--on-existing") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+    if let Some(value) = on_existing {
+        config.on_existing = match value.as_str() {
+            "overwrite" => OnExisting::Overwrite,
+            "skip" => OnExisting::Skip,
+            "rename" => OnExisting::Rename,
+            _ => {
+                eprintln!(
+                    "invalid --on-existing value: {} (overwrite, skip or rename)",
+                    value
+                );
+                return;
+            }
+        };
+    }
+
+    let bytes = match take_flag(&mut args, "--bytes") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+    let byte_limit = match bytes.as_deref().map(parse_rate) {
+        None => None,
+        Some(Some(limit)) if limit > 0 => Some(limit),
+        Some(_) => {
+            eprintln!("invalid --bytes value: {}", bytes.unwrap_or_default());
+            return;
+        }
+    };
+
+    let after = match take_flag(&mut args, "--after") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+    let depends_on = match after.as_deref().map(TaskId::parse_str) {
+        None => None,
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            eprintln!("invalid --after task id: {}", after.unwrap_or_default());
+            return;
+        }
+    };
+
+    let post_data = match take_flag(&mut args, "--post-data") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+
+    let cookies_file = match take_flag(&mut args, "--cookies-file") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+
+    config.decompress_on_complete = take_switch(&mut args, "--decompress");
+    config.flat_output = take_switch(&mut args, "--flat");
+    let extract = take_switch(&mut args, "--extract");
+    let watch = take_switch(&mut args, "--watch");
+    let paused = take_switch(&mut args, "--paused");
+    let no_resolve = take_switch(&mut args, "--no-resolve");
+    let skip_head = take_switch(&mut args, "--skip-head");
+
+    let daemon = match take_flag(&mut args, "--daemon") {
+        Ok(value) => value,
+        Err(()) => {
+            print_usage();
+            return;
+        }
+    };
+    if let Some(socket) = daemon {
+        let task_flags = [
+            ("--paused", paused),
+            ("--after", depends_on.is_some()),
+            ("--bytes", byte_limit.is_some()),
+            ("--no-resolve", no_resolve),
+            ("--skip-head", skip_head),
+            ("--post-data", post_data.is_some()),
+            ("--cookies-file", cookies_file.is_some()),
+            ("--extract", extract),
+            ("--bearer", bearer.is_some()),
+            ("--user-agent", user_agent.is_some()),
+        ];
+        if let Some((flag, _)) = task_flags.iter().find(|(_, set)| *set) {
+            eprintln!("error: {} is not available with --daemon", flag);
+            process::exit(1);
+        }
+        if !run_daemon_command(Path::new(&socket), &args, watch) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    let engine = match build_engine(config) {
         Ok(engine) => engine,
         Err(err) => {
             eprintln!("error: {}", err);
@@ -20,13 +192,12 @@ fn main() {
     };
     let engine = Arc::new(engine);
 
-    let args: Vec<String> = env::args().collect();
-
     if args.len() < 2 {
         print_usage();
         return;
     }
 
+    let mut verify_failed = false;
     match args[1].as_str() {
         "add" => {
             let url = match args.get(2) {
@@ -40,19 +211,90 @@ fn main() {
             if dest.is_empty() {
                 println!("dest kosong, nama file akan diambil otomatis");
             }
-            match engine.add_task(url, dest) {
+            let mut builder = TaskBuilder::new(url.clone(), dest)
+                .auto_extract(extract)
+                .skip_resolution(no_resolve)
+                .skip_head(skip_head)
+                .byte_limit(byte_limit);
+            if paused {
+                builder = builder.paused();
+            }
+            if let Some(id) = depends_on {
+                builder = builder.depends_on(id);
+            }
+            if let Some(data) = &post_data {
+                builder = builder.post("application/x-www-form-urlencoded", data.clone());
+            }
+            if let Some(user_agent) = &user_agent {
+                builder = builder.user_agent(user_agent.clone());
+            }
+            if let Some(token) = &bearer {
+                builder = builder.header("Authorization", format!("Bearer {}", token));
+            }
+            if let Some(path) = &cookies_file {
+                match load_cookies(path, &url) {
+                    Ok(cookies) => {
+                        for (name, value) in cookies {
+                            builder = builder.cookie(name, value);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        return;
+                    }
+                }
+            }
+            let added = if !no_resolve && is_torrent(&url, None) {
+                builder.build().and_then(|task| engine.add_torrent(task))
+            } else {
+                engine.add_task_with(builder)
+            };
+            match added {
                 Ok(id) => println!("added task: {}", id),
                 Err(err) => eprintln!("error: {}", err),
             }
         }
         "list" => match engine.list_tasks() {
             Ok(tasks) => {
+                let now = epoch_now();
                 for task in tasks {
-                    println!("{}\t{}\t{}", task.id, task.status, task.url);
+                    println!(
+                        "{}\t{}\t{}\t{}",
+                        task.id,
+                        task.status,
+                        format_age(task.updated_at, now),
+                        task.url
+                    );
                 }
             }
             Err(err) => eprintln!("error: {}", err),
         },
+        "info" => run_with_id(engine.as_ref(), &args, 2, |engine, id| {
+            print_info(&engine.get_task(id)?);
+            Ok(())
+        }),
+        "status" => {
+            if print_status(|| engine.stats().map_err(|err| err.to_string()), watch) {
+                print_usage_totals(engine.as_ref());
+            }
+        }
+        "reset-stats" => match engine.reset_stats() {
+            Ok(()) => println!("download totals reset"),
+            Err(err) => eprintln!("error: {}", err),
+        },
+        "export" => match args.get(2) {
+            Some(path) => export_tasks(engine.as_ref(), path),
+            None => print_usage(),
+        },
+        "import" => match args.get(2) {
+            Some(path) => import_tasks(engine.as_ref(), path),
+            None => print_usage(),
+        },
+        "verify" => {
+            if !run_verify(engine.as_ref(), &args) {
+                verify_failed = true;
+            }
+        }
         "start-next" => {
             if let Err(err) = engine.enqueue_queued() {
                 eprintln!("error: {}", err);
@@ -82,16 +324,273 @@ fn main() {
             }
             stop.store(true, Ordering::SeqCst);
             let _ = handle.join();
-        },
+        }
         "pause" => run_with_id(engine.as_ref(), &args, 2, |engine, id| engine.pause_task(id)),
         "resume" => run_with_id(engine.as_ref(), &args, 2, |engine, id| engine.resume_task(id)),
+        "retry" => run_with_id(engine.as_ref(), &args, 2, |engine, id| engine.retry_task(id)),
         "cancel" => run_with_id(engine.as_ref(), &args, 2, |engine, id| engine.cancel_task(id)),
+        "remove" => run_remove(engine.as_ref(), &args),
         _ => print_usage(),
     }
+
+    if let Err(err) = engine.shutdown() {
+        eprintln!("error: {}", err);
+    }
+    if verify_failed {
+        process::exit(1);
+    }
+}
+
+/// Reads a Netscape `cookies.txt` and keeps the cookies a browser would send to `url`.
+fn load_cookies(path: &str, url: &str) -> Result<HashMap<String, String>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let jar = parse_netscape_cookies(&text).map_err(|err| err.to_string())?;
+    let cookies = cookies_for_url(&jar, url, epoch_now()).map_err(|err| err.to_string())?;
+    if cookies.is_empty() {
+        eprintln!("warning: no cookies in {} apply to {}", path, url);
+    }
+    Ok(cookies)
 }
 
-fn build_engine() -> Result<DownloadEngine, idm_core::CoreError> {
-    let mut engine = DownloadEngine::new(EngineConfig::default());
+fn export_tasks(engine: &DownloadEngine, path: &str) {
+    let result = engine.list_tasks().and_then(|tasks| {
+        TaskExport::new(&tasks)
+            .to_json()
+            .map(|json| (tasks.len(), json))
+    });
+    match result {
+        Ok((count, json)) => match fs::write(path, json + "\n") {
+            Ok(()) => println!("exported {} tasks to {}", count, path),
+            Err(err) => eprintln!("error: {}: {}", path, err),
+        },
+        Err(err) => eprintln!("error: {}", err),
+    }
+}
+
+/// Adds every task in an export file as a new queued task.
+fn import_tasks(engine: &DownloadEngine, path: &str) {
+    let export = match fs::read_to_string(path) {
+        Ok(json) => TaskExport::from_json(&json),
+        Err(err) => {
+            eprintln!("error: {}: {}", path, err);
+            return;
+        }
+    };
+    let export = match export {
+        Ok(export) => export,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return;
+        }
+    };
+    let mut imported = 0;
+    for definition in export.tasks {
+        match definition
+            .into_task()
+            .and_then(|task| engine.add_prepared_task(task))
+        {
+            Ok(_) => imported += 1,
+            Err(err) => eprintln!("error: {}", err),
+        }
+    }
+    println!("imported {} tasks from {}", imported, path);
+}
+
+/// `remove <id>` deletes one task; `remove --status <s>[,<s>...] [--older-than <days>]`
+/// deletes every match, skipping running tasks.
+fn run_remove(engine: &DownloadEngine, args: &[String]) {
+    let mut args = args.to_vec();
+    let (statuses, older_than) = match (
+        take_flag(&mut args, "--status"),
+        take_flag(&mut args, "--older-than"),
+    ) {
+        (Ok(statuses), Ok(older_than)) => (statuses, older_than),
+        _ => {
+            print_usage();
+            return;
+        }
+    };
+    if statuses.is_none() && older_than.is_none() {
+        run_with_id(engine, &args, 2, |engine, id| engine.remove_task(id));
+        return;
+    }
+
+    let mut filter = TaskFilter::default();
+    for name in statuses.iter().flat_map(|value| value.split(',')) {
+        match TaskStatus::from_str(name.trim()) {
+            Some(status) => filter.statuses.push(status),
+            None => {
+                eprintln!("unknown status: {}", name);
+                return;
+            }
+        }
+    }
+    if let Some(days) = older_than {
+        match days.parse::<u64>() {
+            Ok(days) => filter.older_than_secs = Some(days.saturating_mul(86_400)),
+            Err(_) => {
+                eprintln!("invalid --older-than value: {}", days);
+                return;
+            }
+        }
+    }
+    match engine.remove_by(&filter) {
+        Ok(report) => {
+            for id in &report.skipped {
+                println!("skipped running task: {}", id);
+            }
+            println!("removed {} tasks", report.removed);
+        }
+        Err(err) => eprintln!("error: {}", err),
+    }
+}
+
+/// `--daemon <socket>`: sends the command to a running `idm-daemon --socket` instead of
+/// opening the database. Returns whether it succeeded.
+fn run_daemon_command(socket: &Path, args: &[String], watch: bool) -> bool {
+    let send = |request: IpcRequest| -> Result<IpcResponse, String> {
+        let response = send_request(socket, &request).map_err(|err| err.to_string())?;
+        if response.ok {
+            Ok(response)
+        } else {
+            Err(response
+                .error
+                .unwrap_or_else(|| "request failed".to_string()))
+        }
+    };
+    let with_id = |request: fn(TaskId) -> IpcRequest| -> Result<(), String> {
+        let id = args.get(2).ok_or_else(|| "missing task id".to_string())?;
+        let id = TaskId::parse_str(id).map_err(|_| "invalid task id".to_string())?;
+        send(request(id)).map(drop)
+    };
+
+    let outcome = match args.get(1).map(String::as_str) {
+        Some("status") => {
+            let fetch = || {
+                send(IpcRequest::Status)?
+                    .stats
+                    .ok_or_else(|| "daemon sent no stats".to_string())
+            };
+            return print_status(fetch, watch);
+        }
+        Some("list") => send(IpcRequest::List).map(|response| {
+            for task in response.tasks.unwrap_or_default() {
+                println!(
+                    "{}\t{}\t{}/{}\t{}",
+                    task.id,
+                    task.status,
+                    format_bytes(task.downloaded_bytes),
+                    format_bytes(task.total_bytes),
+                    task.url
+                );
+            }
+        }),
+        Some("add") => match args.get(2) {
+            Some(url) => send(IpcRequest::Add {
+                url: url.clone(),
+                dest_path: args.get(3).cloned().unwrap_or_default(),
+            })
+            .map(|response| {
+                if let Some(id) = response.id {
+                    println!("added task: {}", id);
+                }
+            }),
+            None => Err("missing url".to_string()),
+        },
+        Some("pause") => with_id(|id| IpcRequest::Pause { id }),
+        Some("resume") => with_id(|id| IpcRequest::Resume { id }),
+        Some("cancel") => with_id(|id| IpcRequest::Cancel { id }),
+        Some("remove") => with_id(|id| IpcRequest::Remove { id }),
+        Some(command) => Err(format!("{} is not available with --daemon", command)),
+        None => {
+            print_usage();
+            return false;
+        }
+    };
+    match outcome {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            false
+        }
+    }
+}
+
+/// `verify <path> <type>:<hex>` checks any file; `verify <id>` re-checks a task's
+/// file against its stored checksum. Returns whether the file matched.
+fn run_verify(engine: &DownloadEngine, args: &[String]) -> bool {
+    let (path, checksum) = match (args.get(2), args.get(3)) {
+        (Some(path), Some(spec)) => match ChecksumRequest::parse(spec) {
+            Some(checksum) => (path.clone(), checksum),
+            None => {
+                eprintln!("invalid checksum, expected <md5|sha1|sha256>:<hex>");
+                return false;
+            }
+        },
+        (Some(id), None) => {
+            let task = match TaskId::parse_str(id).map(|id| engine.get_task(&id)) {
+                Ok(Ok(task)) => task,
+                Ok(Err(err)) => {
+                    eprintln!("error: {}", err);
+                    return false;
+                }
+                Err(_) => {
+                    eprintln!("invalid task id");
+                    return false;
+                }
+            };
+            let Some(checksum) = task.checksum else {
+                eprintln!("task {} has no stored checksum", task.id);
+                return false;
+            };
+            (task.dest_path, checksum)
+        }
+        _ => {
+            print_usage();
+            return false;
+        }
+    };
+
+    if !Path::new(&path).is_file() {
+        eprintln!("file not found: {}", path);
+        return false;
+    }
+    let ok = match engine.verify_file(&path, &checksum) {
+        Ok(ok) => ok,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return false;
+        }
+    };
+    println!(
+        "{} {} {}",
+        if ok { "OK" } else { "FAILED" },
+        checksum.checksum_type.as_str(),
+        path
+    );
+    ok
+}
+
+/// Removes `name <value>` from `args`; `Err` if the flag is present without a value.
+fn take_flag(args: &mut Vec<String>, name: &str) -> Result<Option<String>, ()> {
+    let Some(pos) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    let value = args.get(pos + 1).cloned().ok_or(())?;
+    args.drain(pos..pos + 2);
+    Ok(Some(value))
+}
+
+fn take_switch(args: &mut Vec<String>, name: &str) -> bool {
+    let Some(pos) = args.iter().position(|arg| arg == name) else {
+        return false;
+    };
+    args.remove(pos);
+    true
+}
+
+fn build_engine(config: EngineConfig) -> Result<DownloadEngine, idm_core::CoreError> {
+    let mut engine = DownloadEngine::new(config);
     if let Ok(path) = env::var("IDM_DB") {
         let storage = SqliteStorage::new(path)?;
         engine = engine.with_storage(Box::new(storage));
@@ -126,21 +625,149 @@ where
 
 fn print_usage() {
     eprintln!(
-        "Usage: idm-cli <command> [args]\n\
+        "Usage: idm-cli [--limit <rate>] [--bearer <token>] [--user-agent <ua>] [--output-template <tpl>] [--on-existing <policy>] [--decompress] [--daemon <socket>] <command> [args]\n\
+Options:\n\
+  --limit <rate>       Global speed limit, e.g. 500k, 1m, 2g (0 = unlimited)\n\
+  --bearer <token>     Send 'Authorization: Bearer <token>' for added tasks\n\
+  --user-agent <ua>    User-Agent for added tasks instead of the engine default\n\
+  --output-template <tpl>\n\
+                       Name files saved into a directory, e.g. '%(host)s-%(name)s.%(ext)s';\n\
+                       placeholders: host, name, ext, date, id\n\
+  --on-existing <policy>\n\
+                       When the destination file exists: overwrite (default), skip if it\n\
+                       already has the expected size, or rename to 'name (1).ext'\n\
+  --decompress         Decode finished .gz/.bz2/.xz files (not .tar.gz/.zip archives)\n\
+                       into the name without the extension and delete the original\n\
+  --flat               Save every file of a Pixeldrain list straight into its destination\n\
+                       instead of recreating the source's subdirectories\n\
+  --daemon <socket>    Send status, list, add <url> [dest], pause, resume, cancel and\n\
+                       remove to an 'idm-daemon --socket <socket>' instead of opening\n\
+                       the database\n\
 Commands:\n\
-  add <url> [dest]     Add a task (dest optional)\n\
-  list                 List tasks\n\
+  add <url> [dest] [--extract] [--paused] [--no-resolve] [--skip-head] [--bytes <size>] [--after <id>] [--post-data <body>] [--cookies-file <cookies.txt>]\n\
+                       Add a task (dest optional); --extract unpacks .zip/.tar.gz when done;\n\
+                       --paused keeps it out of run/start-next until 'resume <id>';\n\
+                       --no-resolve downloads the URL as given, skipping hosting-page\n\
+                       and provider handling;\n\
+                       --skip-head probes with a ranged GET instead of HEAD;\n\
+                       --bytes fetches only the first <size> bytes, e.g. 10m;\n\
+                       --after waits for task <id> to complete (and fails if it fails);\n\
+                       --post-data fetches the file with a form-encoded POST of <body>;\n\
+                       --cookies-file sends the matching cookies from a browser export\n\
+  list                 List tasks with the time since each was last updated\n\
+  info <id>            Show a task's destination, progress and timestamps\n\
+  export <file>        Write all task definitions to a portable JSON file\n\
+  import <file>        Add the tasks from an export file as new queued tasks\n\
+  verify <path> <type>:<hex>\n\
+                       Check a file against an md5/sha1/sha256 checksum\n\
+  verify <id>          Re-check a task's file against its stored checksum\n\
+  status [--watch]     Task counts and speed, then bytes downloaded today, this month\n\
+                       and in total; --watch refreshes the counts every second\n\
+  reset-stats          Zero the download totals shown by status\n\
   start-next           Start next queued task and wait\n\
   run                  Run queued tasks until complete\n\
   pause <id>           Pause a task\n\
   resume <id>          Resume a task\n\
+  retry <id>           Re-queue a failed task, rechecking its partial file\n\
   cancel <id>          Cancel a task\n\
+  remove <id>          Delete a task that is not running\n\
+  remove --status <s>[,<s>...] [--older-than <days>]\n\
+                       Delete every matching task, e.g. --status canceled,failed;\n\
+                       running tasks are skipped\n\
 Environment:\n\
   IDM_DB=/path/to/db   Persist tasks in SQLite\n\
   IDM_DOWNLOAD_DIR     Default download dir when dest missing"
     );
 }
 
+/// Prints counts by status and the aggregate speed, sampled over one second.
+/// With `watch`, keeps redrawing the same line until interrupted.
+/// Returns whether a one-shot summary was printed.
+fn print_status(fetch: impl Fn() -> Result<EngineStats, String>, watch: bool) -> bool {
+    let mut previous = match fetch() {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return false;
+        }
+    };
+    let mut sampled_at = Instant::now();
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let stats = match fetch() {
+            Ok(stats) => stats,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                return false;
+            }
+        };
+        let now = Instant::now();
+        let elapsed = now.duration_since(sampled_at).as_secs_f64();
+        let delta = stats
+            .downloaded_bytes
+            .saturating_sub(previous.downloaded_bytes);
+        let speed = if elapsed > 0.0 {
+            (delta as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let line = format!(
+            "{} active, {} resolving, {} queued, {} paused, {} done, {} failed, {}/s",
+            stats.active,
+            stats.resolving,
+            stats.queued,
+            stats.paused,
+            stats.completed,
+            stats.failed,
+            format_bytes(speed),
+        );
+        if !watch {
+            println!("{}", line);
+            return true;
+        }
+        // Clear the line and return to its start so each refresh overwrites the last.
+        print!("\r\x1b[2K{}", line);
+        let _ = io::stdout().flush();
+        previous = stats;
+        sampled_at = now;
+    }
+}
+
+/// Completed-download bytes for today, this month (UTC) and since the last reset.
+fn print_usage_totals(engine: &DownloadEngine) {
+    let days = match engine.daily_bytes() {
+        Ok(days) => days,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return;
+        }
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let today = now / 86_400;
+    // `YYYY-MM` prefix of the RFC 3339 date.
+    let month = |day: u64| format_rfc3339(day * 86_400)[..7].to_string();
+    let this_month = month(today);
+    let (mut today_bytes, mut month_bytes, mut total_bytes) = (0, 0, 0);
+    for (day, bytes) in days {
+        total_bytes += bytes;
+        if month(day) == this_month {
+            month_bytes += bytes;
+        }
+        if day == today {
+            today_bytes += bytes;
+        }
+    }
+    println!(
+        "downloaded: {} today, {} this month, {} total",
+        format_bytes(today_bytes),
+        format_bytes(month_bytes),
+        format_bytes(total_bytes),
+    );
+}
+
 fn spawn_progress(engine: Arc<DownloadEngine>) -> (thread::JoinHandle<()>, Arc<AtomicBool>) {
     let stop = Arc::new(AtomicBool::new(false));
     let stop_clone = Arc::clone(&stop);
@@ -150,11 +777,14 @@ fn spawn_progress(engine: Arc<DownloadEngine>) -> (thread::JoinHandle<()>, Arc<A
             if stop_clone.load(Ordering::SeqCst) {
                 break;
             }
-            if let Ok(tasks) = engine.list_tasks() {
+            if let Ok(tasks) = engine.list_task_summaries() {
                 let mut lines = Vec::new();
                 let now = Instant::now();
                 for task in tasks {
-                    if task.status != TaskStatus::Active && task.status != TaskStatus::Queued {
+                    if !matches!(
+                        task.status,
+                        TaskStatus::Queued | TaskStatus::Resolving | TaskStatus::Active
+                    ) {
                         continue;
                     }
                     let total = task.total_bytes;
@@ -180,12 +810,10 @@ fn spawn_progress(engine: Arc<DownloadEngine>) -> (thread::JoinHandle<()>, Arc<A
                         .file_name()
                         .and_then(|value| value.to_str())
                         .unwrap_or("download");
-                    let remaining = total.saturating_sub(downloaded);
-                    let eta = if total > 0 && speed_bps > 0 {
-                        format_duration(remaining / speed_bps)
-                    } else {
-                        "--:--".to_string()
-                    };
+                    let eta = engine
+                        .task_eta(&task.id)
+                        .map(|eta| format_duration(eta.as_secs()))
+                        .unwrap_or_else(|| "--:--".to_string());
                     let line = format!(
                         "[{}] {} {} {}/{} ({}/s) eta {}",
                         task.status,
@@ -210,6 +838,45 @@ fn spawn_progress(engine: Arc<DownloadEngine>) -> (thread::JoinHandle<()>, Arc<A
     (handle, stop)
 }
 
+fn print_info(task: &Task) {
+    let now = epoch_now();
+    println!("id:       {}", task.id);
+    println!("url:      {}", task.url);
+    if let Some(url) = &task.resolved_url {
+        println!("served:   {}", url);
+    }
+    println!("dest:     {}", task.dest_path);
+    println!("status:   {}", task.status);
+    println!(
+        "progress: {} / {}",
+        format_bytes(task.downloaded_bytes),
+        format_bytes(task.total_bytes)
+    );
+    if task.partial {
+        println!("partial:  first {} only", format_bytes(task.total_bytes));
+    }
+    println!(
+        "created:  {} ({})",
+        format_rfc3339(task.created_at),
+        format_age(task.created_at, now)
+    );
+    println!(
+        "updated:  {} ({})",
+        format_rfc3339(task.updated_at),
+        format_age(task.updated_at, now)
+    );
+    if let Some(error) = &task.error {
+        println!("error:    {}", error);
+    }
+}
+
+fn epoch_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 fn format_bytes(bytes: u64) -> String {
     const KB: f64 = 1024.0;
     const MB: f64 = KB * 1024.0;
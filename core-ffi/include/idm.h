@@ -0,0 +1,211 @@
+/* Generated by cbindgen from core-ffi/src/lib.rs. Do not edit by hand. */
+
+#ifndef IDM_H
+#define IDM_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * ABI revision of this library. Bump whenever an exported signature or struct layout changes.
+ */
+#define IDM_ABI_VERSION 1
+
+typedef struct ConfigHandle ConfigHandle;
+
+typedef struct EngineHandle EngineHandle;
+
+/**
+ * Canned HTTP responses for offline tests of the engine; see `idm_engine_new_with_mock_net`.
+ */
+typedef struct MockNetHandle MockNetHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Message for the most recent failed call on this thread that records one (the
+ * `_json` getters whose docs point here), or null. Free with `idm_string_free`.
+ */
+char *idm_last_error_message(void);
+
+uint32_t idm_abi_version(void);
+
+struct EngineHandle *idm_engine_new(void);
+
+struct EngineHandle *idm_engine_new_with_db(const char *path);
+
+struct EngineHandle *idm_engine_new_with_config(const struct ConfigHandle *config,
+                                                const char *db_path);
+
+/**
+ * Like `idm_engine_new_with_config`, but all network traffic is served from `net`.
+ * Responses registered on `net` later are still picked up; `net` may be freed after this call.
+ */
+struct EngineHandle *idm_engine_new_with_mock_net(const struct ConfigHandle *config,
+                                                  const char *db_path,
+                                                  const struct MockNetHandle *net);
+
+void idm_engine_free(struct EngineHandle *ptr);
+
+struct MockNetHandle *idm_mock_net_new(void);
+
+/**
+ * Serves `len` bytes at `data` for requests to exactly `url`. `content_type` may be null.
+ */
+int32_t idm_mock_net_add(struct MockNetHandle *ptr,
+                         const char *url,
+                         uint16_t status,
+                         const char *content_type,
+                         const uint8_t *data,
+                         size_t len);
+
+void idm_mock_net_free(struct MockNetHandle *ptr);
+
+struct ConfigHandle *idm_config_new(void);
+
+void idm_config_free(struct ConfigHandle *ptr);
+
+int32_t idm_config_set_max_concurrent(struct ConfigHandle *ptr, uint32_t tasks);
+
+int32_t idm_config_set_segments(struct ConfigHandle *ptr, uint32_t segments);
+
+/**
+ * `0` removes the per-host cap.
+ */
+int32_t idm_config_set_max_connections_per_host(struct ConfigHandle *ptr, uint32_t connections);
+
+int32_t idm_config_set_min_segment_size(struct ConfigHandle *ptr, uint64_t bytes);
+
+int32_t idm_config_set_global_limit(struct ConfigHandle *ptr, uint64_t bytes_per_sec);
+
+int32_t idm_config_set_per_task_limit(struct ConfigHandle *ptr, uint64_t bytes_per_sec);
+
+int32_t idm_config_set_user_agent(struct ConfigHandle *ptr, const char *user_agent);
+
+int32_t idm_config_set_retry_count(struct ConfigHandle *ptr, uint32_t count);
+
+int32_t idm_config_set_retry_backoff_secs(struct ConfigHandle *ptr, uint64_t secs);
+
+int32_t idm_config_set_task_retry_count(struct ConfigHandle *ptr, uint32_t count);
+
+int32_t idm_config_set_task_retry_backoff_secs(struct ConfigHandle *ptr, uint64_t secs);
+
+/**
+ * Cap on unfinished tasks; adds fail once it is reached. `0` means no cap.
+ */
+int32_t idm_config_set_max_tasks(struct ConfigHandle *ptr, uint64_t tasks);
+
+/**
+ * Minimum milliseconds between progress events for one task; `0` sends every update.
+ */
+int32_t idm_config_set_progress_event_interval_ms(struct ConfigHandle *ptr, uint64_t millis);
+
+/**
+ * `0` disables read-stall detection.
+ */
+int32_t idm_config_set_stall_timeout_secs(struct ConfigHandle *ptr, uint64_t secs);
+
+/**
+ * `mode`: 0 = always (`set_len`), 1 = never, 2 = sparse (`fallocate` where supported).
+ */
+int32_t idm_config_set_preallocate(struct ConfigHandle *ptr, uint32_t mode);
+
+/**
+ * `policy`: 0 = overwrite, 1 = skip if the file already has the expected size, 2 = rename.
+ */
+int32_t idm_config_set_on_existing(struct ConfigHandle *ptr, uint32_t policy);
+
+/**
+ * Send the page a direct link was scraped from as its `Referer` (unless the task sets
+ * one). Enabled by default.
+ */
+int32_t idm_config_set_auto_referer(struct ConfigHandle *ptr, bool enabled);
+
+/**
+ * When to send `Accept-Encoding: identity`: 0 = never, 1 = ranged requests, 2 = always.
+ */
+int32_t idm_config_set_identity_encoding(struct ConfigHandle *ptr, uint32_t mode);
+
+/**
+ * Filename template for directory destinations, e.g. `%(host)s-%(name)s.%(ext)s`.
+ * Returns -1 if the template uses an unknown placeholder.
+ */
+int32_t idm_config_set_output_template(struct ConfigHandle *ptr, const char *template_);
+
+char *idm_engine_add_task(struct EngineHandle *ptr, const char *url, const char *dest_path);
+
+char *idm_engine_start_next(struct EngineHandle *ptr);
+
+int32_t idm_engine_enqueue_queued(struct EngineHandle *ptr);
+
+char *idm_engine_list_tasks_json(struct EngineHandle *ptr);
+
+/**
+ * JSON array of tasks with `updated_at > since_epoch` (seconds). Timestamps have
+ * one-second resolution, so pass the previous poll time minus one to avoid missing
+ * updates made in that same second. Removed tasks never appear here.
+ */
+char *idm_engine_list_tasks_delta_json(struct EngineHandle *ptr, uint64_t since_epoch);
+
+char *idm_engine_get_task_json(struct EngineHandle *ptr, const char *id);
+
+/**
+ * JSON array of `[epoch_ms, downloaded_bytes]` pairs for the task's last minute of
+ * progress, oldest first, sampled at most once per second while data arrives. Returns
+ * null on failure; see `idm_last_error_message`.
+ */
+char *idm_engine_speed_history_json(struct EngineHandle *ptr, const char *id);
+
+/**
+ * Seconds left for an active download at its smoothed speed, or -1 when there is no
+ * estimate (unknown size, not downloading, or stalled).
+ */
+int64_t idm_engine_task_eta_secs(struct EngineHandle *ptr, const char *id);
+
+/**
+ * Resolves `url` without adding a task: `{final_url, filename, total_bytes,
+ * accept_ranges, content_type}` (`total_bytes` is null when unknown). Returns null on
+ * failure; see `idm_last_error_message`. Blocks on the network, so call it off the UI
+ * thread.
+ */
+char *idm_engine_probe_json(struct EngineHandle *ptr, const char *url);
+
+/**
+ * JSON array of the tasks stored for `url` (as their URL or a mirror), unfinished ones
+ * first; `[]` when there are none. Returns null on failure; see `idm_last_error_message`.
+ */
+char *idm_engine_find_by_url_json(struct EngineHandle *ptr, const char *url);
+
+/**
+ * Calls `callback` on a background thread with each engine event as JSON, e.g.
+ * `{"event":"task_progress","id":"…","downloaded_bytes":1024,"total_bytes":4096}`, and
+ * `user_data`; free the string with `idm_string_free`. Replaces any earlier callback;
+ * null just removes it. Progress events are coalesced per task like the engine's event
+ * subscription: at most one per `idm_config_set_progress_event_interval_ms`, so
+ * intermediate byte counts may be skipped, while state changes are passed on
+ * immediately, right after that task's newest held-back progress.
+ */
+int32_t idm_engine_set_event_callback(struct EngineHandle *ptr,
+                                      void (*callback)(char *event_json, void *user_data),
+                                      void *user_data);
+
+int32_t idm_engine_pause_task(struct EngineHandle *ptr, const char *id);
+
+int32_t idm_engine_resume_task(struct EngineHandle *ptr, const char *id);
+
+int32_t idm_engine_cancel_task(struct EngineHandle *ptr, const char *id);
+
+int32_t idm_engine_remove_task(struct EngineHandle *ptr, const char *id);
+
+void idm_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* IDM_H */
@@ -1,12 +1,22 @@
+use std::cell::RefCell;
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 use std::ptr;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use idm_core::config::EngineConfig;
+use idm_core::config::{
+    EngineConfig, EngineConfigBuilder, IdentityEncoding, OnExisting, Preallocate,
+};
+use idm_core::net::StaticNetClient;
 use idm_core::storage::SqliteStorage;
+use idm_core::template::OutputTemplate;
 use idm_core::{DownloadEngine, TaskId};
 
+/// ABI revision of this library. Bump whenever an exported signature or struct layout changes.
+pub const IDM_ABI_VERSION: u32 = 1;
+
 fn cstr_to_string(ptr: *const c_char) -> Option<String> {
     if ptr.is_null() {
         return None;
@@ -14,17 +24,56 @@ fn cstr_to_string(ptr: *const c_char) -> Option<String> {
     Some(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string())
 }
 
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl Into<String>) {
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = Some(message.into()));
+}
+
+/// Message for the most recent failed call on this thread that records one (the
+/// `_json` getters whose docs point here), or null. Free with `idm_string_free`.
+#[no_mangle]
+pub extern "C" fn idm_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|slot| {
+        slot.borrow()
+            .as_ref()
+            .and_then(|message| CString::new(message.as_str()).ok())
+            .map(|value| value.into_raw())
+            .unwrap_or(ptr::null_mut())
+    })
+}
+
 pub struct EngineHandle {
     engine: Mutex<DownloadEngine>,
+    /// Stops the thread feeding the current event callback.
+    callback_stop: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl EngineHandle {
+    fn new(engine: DownloadEngine) -> Self {
+        Self {
+            engine: Mutex::new(engine),
+            callback_stop: Mutex::new(None),
+        }
+    }
+}
+
+/// The registering caller vouches that `user_data` may be used from another thread.
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+#[no_mangle]
+pub extern "C" fn idm_abi_version() -> u32 {
+    IDM_ABI_VERSION
 }
 
 #[no_mangle]
 pub extern "C" fn idm_engine_new() -> *mut EngineHandle {
     let engine = DownloadEngine::new(EngineConfig::default());
-    let handle = EngineHandle {
-        engine: Mutex::new(engine),
-    };
-    Box::into_raw(Box::new(handle))
+    Box::into_raw(Box::new(EngineHandle::new(engine)))
 }
 
 #[no_mangle]
@@ -38,14 +87,128 @@ pub extern "C" fn idm_engine_new_with_db(path: *const c_char) -> *mut EngineHand
         Err(_) => return ptr::null_mut(),
     };
     engine = engine.with_storage(Box::new(storage));
-    let handle = EngineHandle {
-        engine: Mutex::new(engine),
-    };
-    Box::into_raw(Box::new(handle))
+    Box::into_raw(Box::new(EngineHandle::new(engine)))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_engine_new_with_config(
+    config: *const ConfigHandle,
+    db_path: *const c_char,
+) -> *mut EngineHandle {
+    match build_engine(config, db_path) {
+        Some(engine) => Box::into_raw(Box::new(EngineHandle::new(engine))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Like `idm_engine_new_with_config`, but all network traffic is served from `net`.
+/// Responses registered on `net` later are still picked up; `net` may be freed after this call.
+#[no_mangle]
+pub extern "C" fn idm_engine_new_with_mock_net(
+    config: *const ConfigHandle,
+    db_path: *const c_char,
+    net: *const MockNetHandle,
+) -> *mut EngineHandle {
+    if net.is_null() {
+        return ptr::null_mut();
+    }
+    let net = unsafe { &*net }.net.clone();
+    match build_engine(config, db_path) {
+        Some(engine) => Box::into_raw(Box::new(EngineHandle::new(
+            engine.with_net_client(Box::new(net)),
+        ))),
+        None => ptr::null_mut(),
+    }
+}
+
+fn build_engine(config: *const ConfigHandle, db_path: *const c_char) -> Option<DownloadEngine> {
+    if config.is_null() {
+        return None;
+    }
+    let builder = unsafe { &*config }.builder.clone();
+    let config = builder.build().ok()?;
+    let mut engine = DownloadEngine::new(config);
+    if let Some(path) = cstr_to_string(db_path) {
+        let storage = SqliteStorage::new(path).ok()?;
+        engine = engine.with_storage(Box::new(storage));
+    }
+    Some(engine)
 }
 
 #[no_mangle]
 pub extern "C" fn idm_engine_free(ptr: *mut EngineHandle) {
+    if ptr.is_null() {
+        return;
+    }
+    let handle = unsafe { Box::from_raw(ptr) };
+    if let Ok(Some(stop)) = handle.callback_stop.lock().map(|mut stop| stop.take()) {
+        stop.store(true, Ordering::SeqCst);
+    }
+    drop(handle);
+}
+
+/// Canned HTTP responses for offline tests of the engine; see `idm_engine_new_with_mock_net`.
+pub struct MockNetHandle {
+    net: StaticNetClient,
+}
+
+#[no_mangle]
+pub extern "C" fn idm_mock_net_new() -> *mut MockNetHandle {
+    Box::into_raw(Box::new(MockNetHandle {
+        net: StaticNetClient::new(),
+    }))
+}
+
+/// Serves `len` bytes at `data` for requests to exactly `url`. `content_type` may be null.
+#[no_mangle]
+pub extern "C" fn idm_mock_net_add(
+    ptr: *mut MockNetHandle,
+    url: *const c_char,
+    status: u16,
+    content_type: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    if ptr.is_null() || (data.is_null() && len > 0) {
+        return -1;
+    }
+    let Some(url) = cstr_to_string(url) else {
+        return -1;
+    };
+    let body = if len == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }.to_vec()
+    };
+    unsafe { &*ptr }
+        .net
+        .insert(url, status, cstr_to_string(content_type), body);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn idm_mock_net_free(ptr: *mut MockNetHandle) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+pub struct ConfigHandle {
+    builder: EngineConfigBuilder,
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_new() -> *mut ConfigHandle {
+    Box::into_raw(Box::new(ConfigHandle {
+        builder: EngineConfig::builder(),
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_free(ptr: *mut ConfigHandle) {
     if ptr.is_null() {
         return;
     }
@@ -54,6 +217,165 @@ pub extern "C" fn idm_engine_free(ptr: *mut EngineHandle) {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn idm_config_set_max_concurrent(ptr: *mut ConfigHandle, tasks: u32) -> i32 {
+    update_config(ptr, |builder| builder.max_concurrent(tasks as usize))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_segments(ptr: *mut ConfigHandle, segments: u32) -> i32 {
+    update_config(ptr, |builder| builder.segments(segments))
+}
+
+/// `0` removes the per-host cap.
+#[no_mangle]
+pub extern "C" fn idm_config_set_max_connections_per_host(
+    ptr: *mut ConfigHandle,
+    connections: u32,
+) -> i32 {
+    update_config(ptr, |builder| {
+        builder.max_connections_per_host(connections as usize)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_min_segment_size(ptr: *mut ConfigHandle, bytes: u64) -> i32 {
+    update_config(ptr, |builder| builder.min_segment_size(bytes))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_global_limit(ptr: *mut ConfigHandle, bytes_per_sec: u64) -> i32 {
+    update_config(ptr, |builder| builder.global_limit(bytes_per_sec))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_per_task_limit(ptr: *mut ConfigHandle, bytes_per_sec: u64) -> i32 {
+    update_config(ptr, |builder| builder.per_task_limit(bytes_per_sec))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_user_agent(
+    ptr: *mut ConfigHandle,
+    user_agent: *const c_char,
+) -> i32 {
+    let Some(user_agent) = cstr_to_string(user_agent) else {
+        return -1;
+    };
+    update_config(ptr, |builder| builder.user_agent(user_agent))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_retry_count(ptr: *mut ConfigHandle, count: u32) -> i32 {
+    update_config(ptr, |builder| builder.retry_count(count))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_retry_backoff_secs(ptr: *mut ConfigHandle, secs: u64) -> i32 {
+    update_config(ptr, |builder| builder.retry_backoff_secs(secs))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_task_retry_count(ptr: *mut ConfigHandle, count: u32) -> i32 {
+    update_config(ptr, |builder| builder.task_retry_count(count))
+}
+
+#[no_mangle]
+pub extern "C" fn idm_config_set_task_retry_backoff_secs(ptr: *mut ConfigHandle, secs: u64) -> i32 {
+    update_config(ptr, |builder| builder.task_retry_backoff_secs(secs))
+}
+
+/// Cap on unfinished tasks; adds fail once it is reached. `0` means no cap.
+#[no_mangle]
+pub extern "C" fn idm_config_set_max_tasks(ptr: *mut ConfigHandle, tasks: u64) -> i32 {
+    update_config(ptr, |builder| builder.max_tasks(tasks as usize))
+}
+
+/// Minimum milliseconds between progress events for one task; `0` sends every update.
+#[no_mangle]
+pub extern "C" fn idm_config_set_progress_event_interval_ms(
+    ptr: *mut ConfigHandle,
+    millis: u64,
+) -> i32 {
+    update_config(ptr, |builder| builder.progress_event_interval_ms(millis))
+}
+
+/// `0` disables read-stall detection.
+#[no_mangle]
+pub extern "C" fn idm_config_set_stall_timeout_secs(ptr: *mut ConfigHandle, secs: u64) -> i32 {
+    update_config(ptr, |builder| builder.stall_timeout_secs(secs))
+}
+
+/// `mode`: 0 = always (`set_len`), 1 = never, 2 = sparse (`fallocate` where supported).
+#[no_mangle]
+pub extern "C" fn idm_config_set_preallocate(ptr: *mut ConfigHandle, mode: u32) -> i32 {
+    let mode = match mode {
+        0 => Preallocate::Always,
+        1 => Preallocate::Never,
+        2 => Preallocate::Sparse,
+        _ => return -1,
+    };
+    update_config(ptr, |builder| builder.preallocate(mode))
+}
+
+/// `policy`: 0 = overwrite, 1 = skip if the file already has the expected size, 2 = rename.
+#[no_mangle]
+pub extern "C" fn idm_config_set_on_existing(ptr: *mut ConfigHandle, policy: u32) -> i32 {
+    let policy = match policy {
+        0 => OnExisting::Overwrite,
+        1 => OnExisting::Skip,
+        2 => OnExisting::Rename,
+        _ => return -1,
+    };
+    update_config(ptr, |builder| builder.on_existing(policy))
+}
+
+/// Send the page a direct link was scraped from as its `Referer` (unless the task sets
+/// one). Enabled by default.
+#[no_mangle]
+pub extern "C" fn idm_config_set_auto_referer(ptr: *mut ConfigHandle, enabled: bool) -> i32 {
+    update_config(ptr, |builder| builder.auto_referer(enabled))
+}
+
+/// When to send `Accept-Encoding: identity`: 0 = never, 1 = ranged requests, 2 = always.
+#[no_mangle]
+pub extern "C" fn idm_config_set_identity_encoding(ptr: *mut ConfigHandle, mode: u32) -> i32 {
+    let mode = match mode {
+        0 => IdentityEncoding::Never,
+        1 => IdentityEncoding::Ranged,
+        2 => IdentityEncoding::Always,
+        _ => return -1,
+    };
+    update_config(ptr, |builder| builder.identity_encoding(mode))
+}
+
+/// Filename template for directory destinations, e.g. `%(host)s-%(name)s.%(ext)s`.
+/// Returns -1 if the template uses an unknown placeholder.
+#[no_mangle]
+pub extern "C" fn idm_config_set_output_template(
+    ptr: *mut ConfigHandle,
+    template: *const c_char,
+) -> i32 {
+    let Some(template) = cstr_to_string(template) else {
+        return -1;
+    };
+    let Ok(template) = OutputTemplate::parse(&template) else {
+        return -1;
+    };
+    update_config(ptr, |builder| builder.output_template(template))
+}
+
+fn update_config<F>(ptr: *mut ConfigHandle, f: F) -> i32
+where
+    F: FnOnce(EngineConfigBuilder) -> EngineConfigBuilder,
+{
+    if ptr.is_null() {
+        return -1;
+    }
+    let handle = unsafe { &mut *ptr };
+    handle.builder = f(handle.builder.clone());
+    0
+}
+
 #[no_mangle]
 pub extern "C" fn idm_engine_add_task(
     ptr: *mut EngineHandle,
@@ -138,6 +460,32 @@ pub extern "C" fn idm_engine_list_tasks_json(ptr: *mut EngineHandle) -> *mut c_c
     }
 }
 
+/// JSON array of tasks with `updated_at > since_epoch` (seconds). Timestamps have
+/// one-second resolution, so pass the previous poll time minus one to avoid missing
+/// updates made in that same second. Removed tasks never appear here.
+#[no_mangle]
+pub extern "C" fn idm_engine_list_tasks_delta_json(
+    ptr: *mut EngineHandle,
+    since_epoch: u64,
+) -> *mut c_char {
+    if ptr.is_null() {
+        return ptr::null_mut();
+    }
+    let handle = unsafe { &*ptr };
+    let engine = match handle.engine.lock() {
+        Ok(guard) => guard,
+        Err(_) => return ptr::null_mut(),
+    };
+    match engine.list_tasks_updated_since(since_epoch) {
+        Ok(tasks) => serde_json::to_string(&tasks)
+            .ok()
+            .and_then(|value| CString::new(value).ok())
+            .map(|value| value.into_raw())
+            .unwrap_or(ptr::null_mut()),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn idm_engine_get_task_json(
     ptr: *mut EngineHandle,
@@ -168,6 +516,206 @@ pub extern "C" fn idm_engine_get_task_json(
     }
 }
 
+/// JSON array of `[epoch_ms, downloaded_bytes]` pairs for the task's last minute of
+/// progress, oldest first, sampled at most once per second while data arrives. Returns
+/// null on failure; see `idm_last_error_message`.
+#[no_mangle]
+pub extern "C" fn idm_engine_speed_history_json(
+    ptr: *mut EngineHandle,
+    id: *const c_char,
+) -> *mut c_char {
+    if ptr.is_null() {
+        set_last_error("engine is null");
+        return ptr::null_mut();
+    }
+    let Some(id) = cstr_to_string(id) else {
+        set_last_error("id is null");
+        return ptr::null_mut();
+    };
+    let task_id = match TaskId::parse_str(&id) {
+        Ok(value) => value,
+        Err(err) => {
+            set_last_error(format!("invalid task id: {}", err));
+            return ptr::null_mut();
+        }
+    };
+    let handle = unsafe { &*ptr };
+    let engine = match handle.engine.lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            set_last_error("engine lock poisoned");
+            return ptr::null_mut();
+        }
+    };
+    let json = serde_json::to_string(&engine.speed_history(&task_id))
+        .map_err(|err| idm_core::CoreError::Serialization(err.to_string()))
+        .and_then(|value| {
+            CString::new(value).map_err(|err| idm_core::CoreError::Serialization(err.to_string()))
+        });
+    match json {
+        Ok(value) => value.into_raw(),
+        Err(err) => {
+            set_last_error(err.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Seconds left for an active download at its smoothed speed, or -1 when there is no
+/// estimate (unknown size, not downloading, or stalled).
+#[no_mangle]
+pub extern "C" fn idm_engine_task_eta_secs(ptr: *mut EngineHandle, id: *const c_char) -> i64 {
+    if ptr.is_null() {
+        return -1;
+    }
+    let Some(task_id) = cstr_to_string(id).and_then(|id| TaskId::parse_str(&id).ok()) else {
+        return -1;
+    };
+    let handle = unsafe { &*ptr };
+    let engine = match handle.engine.lock() {
+        Ok(guard) => guard,
+        Err(_) => return -1,
+    };
+    engine
+        .task_eta(&task_id)
+        .map(|eta| eta.as_secs() as i64)
+        .unwrap_or(-1)
+}
+
+/// Resolves `url` without adding a task: `{final_url, filename, total_bytes,
+/// accept_ranges, content_type}` (`total_bytes` is null when unknown). Returns null on
+/// failure; see `idm_last_error_message`. Blocks on the network, so call it off the UI
+/// thread.
+#[no_mangle]
+pub extern "C" fn idm_engine_probe_json(ptr: *mut EngineHandle, url: *const c_char) -> *mut c_char {
+    if ptr.is_null() {
+        set_last_error("engine is null");
+        return ptr::null_mut();
+    }
+    let Some(url) = cstr_to_string(url) else {
+        set_last_error("url is null");
+        return ptr::null_mut();
+    };
+    let handle = unsafe { &*ptr };
+    // Released before the network call, so other calls aren't blocked behind the probe.
+    let prober = match handle.engine.lock() {
+        Ok(engine) => engine.prober(),
+        Err(_) => {
+            set_last_error("engine lock poisoned");
+            return ptr::null_mut();
+        }
+    };
+    let json = prober
+        .probe(&url)
+        .and_then(|probe| {
+            serde_json::to_string(&probe)
+                .map_err(|err| idm_core::CoreError::Serialization(err.to_string()))
+        })
+        .and_then(|value| {
+            CString::new(value).map_err(|err| idm_core::CoreError::Serialization(err.to_string()))
+        });
+    match json {
+        Ok(value) => value.into_raw(),
+        Err(err) => {
+            set_last_error(err.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// JSON array of the tasks stored for `url` (as their URL or a mirror), unfinished ones
+/// first; `[]` when there are none. Returns null on failure; see `idm_last_error_message`.
+#[no_mangle]
+pub extern "C" fn idm_engine_find_by_url_json(
+    ptr: *mut EngineHandle,
+    url: *const c_char,
+) -> *mut c_char {
+    if ptr.is_null() {
+        set_last_error("engine is null");
+        return ptr::null_mut();
+    }
+    let Some(url) = cstr_to_string(url) else {
+        set_last_error("url is null");
+        return ptr::null_mut();
+    };
+    let handle = unsafe { &*ptr };
+    let engine = match handle.engine.lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            set_last_error("engine lock poisoned");
+            return ptr::null_mut();
+        }
+    };
+    let json = engine
+        .find_by_url(&url)
+        .and_then(|tasks| {
+            serde_json::to_string(&tasks)
+                .map_err(|err| idm_core::CoreError::Serialization(err.to_string()))
+        })
+        .and_then(|value| {
+            CString::new(value).map_err(|err| idm_core::CoreError::Serialization(err.to_string()))
+        });
+    match json {
+        Ok(value) => value.into_raw(),
+        Err(err) => {
+            set_last_error(err.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Calls `callback` on a background thread with each engine event as JSON, e.g.
+/// `{"event":"task_progress","id":"…","downloaded_bytes":1024,"total_bytes":4096}`, and
+/// `user_data`; free the string with `idm_string_free`. Replaces any earlier callback;
+/// null just removes it. Progress events are coalesced per task like the engine's event
+/// subscription: at most one per `idm_config_set_progress_event_interval_ms`, so
+/// intermediate byte counts may be skipped, while state changes are passed on
+/// immediately, right after that task's newest held-back progress.
+#[no_mangle]
+pub extern "C" fn idm_engine_set_event_callback(
+    ptr: *mut EngineHandle,
+    callback: Option<extern "C" fn(event_json: *mut c_char, user_data: *mut c_void)>,
+    user_data: *mut c_void,
+) -> i32 {
+    if ptr.is_null() {
+        return -1;
+    }
+    let handle = unsafe { &*ptr };
+    let Ok(mut current) = handle.callback_stop.lock() else {
+        return -1;
+    };
+    if let Some(stop) = current.take() {
+        stop.store(true, Ordering::SeqCst);
+    }
+    let Some(callback) = callback else {
+        return 0;
+    };
+    let events = match handle.engine.lock() {
+        Ok(engine) => engine.subscribe(),
+        Err(_) => return -1,
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    *current = Some(Arc::clone(&stop));
+    let user_data = UserData(user_data);
+    thread::spawn(move || {
+        let user_data = user_data;
+        // Ends once the engine is freed and its event senders are dropped.
+        for event in events {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            let Some(json) = serde_json::to_string(&event)
+                .ok()
+                .and_then(|value| CString::new(value).ok())
+            else {
+                continue;
+            };
+            callback(json.into_raw(), user_data.0);
+        }
+    });
+    0
+}
+
 #[no_mangle]
 pub extern "C" fn idm_engine_pause_task(ptr: *mut EngineHandle, id: *const c_char) -> i32 {
     control_task(ptr, id, |engine, task_id| engine.pause_task(task_id))
@@ -1,27 +1,35 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::sync::Arc;
 
-use md5::{Digest as Md5Digest, Md5};
-use sha1::{Digest as Sha1Digest, Sha1};
-use sha2::{Digest as Sha2Digest, Sha256};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+use crate::error::{CoreError, CoreResult};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChecksumType {
     Md5,
     Sha1,
     Sha256,
+    /// Checked by the `Verifier` registered under this name.
+    Custom(String),
 }
 
 impl ChecksumType {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             ChecksumType::Md5 => "md5",
             ChecksumType::Sha1 => "sha1",
             ChecksumType::Sha256 => "sha256",
+            ChecksumType::Custom(name) => name,
         }
     }
 
+    /// Built-in types only.
     pub fn from_str(value: &str) -> Option<Self> {
         match value {
             "md5" => Some(ChecksumType::Md5),
@@ -30,77 +38,150 @@ impl ChecksumType {
             _ => None,
         }
     }
+
+    /// Like `from_str`, but any other name is a `Custom` type.
+    pub fn from_name(value: &str) -> Self {
+        Self::from_str(value).unwrap_or_else(|| ChecksumType::Custom(value.to_string()))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChecksumRequest {
     pub checksum_type: ChecksumType,
+    /// Hex for the built-in types; custom verifiers get it verbatim.
     pub expected_hex: String,
 }
 
-pub fn verify_checksum(path: &str, req: &ChecksumRequest) -> bool {
-    match req.checksum_type {
-        ChecksumType::Md5 => verify_md5(path, &req.expected_hex),
-        ChecksumType::Sha1 => verify_sha1(path, &req.expected_hex),
-        ChecksumType::Sha256 => verify_sha256(path, &req.expected_hex),
+impl ChecksumRequest {
+    /// Parses `<type>:<hex>`, e.g. `sha256:9f86d0…`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, hex) = spec.split_once(':')?;
+        let checksum_type = ChecksumType::from_str(&kind.trim().to_ascii_lowercase())?;
+        let hex = hex.trim();
+        if hex.is_empty() || !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(Self {
+            checksum_type,
+            expected_hex: hex.to_string(),
+        })
+    }
+
+    pub fn custom(name: impl Into<String>, expected: impl Into<String>) -> Self {
+        Self {
+            checksum_type: ChecksumType::Custom(name.into()),
+            expected_hex: expected.into(),
+        }
     }
 }
 
-fn verify_md5(path: &str, expected: &str) -> bool {
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return false,
-    };
-    let mut hasher = <Md5 as Md5Digest>::new();
-    let mut buf = [0u8; 1024 * 64];
-    loop {
-        let read = match file.read(&mut buf) {
-            Ok(0) => break,
-            Ok(n) => n,
-            Err(_) => return false,
+/// A checksum algorithm, registered with the engine under a name.
+pub trait Verifier: Send + Sync {
+    /// The file's digest, written the way expected values are.
+    fn compute(&self, path: &str) -> CoreResult<String>;
+
+    /// Compares case-insensitively, which suits hex digests.
+    fn verify(&self, path: &str, expected: &str) -> bool {
+        self.compute(path)
+            .is_ok_and(|actual| actual.eq_ignore_ascii_case(expected.trim()))
+    }
+}
+
+struct DigestVerifier(fn(&str) -> CoreResult<String>);
+
+impl Verifier for DigestVerifier {
+    fn compute(&self, path: &str) -> CoreResult<String> {
+        (self.0)(path)
+    }
+}
+
+/// Verifiers by checksum type name; `md5`, `sha1` and `sha256` are registered by default.
+#[derive(Clone)]
+pub struct VerifierRegistry {
+    verifiers: HashMap<String, Arc<dyn Verifier>>,
+}
+
+impl Default for VerifierRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            verifiers: HashMap::new(),
         };
-        hasher.update(&buf[..read]);
+        registry.register("md5", Arc::new(DigestVerifier(hex_digest::<Md5>)));
+        registry.register("sha1", Arc::new(DigestVerifier(hex_digest::<Sha1>)));
+        registry.register("sha256", Arc::new(DigestVerifier(hex_digest::<Sha256>)));
+        registry
     }
-    let actual = format!("{:x}", hasher.finalize());
-    actual.eq_ignore_ascii_case(expected)
 }
 
-fn verify_sha1(path: &str, expected: &str) -> bool {
-    let file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return false,
-    };
-    let mut reader = BufReader::new(file);
-    let mut hasher = <Sha1 as Sha1Digest>::new();
+impl VerifierRegistry {
+    /// Replaces any verifier already registered as `name`, built-ins included.
+    pub fn register(&mut self, name: impl Into<String>, verifier: Arc<dyn Verifier>) {
+        self.verifiers.insert(name.into(), verifier);
+    }
+
+    pub fn get(&self, checksum_type: &ChecksumType) -> Option<&Arc<dyn Verifier>> {
+        self.verifiers.get(checksum_type.as_str())
+    }
+
+    /// Fails with `Unsupported` when nothing is registered for the request's type.
+    pub fn verify(&self, path: &str, req: &ChecksumRequest) -> CoreResult<bool> {
+        let verifier = self.get(&req.checksum_type).ok_or_else(|| {
+            CoreError::Unsupported(format!(
+                "no checksum verifier registered as {}",
+                req.checksum_type.as_str()
+            ))
+        })?;
+        Ok(verifier.verify(path, &req.expected_hex))
+    }
+}
+
+/// Checks with the built-in verifiers; `false` for custom types.
+pub fn verify_checksum(path: &str, req: &ChecksumRequest) -> bool {
+    VerifierRegistry::default()
+        .verify(path, req)
+        .unwrap_or(false)
+}
+
+/// SHA-256 of `len` bytes of `path` starting at `start`, lowercase hex. Fails if the
+/// file ends first.
+pub fn range_sha256(path: &str, start: u64, len: u64) -> CoreResult<String> {
+    let mut file = File::open(path).map_err(|err| CoreError::Io(err.to_string()))?;
+    file.seek(SeekFrom::Start(start))
+        .map_err(|err| CoreError::Io(err.to_string()))?;
+    let mut reader = BufReader::new(file).take(len);
+    let mut hasher = Sha256::new();
     let mut buf = [0u8; 1024 * 64];
-    loop {
-        let read = match reader.read(&mut buf) {
-            Ok(0) => break,
-            Ok(n) => n,
-            Err(_) => return false,
-        };
+    let mut remaining = len;
+    while remaining > 0 {
+        let read = reader
+            .read(&mut buf)
+            .map_err(|err| CoreError::Io(err.to_string()))?;
+        if read == 0 {
+            return Err(CoreError::Io(format!(
+                "{} ends before byte {}",
+                path,
+                start + len
+            )));
+        }
         hasher.update(&buf[..read]);
+        remaining -= read as u64;
     }
-    let actual = format!("{:x}", hasher.finalize());
-    actual.eq_ignore_ascii_case(expected)
+    Ok(hex::encode(hasher.finalize()))
 }
 
-fn verify_sha256(path: &str, expected: &str) -> bool {
-    let file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return false,
-    };
+fn hex_digest<D: Digest>(path: &str) -> CoreResult<String> {
+    let file = File::open(path).map_err(|err| CoreError::Io(err.to_string()))?;
     let mut reader = BufReader::new(file);
-    let mut hasher = <Sha256 as Sha2Digest>::new();
+    let mut hasher = D::new();
     let mut buf = [0u8; 1024 * 64];
     loop {
-        let read = match reader.read(&mut buf) {
-            Ok(0) => break,
-            Ok(n) => n,
-            Err(_) => return false,
-        };
+        let read = reader
+            .read(&mut buf)
+            .map_err(|err| CoreError::Io(err.to_string()))?;
+        if read == 0 {
+            break;
+        }
         hasher.update(&buf[..read]);
     }
-    let actual = format!("{:x}", hasher.finalize());
-    actual.eq_ignore_ascii_case(expected)
+    Ok(hex::encode(hasher.finalize()))
 }
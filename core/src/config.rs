@@ -1,30 +1,432 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::error::{CoreError, CoreResult};
+use crate::segment::{validate_breakpoints, DEFAULT_CONCURRENCY_BREAKPOINTS};
+use crate::template::OutputTemplate;
+
+/// How the destination file is sized before a ranged download starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preallocate {
+    /// `set_len` to the full size up front (zero-fills on filesystems without sparse files, e.g. exFAT).
+    #[default]
+    Always,
+    /// Leave the file to grow as segments are written at their offsets.
+    Never,
+    /// Reserve blocks with `fallocate` where supported, otherwise behave like `Never`.
+    Sparse,
+}
+
+/// When requests carry `Accept-Encoding: identity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdentityEncoding {
+    /// Never; for the rare server that rejects `identity`.
+    Never,
+    /// On ranged requests, where a compressed body would not match the requested bytes.
+    #[default]
+    Ranged,
+    /// On every request, including probes.
+    Always,
+}
+
+/// What a fresh download does when its destination file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnExisting {
+    /// Replace the existing file once the download completes.
+    #[default]
+    Overwrite,
+    /// Complete the task without downloading if the file already has the expected size;
+    /// a file of any other size is kept and the download is renamed as with `Rename`.
+    Skip,
+    /// Save under the first free `name (n).ext` beside the existing file.
+    Rename,
+}
+
+/// How many segments a ranged download is split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SegmentStrategy {
+    /// By file size from `concurrency_breakpoints`, capped by `max_segments_per_task`.
+    #[default]
+    Smart,
+    /// Exactly this many, whatever the size; only `min_segment_size_bytes` can lower it.
+    Fixed(u32),
+    /// Meant to adjust to measured throughput; behaves like `Smart` for now.
+    Adaptive,
+}
+
 #[derive(Debug, Clone)]
 pub struct EngineConfig {
     pub max_concurrent_tasks: usize,
+    /// Cap on queued, resolving, active and paused tasks; `add_task` fails once it is
+    /// reached. Finished, failed and canceled tasks don't count. `None` means no cap.
+    pub max_tasks: Option<usize>,
     pub max_segments_per_task: u32,
+    /// Simultaneous connections allowed to one hostname across all tasks; `0` means unlimited.
+    pub max_connections_per_host: usize,
     pub min_segment_size_bytes: u64,
+    /// `(max_bytes, connections)` pairs mapping file size to segment count, still capped
+    /// by `max_segments_per_task`; see `segment::concurrency_for`.
+    pub concurrency_breakpoints: Vec<(u64, u32)>,
+    pub segment_strategy: SegmentStrategy,
     pub global_speed_limit_bytes_per_sec: Option<u64>,
     pub per_task_speed_limit_bytes_per_sec: Option<u64>,
     pub user_agent: String,
+    /// User-Agents tried in turn after a download request is refused with `403`.
+    pub user_agent_rotation: Vec<String>,
+    /// Headers sent with every request; a task's own header of the same name wins.
+    pub default_headers: HashMap<String, String>,
+    /// Per-segment retries once a download URL has been resolved.
     pub retry_count: u32,
     pub retry_backoff_secs: u64,
+    /// Whole-task retries when no candidate URL can be resolved (DNS, HEAD failures).
+    pub task_retry_count: u32,
+    pub task_retry_backoff_secs: u64,
+    /// Times a failed task is put back in the queue before it stays `Failed`; `0` fails
+    /// on the first error.
+    pub max_task_restarts: u32,
+    /// Wait before a re-queued task may start again.
+    pub task_restart_cooldown_secs: u64,
+    /// Abort a segment's read after this many seconds without data and let it retry
+    /// from its current offset; `0` disables stall detection.
+    pub stall_timeout_secs: u64,
     pub progress_flush_bytes: u64,
+    /// Also persist progress once this many milliseconds have passed since the last
+    /// flush, so slow downloads lose a bounded window on a crash. `0` flushes by bytes only.
+    pub progress_flush_interval_ms: u64,
+    /// Minimum milliseconds between `TaskProgress` events for one task; updates in between
+    /// are coalesced. `0` sends every update.
+    pub progress_event_interval_ms: u64,
     pub status_check_bytes: u64,
+    pub hls_write_buffer_bytes: usize,
+    pub completed_dir: Option<PathBuf>,
+    /// Where in-progress `.part` files are written instead of beside the destination, e.g.
+    /// fast local storage when saving to a network share.
+    pub temp_dir: Option<PathBuf>,
+    pub preallocate: Preallocate,
+    pub identity_encoding: IdentityEncoding,
+    pub on_existing: OnExisting,
+    /// Send the page a direct link was scraped from as that link's `Referer`, unless
+    /// the task sets its own; some hosts refuse the download otherwise.
+    pub auto_referer: bool,
+    /// Learn size and range support from a `Range: bytes=0-0` GET instead of a HEAD, for
+    /// servers that reject or misreport HEAD. A HEAD answered with `405` or `501` falls
+    /// back to the GET either way.
+    pub skip_head: bool,
+    /// Unpack completed `.zip`/`.tar.gz` downloads into a sibling directory.
+    pub auto_extract: bool,
+    pub delete_archive_after_extract: bool,
+    /// Decode completed `.gz`/`.bz2`/`.xz` downloads into the name without that extension
+    /// and remove the compressed file. Archives `auto_extract` understands are left alone.
+    pub decompress_on_complete: bool,
+    /// Set finished files' mtime from the server's `Last-Modified` header.
+    pub preserve_mtime: bool,
+    /// Save every file of a multi-file download (e.g. a Pixeldrain list) straight into
+    /// the destination instead of recreating the source's subdirectories under it.
+    pub flat_output: bool,
+    /// Hash each segment as it completes and store the digest with it, so
+    /// `DownloadEngine::verify_segments` can later find damaged ranges.
+    pub segment_checksums: bool,
+    /// Names files saved into a directory destination, e.g. `%(host)s-%(name)s.%(ext)s`.
+    pub output_template: Option<OutputTemplate>,
+}
+
+impl EngineConfig {
+    pub fn builder() -> EngineConfigBuilder {
+        EngineConfigBuilder::default()
+    }
 }
 
 impl Default for EngineConfig {
     fn default() -> Self {
         Self {
             max_concurrent_tasks: 4,
+            max_tasks: None,
             max_segments_per_task: 8,
+            max_connections_per_host: 0,
             min_segment_size_bytes: 2 * 1024 * 1024,
+            concurrency_breakpoints: DEFAULT_CONCURRENCY_BREAKPOINTS.to_vec(),
+            segment_strategy: SegmentStrategy::Smart,
             global_speed_limit_bytes_per_sec: None,
             per_task_speed_limit_bytes_per_sec: None,
             user_agent: "IDM-Open/0.1".to_string(),
+            user_agent_rotation: Vec::new(),
+            default_headers: HashMap::new(),
             retry_count: 5,
             retry_backoff_secs: 3,
+            task_retry_count: 3,
+            task_retry_backoff_secs: 10,
+            max_task_restarts: 0,
+            task_restart_cooldown_secs: 15 * 60,
+            stall_timeout_secs: 60,
             progress_flush_bytes: 1024 * 1024,
+            progress_flush_interval_ms: 5_000,
+            progress_event_interval_ms: 250,
             status_check_bytes: 512 * 1024,
+            hls_write_buffer_bytes: 1024 * 1024,
+            completed_dir: None,
+            temp_dir: None,
+            preallocate: Preallocate::Always,
+            identity_encoding: IdentityEncoding::Ranged,
+            on_existing: OnExisting::Overwrite,
+            auto_referer: true,
+            skip_head: false,
+            auto_extract: false,
+            delete_archive_after_extract: false,
+            decompress_on_complete: false,
+            preserve_mtime: false,
+            flat_output: false,
+            segment_checksums: false,
+            output_template: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfigBuilder {
+    config: EngineConfig,
+}
+
+/// Starts from an existing configuration, e.g. to validate one built by hand.
+impl From<EngineConfig> for EngineConfigBuilder {
+    fn from(config: EngineConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl EngineConfigBuilder {
+    pub fn max_concurrent(mut self, tasks: usize) -> Self {
+        self.config.max_concurrent_tasks = tasks;
+        self
+    }
+
+    /// `0` means no cap.
+    pub fn max_tasks(mut self, tasks: usize) -> Self {
+        self.config.max_tasks = Some(tasks).filter(|v| *v > 0);
+        self
+    }
+
+    pub fn segments(mut self, segments: u32) -> Self {
+        self.config.max_segments_per_task = segments;
+        self
+    }
+
+    pub fn max_connections_per_host(mut self, connections: usize) -> Self {
+        self.config.max_connections_per_host = connections;
+        self
+    }
+
+    pub fn min_segment_size(mut self, bytes: u64) -> Self {
+        self.config.min_segment_size_bytes = bytes;
+        self
+    }
+
+    /// Replaces the default size-to-connections mapping, e.g. `[(u64::MAX, 8)]` to split
+    /// every file eight ways.
+    pub fn concurrency_breakpoints(mut self, breakpoints: Vec<(u64, u32)>) -> Self {
+        self.config.concurrency_breakpoints = breakpoints;
+        self
+    }
+
+    pub fn segment_strategy(mut self, strategy: SegmentStrategy) -> Self {
+        self.config.segment_strategy = strategy;
+        self
+    }
+
+    /// Global speed limit in bytes per second; `0` means unlimited.
+    pub fn global_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.config.global_speed_limit_bytes_per_sec = Some(bytes_per_sec).filter(|v| *v > 0);
+        self
+    }
+
+    /// Per-task speed limit in bytes per second; `0` means unlimited.
+    pub fn per_task_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.config.per_task_speed_limit_bytes_per_sec = Some(bytes_per_sec).filter(|v| *v > 0);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.config.user_agent = user_agent.into();
+        self
+    }
+
+    pub fn user_agent_rotation(mut self, agents: Vec<String>) -> Self {
+        self.config.user_agent_rotation = agents;
+        self
+    }
+
+    pub fn default_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.config.default_headers = headers;
+        self
+    }
+
+    pub fn retry_count(mut self, count: u32) -> Self {
+        self.config.retry_count = count;
+        self
+    }
+
+    pub fn retry_backoff_secs(mut self, secs: u64) -> Self {
+        self.config.retry_backoff_secs = secs;
+        self
+    }
+
+    pub fn task_retry_count(mut self, count: u32) -> Self {
+        self.config.task_retry_count = count;
+        self
+    }
+
+    pub fn task_retry_backoff_secs(mut self, secs: u64) -> Self {
+        self.config.task_retry_backoff_secs = secs;
+        self
+    }
+
+    pub fn max_task_restarts(mut self, restarts: u32) -> Self {
+        self.config.max_task_restarts = restarts;
+        self
+    }
+
+    pub fn task_restart_cooldown_secs(mut self, secs: u64) -> Self {
+        self.config.task_restart_cooldown_secs = secs;
+        self
+    }
+
+    pub fn stall_timeout_secs(mut self, secs: u64) -> Self {
+        self.config.stall_timeout_secs = secs;
+        self
+    }
+
+    pub fn progress_flush_bytes(mut self, bytes: u64) -> Self {
+        self.config.progress_flush_bytes = bytes;
+        self
+    }
+
+    pub fn progress_flush_interval_ms(mut self, millis: u64) -> Self {
+        self.config.progress_flush_interval_ms = millis;
+        self
+    }
+
+    pub fn progress_event_interval_ms(mut self, millis: u64) -> Self {
+        self.config.progress_event_interval_ms = millis;
+        self
+    }
+
+    pub fn status_check_bytes(mut self, bytes: u64) -> Self {
+        self.config.status_check_bytes = bytes;
+        self
+    }
+
+    pub fn hls_write_buffer_bytes(mut self, bytes: usize) -> Self {
+        self.config.hls_write_buffer_bytes = bytes;
+        self
+    }
+
+    /// Finished files are moved into this directory, keeping their filename.
+    pub fn completed_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.completed_dir = Some(dir.into());
+        self
+    }
+
+    /// Downloads are written here and moved to their destination once complete.
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.temp_dir = Some(dir.into());
+        self
+    }
+
+    pub fn preallocate(mut self, mode: Preallocate) -> Self {
+        self.config.preallocate = mode;
+        self
+    }
+
+    pub fn on_existing(mut self, policy: OnExisting) -> Self {
+        self.config.on_existing = policy;
+        self
+    }
+
+    pub fn identity_encoding(mut self, mode: IdentityEncoding) -> Self {
+        self.config.identity_encoding = mode;
+        self
+    }
+
+    pub fn skip_head(mut self, enabled: bool) -> Self {
+        self.config.skip_head = enabled;
+        self
+    }
+
+    pub fn auto_extract(mut self, enabled: bool) -> Self {
+        self.config.auto_extract = enabled;
+        self
+    }
+
+    pub fn delete_archive_after_extract(mut self, enabled: bool) -> Self {
+        self.config.delete_archive_after_extract = enabled;
+        self
+    }
+
+    pub fn decompress_on_complete(mut self, enabled: bool) -> Self {
+        self.config.decompress_on_complete = enabled;
+        self
+    }
+
+    pub fn auto_referer(mut self, enabled: bool) -> Self {
+        self.config.auto_referer = enabled;
+        self
+    }
+
+    pub fn preserve_mtime(mut self, enabled: bool) -> Self {
+        self.config.preserve_mtime = enabled;
+        self
+    }
+
+    pub fn flat_output(mut self, enabled: bool) -> Self {
+        self.config.flat_output = enabled;
+        self
+    }
+
+    pub fn segment_checksums(mut self, enabled: bool) -> Self {
+        self.config.segment_checksums = enabled;
+        self
+    }
+
+    pub fn output_template(mut self, template: OutputTemplate) -> Self {
+        self.config.output_template = Some(template);
+        self
+    }
+
+    pub fn build(self) -> CoreResult<EngineConfig> {
+        let config = self.config;
+        if config.max_concurrent_tasks == 0 {
+            return Err(CoreError::InvalidConfig(
+                "max_concurrent_tasks must be at least 1".to_string(),
+            ));
+        }
+        if config.max_segments_per_task == 0 {
+            return Err(CoreError::InvalidConfig(
+                "max_segments_per_task must be at least 1".to_string(),
+            ));
+        }
+        if config.user_agent.trim().is_empty() {
+            return Err(CoreError::InvalidConfig(
+                "user_agent must not be empty".to_string(),
+            ));
+        }
+        validate_breakpoints(&config.concurrency_breakpoints).map_err(CoreError::InvalidConfig)?;
+        if let Some(name) = config.default_headers.keys().find(|name| {
+            name.is_empty() || !name.chars().all(|ch| ch.is_ascii_graphic() && ch != ':')
+        }) {
+            return Err(CoreError::InvalidConfig(format!(
+                "invalid default header name {:?}",
+                name
+            )));
+        }
+        if config.segment_strategy == SegmentStrategy::Fixed(0) {
+            return Err(CoreError::InvalidConfig(
+                "fixed segment count must be at least 1".to_string(),
+            ));
+        }
+        if config.progress_flush_bytes == 0 || config.status_check_bytes == 0 {
+            return Err(CoreError::InvalidConfig(
+                "progress_flush_bytes and status_check_bytes must be non-zero".to_string(),
+            ));
         }
+        Ok(config)
     }
 }
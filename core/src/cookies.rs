@@ -0,0 +1,116 @@
+//! Netscape `cookies.txt` jars, as exported by browsers and read by curl/wget.
+
+use std::collections::HashMap;
+
+use url::Url;
+
+use crate::error::{CoreError, CoreResult};
+
+/// One line of a cookie jar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetscapeCookie {
+    /// Without the leading dot.
+    pub domain: String,
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    /// Seconds since the Unix epoch; `0` for session cookies.
+    pub expires: u64,
+    pub http_only: bool,
+    pub name: String,
+    pub value: String,
+}
+
+impl NetscapeCookie {
+    /// Whether a browser would send this cookie to `url` at `now`.
+    pub fn matches(&self, url: &Url, now: u64) -> bool {
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return false;
+        };
+        let domain_ok = host == self.domain
+            || (self.include_subdomains && host.ends_with(&format!(".{}", self.domain)));
+        let path = url.path();
+        let path_ok = path == self.path
+            || (path.starts_with(&self.path)
+                && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')));
+        let scheme_ok = !self.secure || url.scheme() == "https";
+        let fresh = self.expires == 0 || self.expires > now;
+        domain_ok && path_ok && scheme_ok && fresh
+    }
+}
+
+/// Parses a jar: tab-separated `domain`, `include_subdomains`, `path`, `secure`,
+/// `expires`, `name`, `value`. Blank lines and `#` comments are skipped, except the
+/// `#HttpOnly_` prefix curl uses to mark HttpOnly cookies.
+pub fn parse_netscape_cookies(text: &str) -> CoreResult<Vec<NetscapeCookie>> {
+    let mut cookies = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| {
+            CoreError::Serialization(format!(
+                "invalid cookies.txt line {}: {}",
+                number + 1,
+                reason
+            ))
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        // Some exporters drop the trailing tab of an empty value.
+        if fields.len() != 7 && fields.len() != 6 {
+            return Err(invalid("expected 7 tab-separated fields"));
+        }
+        let flag = |value: &str| value.eq_ignore_ascii_case("TRUE");
+        let raw_domain = fields[0].trim().to_ascii_lowercase();
+        let domain = raw_domain.trim_start_matches('.').to_string();
+        if domain.is_empty() {
+            return Err(invalid("empty domain"));
+        }
+        let expires = fields[4]
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| invalid("invalid expiry"))?
+            .max(0.0) as u64;
+        cookies.push(NetscapeCookie {
+            include_subdomains: flag(fields[1]) || raw_domain.starts_with('.'),
+            domain,
+            path: match fields[2].trim() {
+                "" => "/".to_string(),
+                path => path.to_string(),
+            },
+            secure: flag(fields[3]),
+            expires,
+            http_only,
+            name: fields[5].to_string(),
+            value: fields.get(6).copied().unwrap_or_default().to_string(),
+        });
+    }
+    Ok(cookies)
+}
+
+/// The name/value pairs from a jar that apply to `url`. When several cookies share a
+/// name, the one with the longest path wins, as a browser would send it first.
+pub fn cookies_for_url(
+    cookies: &[NetscapeCookie],
+    url: &str,
+    now: u64,
+) -> CoreResult<HashMap<String, String>> {
+    let url = Url::parse(url)
+        .map_err(|err| CoreError::Serialization(format!("invalid URL {}: {}", url, err)))?;
+    let mut matching: Vec<&NetscapeCookie> = cookies
+        .iter()
+        .filter(|cookie| cookie.matches(&url, now))
+        .collect();
+    matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+    let mut out = HashMap::new();
+    for cookie in matching {
+        out.entry(cookie.name.clone())
+            .or_insert_with(|| cookie.value.clone());
+    }
+    Ok(out)
+}
@@ -0,0 +1,86 @@
+//! `data:` URLs (RFC 2397). The payload is in the URL itself, so nothing is fetched.
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+
+use crate::error::{CoreError, CoreResult};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataUrl {
+    /// Lowercase, without parameters; `text/plain` when the URL names none.
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+pub fn is_data_url(url: &str) -> bool {
+    url.get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// Decodes `data:[<media type>][;base64],<data>`; the data may be percent-encoded
+/// either way.
+pub fn parse_data_url(url: &str) -> CoreResult<DataUrl> {
+    let invalid = |reason: &str| CoreError::Serialization(format!("invalid data URL: {}", reason));
+    if !is_data_url(url) {
+        return Err(invalid("missing data: scheme"));
+    }
+    let (header, payload) = url[5..]
+        .split_once(',')
+        .ok_or_else(|| invalid("missing ','"))?;
+    let mut params = header.split(';').map(str::trim);
+    let media_type = params
+        .next()
+        .filter(|media_type| media_type.contains('/'))
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_else(|| "text/plain".to_string());
+    let base64 = params.any(|param| param.eq_ignore_ascii_case("base64"));
+
+    let decoded: Vec<u8> = percent_decode_str(payload).collect();
+    let data = if base64 {
+        decode_base64(&decoded).ok_or_else(|| invalid("malformed base64"))?
+    } else {
+        decoded
+    };
+    Ok(DataUrl { media_type, data })
+}
+
+/// File extension for a media type, `bin` for ones not listed.
+pub fn extension_for(media_type: &str) -> &'static str {
+    match media_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/avif" => "avif",
+        "image/bmp" => "bmp",
+        "image/svg+xml" => "svg",
+        "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "text/css" => "css",
+        "text/csv" => "csv",
+        "text/javascript" | "application/javascript" => "js",
+        "text/xml" | "application/xml" => "xml",
+        "application/json" => "json",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "audio/mpeg" => "mp3",
+        "audio/wav" | "audio/x-wav" => "wav",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        _ => "bin",
+    }
+}
+
+/// Standard or URL-safe alphabet; whitespace is skipped and padding is optional.
+fn decode_base64(input: &[u8]) -> Option<Vec<u8>> {
+    let compact: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(&compact).ok())
+}
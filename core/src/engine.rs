@@ -1,57 +1,211 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs::{self, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::checksum::verify_checksum;
-use crate::config::EngineConfig;
+use crate::checksum::{range_sha256, ChecksumRequest, Verifier, VerifierRegistry};
+use crate::config::{EngineConfig, EngineConfigBuilder, IdentityEncoding, OnExisting, Preallocate};
+use crate::data_url::{extension_for, is_data_url, parse_data_url};
 use crate::error::{CoreError, CoreResult};
-use crate::net::{DownloadRequest, NetClient, ReqwestNetClient};
+use crate::event::{EngineEvent, EventBus};
+use crate::extract::{decompress_file, detect_archive, detect_compression, extract_archive};
+use crate::history::{eta, SpeedHistory};
+use crate::net::{
+    content_range_total, multipart_byteranges_boundary, multipart_first_part, parse_content_range,
+    probe_with_get, retry_after, unsatisfied_range_total, DefaultNetClient, DownloadRequest,
+    DownloadResponse, HostLimiter, NetClient, Response,
+};
 use crate::queue::{QueueItem, TaskQueue};
 use crate::resolver::{
-    detect_provider, is_html_content_type, resolve_html_download, resolve_url_candidates, Provider,
+    cloudflare_challenge_error, detect_provider, is_cloudflare_challenge, is_html_content_type,
+    pixeldrain_list_id, resolve_html_download, resolve_pixeldrain_list, resolve_url_candidates,
+    Provider,
 };
 use crate::scheduler::Scheduler;
-use crate::segment::{build_segments, Segment, SegmentStatus};
+use crate::segment::{build_segments, carry_over_progress, tiles_file, Segment, SegmentStatus};
 use crate::storage::{MemoryStorage, Storage};
-use crate::task::{Task, TaskId, TaskStatus};
-use crate::throttle::Throttle;
-use reqwest::Url;
+use crate::task::{
+    now_epoch, HttpMethod, PauseReason, Task, TaskBuilder, TaskFilter, TaskId, TaskStatus,
+    TaskSummary,
+};
+use crate::template::{OutputTemplate, TemplateVars};
+use crate::throttle::{GlobalThrottle, Throttle};
+use crate::torrent::{is_torrent, parse_torrent, MAX_TORRENT_BYTES};
+use filetime::FileTime;
+use http::header::{CONTENT_RANGE, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+pub(crate) const STOP_NONE: u8 = 0;
+pub(crate) const STOP_PAUSED: u8 = 1;
+pub(crate) const STOP_CANCELED: u8 = 2;
+pub(crate) const STOP_FAILED: u8 = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnqueueReport {
+    /// Tasks newly pushed onto the queue (includes `recovered`).
+    pub queued: usize,
+    /// Orphaned `Active` tasks reset to `Queued`.
+    pub recovered: usize,
+    pub already_queued: usize,
+    /// `Active` tasks with a running worker, left untouched.
+    pub skipped_active: usize,
+    /// Automatically re-queued tasks still inside `task_restart_cooldown_secs`.
+    pub cooling_down: usize,
+    /// `Queued` tasks whose `depends_on` has not completed yet.
+    pub waiting: usize,
+}
+
+/// Task counts by status plus byte totals; see `DownloadEngine::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineStats {
+    pub queued: usize,
+    pub resolving: usize,
+    pub active: usize,
+    pub paused: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub canceled: usize,
+    /// Bytes downloaded across all tasks; sample twice to derive an aggregate speed.
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// What `DownloadEngine::probe` learned about a URL without downloading it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProbeResult {
+    /// The URL a download would use, after mirrors, hosting pages and redirects.
+    pub final_url: String,
+    /// The name the file would be saved under in a directory destination.
+    pub filename: String,
+    /// `None` when the server does not say.
+    pub total_bytes: Option<u64>,
+    /// Whether the download can be split across connections.
+    pub accept_ranges: bool,
+    pub content_type: Option<String>,
+}
+
+/// Probes URLs like `DownloadEngine::probe`; see `DownloadEngine::prober`.
+#[derive(Clone)]
+pub struct Prober {
+    config: EngineConfig,
+    net: Arc<dyn NetClient>,
+    rename_hook: Option<RenameHook>,
+}
+
+impl Prober {
+    /// Same as `DownloadEngine::probe`, with the engine's settings at the time the
+    /// prober was made.
+    pub fn probe(&self, url: &str) -> CoreResult<ProbeResult> {
+        let config = &self.config;
+        let mut task = Task::new(url.to_string(), String::new());
+        task.extract_url_credentials();
+        let candidates = resolve_url_candidates(task.url_candidates());
+        let resolution = resolve_target(&task, &candidates, config, &self.net, &|| false)?;
+        let head = resolution.selected_head.as_ref();
+        let final_url = head
+            .and_then(|resp| resp.final_url.clone())
+            .unwrap_or(resolution.selected_url);
+        let content_disposition = head.and_then(|resp| resp.content_disposition.as_deref());
+        let content_type = head.and_then(|resp| resp.content_type.clone());
+        let hooked = self.rename_hook.as_ref().and_then(|hook| {
+            let suggested = suggested_filename(&final_url, content_disposition);
+            hook(&final_url, &suggested, content_type.as_deref())
+                .map(|name| sanitize_filename(&name))
+        });
+        let filename = hooked.unwrap_or_else(|| {
+            let dest = resolve_dest_path(
+                "",
+                &final_url,
+                content_disposition,
+                config.output_template.as_ref(),
+                &task.id,
+            );
+            Path::new(&dest)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        Ok(ProbeResult {
+            final_url,
+            filename,
+            total_bytes: Some(resolution.total_bytes).filter(|bytes| *bytes > 0),
+            accept_ranges: resolution.accept_ranges,
+            content_type,
+        })
+    }
+}
+
+/// Outcome of `DownloadEngine::remove_by`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoveReport {
+    pub removed: usize,
+    /// Matching tasks left in place because they are running.
+    pub skipped: Vec<TaskId>,
+}
+
+/// Called with `(url, suggested_name, content_type)` once a directory destination's
+/// filename is known; returning `Some(name)` overrides the suggestion (sanitized like a
+/// `Content-Disposition` name, so it stays in the directory), `None` keeps the default.
+pub type RenameHook = Arc<dyn Fn(&str, &str, Option<&str>) -> Option<String> + Send + Sync>;
 
-const STOP_NONE: u8 = 0;
-const STOP_PAUSED: u8 = 1;
-const STOP_CANCELED: u8 = 2;
-const STOP_FAILED: u8 = 3;
+/// Connection and bandwidth limits shared by all of an engine's tasks.
+#[derive(Clone)]
+struct SharedLimits {
+    hosts: HostLimiter,
+    bandwidth: GlobalThrottle,
+}
 
 pub struct DownloadEngine {
-    pub config: EngineConfig,
+    /// Read through `config()`; replaced by `reconfigure`.
+    config: RwLock<EngineConfig>,
     pub scheduler: Scheduler,
     storage: Arc<Mutex<Box<dyn Storage>>>,
     net: Arc<dyn NetClient>,
-    queue: Mutex<TaskQueue>,
+    limits: SharedLimits,
+    queue: Arc<Mutex<TaskQueue>>,
     active: Arc<Mutex<HashSet<TaskId>>>,
     handles: Mutex<Vec<JoinHandle<()>>>,
+    events: EventBus,
+    /// Speed samples recorded by running tasks.
+    history: SpeedHistory,
+    rename_hook: Option<RenameHook>,
+    verifiers: VerifierRegistry,
+    /// Set by `shutdown`; no task starts after it.
+    stopping: AtomicBool,
 }
 
 impl DownloadEngine {
     pub fn new(config: EngineConfig) -> Self {
         let scheduler = Scheduler::new(config.max_concurrent_tasks);
-        let net = ReqwestNetClient::new(&config.user_agent)
-            .unwrap_or_else(|_| ReqwestNetClient::new("IDM-Open/0.1").expect("net client"));
+        let net = DefaultNetClient::new(&config.user_agent)
+            .unwrap_or_else(|_| DefaultNetClient::new("IDM-Open/0.1").expect("net client"));
+        let progress_interval = Duration::from_millis(config.progress_event_interval_ms);
+        let limits = SharedLimits {
+            hosts: HostLimiter::new(config.max_connections_per_host),
+            bandwidth: GlobalThrottle::new(config.global_speed_limit_bytes_per_sec),
+        };
         Self {
-            config,
+            config: RwLock::new(config),
             scheduler,
             storage: Arc::new(Mutex::new(Box::new(MemoryStorage::default()))),
             net: Arc::new(net),
-            queue: Mutex::new(TaskQueue::default()),
+            limits,
+            queue: Arc::new(Mutex::new(TaskQueue::default())),
             active: Arc::new(Mutex::new(HashSet::new())),
             handles: Mutex::new(Vec::new()),
+            events: EventBus::with_progress_interval(progress_interval),
+            history: SpeedHistory::default(),
+            rename_hook: None,
+            verifiers: VerifierRegistry::default(),
+            stopping: AtomicBool::new(false),
         }
     }
 
@@ -65,21 +219,200 @@ impl DownloadEngine {
         self
     }
 
+    pub fn with_rename_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, &str, Option<&str>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.rename_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Lets tasks use checksums of type `ChecksumType::Custom(name)`; a built-in name
+    /// replaces that algorithm.
+    pub fn with_verifier<V>(mut self, name: impl Into<String>, verifier: V) -> Self
+    where
+        V: Verifier + 'static,
+    {
+        self.verifiers.register(name, Arc::new(verifier));
+        self
+    }
+
+    /// Checks any file with the engine's verifiers, custom ones included.
+    pub fn verify_file(&self, path: &str, checksum: &ChecksumRequest) -> CoreResult<bool> {
+        self.verifiers.verify(path, checksum)
+    }
+
+    /// Indexes of completed segments whose bytes on disk no longer match the digest
+    /// recorded when they finished (see `EngineConfig::segment_checksums`). Reads the
+    /// `.part` file while one exists; segments without a digest are skipped.
+    pub fn verify_segments(&self, id: &TaskId) -> CoreResult<Vec<u32>> {
+        let (task, segments) = {
+            let storage = self
+                .storage
+                .lock()
+                .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+            (storage.load_task(id)?, storage.load_segments(id)?)
+        };
+        let part = part_path(&task.dest_path, &task.id, self.config().temp_dir.as_deref());
+        let path = if Path::new(&part).exists() {
+            &part
+        } else {
+            &task.dest_path
+        };
+        Ok(segments
+            .iter()
+            .filter(|segment| segment.status == SegmentStatus::Completed)
+            .filter_map(|segment| {
+                let expected = segment.sha256.as_ref()?;
+                let actual = range_sha256(path, segment.range_start, segment.size()).ok();
+                (actual.as_ref() != Some(expected)).then_some(segment.index)
+            })
+            .collect())
+    }
+
+    /// A snapshot of the current configuration.
+    pub fn config(&self) -> EngineConfig {
+        self.config
+            .read()
+            .map(|config| config.clone())
+            .unwrap_or_default()
+    }
+
+    /// Swaps in a new configuration without restarting, after validating it as
+    /// `EngineConfigBuilder::build` would. Concurrency, the global speed limit and the
+    /// per-host connection cap take effect immediately, including for running tasks; other
+    /// settings apply to tasks started afterwards.
+    pub fn reconfigure(&self, config: EngineConfig) -> CoreResult<()> {
+        let config = EngineConfigBuilder::from(config).build()?;
+        self.scheduler.set_max_active(config.max_concurrent_tasks);
+        self.limits
+            .bandwidth
+            .set_limit(config.global_speed_limit_bytes_per_sec);
+        self.limits.hosts.set_limit(config.max_connections_per_host);
+        let mut current = self
+            .config
+            .write()
+            .map_err(|_| CoreError::Storage("config lock poisoned".to_string()))?;
+        *current = config;
+        Ok(())
+    }
+
+    pub fn subscribe(&self) -> Receiver<EngineEvent> {
+        self.events.subscribe()
+    }
+
     pub fn add_task(&self, url: String, dest_path: String) -> CoreResult<TaskId> {
-        let task = Task::new(url, dest_path);
+        self.add_prepared_task(Task::new(url, dest_path))
+    }
+
+    /// Adds a task that stays inert until `resume_task`; `run` and `resume_all` leave it alone.
+    pub fn add_task_paused(&self, url: String, dest_path: String) -> CoreResult<TaskId> {
+        let mut task = Task::new(url, dest_path);
+        task.status = TaskStatus::Paused;
+        task.pause_reason = Some(PauseReason::User);
+        self.add_prepared_task(task)
+    }
+
+    /// Builds, stores and (unless built `paused`) queues the task.
+    pub fn add_task_with(&self, builder: TaskBuilder) -> CoreResult<TaskId> {
+        self.add_prepared_task(builder.build()?)
+    }
+
+    /// Stores `task`; only `Queued` tasks are pushed onto the queue.
+    pub fn add_prepared_task(&self, mut task: Task) -> CoreResult<TaskId> {
+        if let Some(checksum) = &task.checksum {
+            if self.verifiers.get(&checksum.checksum_type).is_none() {
+                return Err(CoreError::InvalidConfig(format!(
+                    "no checksum verifier registered as {}",
+                    checksum.checksum_type.as_str()
+                )));
+            }
+        }
+        task.extract_url_credentials();
         let id = task.id;
+        let max_tasks = self.config().max_tasks;
         let mut storage = self
             .storage
             .lock()
             .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+        if let Some(max_tasks) = max_tasks {
+            let pending = storage
+                .list_task_summaries()?
+                .iter()
+                .filter(|summary| {
+                    matches!(
+                        summary.status,
+                        TaskStatus::Queued
+                            | TaskStatus::Resolving
+                            | TaskStatus::Active
+                            | TaskStatus::Paused
+                    )
+                })
+                .count();
+            if pending >= max_tasks {
+                return Err(CoreError::InvalidState("task limit reached".to_string()));
+            }
+        }
+        check_dependency_chain(storage.as_ref(), &task)?;
         storage.save_task(&task)?;
-        self.queue
-            .lock()
-            .map_err(|_| CoreError::Storage("queue lock poisoned".to_string()))?
-            .push(QueueItem::new(id, task.priority));
+        if task.status == TaskStatus::Queued {
+            self.queue
+                .lock()
+                .map_err(|_| CoreError::Storage("queue lock poisoned".to_string()))?
+                .push(QueueItem::new(id, task.priority));
+        }
+        self.events.emit(EngineEvent::TaskAdded { id });
         Ok(id)
     }
 
+    /// Fetches the `.torrent` file at `task.url` and adds the task with its metadata. A
+    /// directory (or empty) `dest_path` gets the torrent's name. Swarm downloads are not
+    /// supported yet, so running the task fails with `Unsupported`.
+    pub fn add_torrent(&self, mut task: Task) -> CoreResult<TaskId> {
+        task.extract_url_credentials();
+        let config = self.config();
+        let req = task_request(task.url.clone(), &config, &task);
+        let response = self.net.get(&req)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(CoreError::Network(format!(
+                "torrent fetch failed with status {}",
+                status.as_u16()
+            )));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        if !is_torrent(&task.url, content_type) {
+            return Err(CoreError::Unsupported(format!(
+                "not a .torrent file: {}",
+                task.url
+            )));
+        }
+        let mut bytes = Vec::new();
+        response
+            .take(MAX_TORRENT_BYTES + 1)
+            .read_to_end(&mut bytes)
+            .map_err(|err| CoreError::Network(err.to_string()))?;
+        if bytes.len() as u64 > MAX_TORRENT_BYTES {
+            return Err(CoreError::Unsupported(format!(
+                "torrent file larger than {} bytes",
+                MAX_TORRENT_BYTES
+            )));
+        }
+        let info = parse_torrent(&bytes)?;
+        if let Some(dir) = dest_dir(&task.dest_path) {
+            task.dest_path = dir
+                .join(sanitize_filename(&info.name))
+                .to_string_lossy()
+                .to_string();
+        }
+        task.total_bytes = info.length;
+        task.torrent = Some(info);
+        self.add_prepared_task(task)
+    }
+
     pub fn list_tasks(&self) -> CoreResult<Vec<Task>> {
         let storage = self
             .storage
@@ -88,9 +421,120 @@ impl DownloadEngine {
         storage.list_tasks()
     }
 
+    /// Tasks changed after `since` (epoch seconds), for incremental polling.
+    pub fn list_tasks_updated_since(&self, since: u64) -> CoreResult<Vec<Task>> {
+        let storage = self
+            .storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+        storage.list_tasks_updated_since(since)
+    }
+
+    pub fn list_task_summaries(&self) -> CoreResult<Vec<TaskSummary>> {
+        let storage = self
+            .storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+        storage.list_task_summaries()
+    }
+
+    pub fn stats(&self) -> CoreResult<EngineStats> {
+        let mut stats = EngineStats::default();
+        for task in self.list_task_summaries()? {
+            let count = match task.status {
+                TaskStatus::Queued => &mut stats.queued,
+                TaskStatus::Resolving => &mut stats.resolving,
+                TaskStatus::Active => &mut stats.active,
+                TaskStatus::Paused => &mut stats.paused,
+                TaskStatus::Completed => &mut stats.completed,
+                TaskStatus::Failed => &mut stats.failed,
+                TaskStatus::Canceled => &mut stats.canceled,
+            };
+            *count += 1;
+            stats.downloaded_bytes = stats.downloaded_bytes.saturating_add(task.downloaded_bytes);
+            stats.total_bytes = stats.total_bytes.saturating_add(task.total_bytes);
+        }
+        Ok(stats)
+    }
+
+    /// Bytes of every download completed since the last `reset_stats`, including tasks
+    /// removed since.
+    pub fn lifetime_bytes(&self) -> CoreResult<u64> {
+        Ok(self.daily_bytes()?.iter().map(|(_, bytes)| bytes).sum())
+    }
+
+    /// `(day, bytes)` of completed downloads per UTC day (days since the Unix epoch),
+    /// oldest first.
+    pub fn daily_bytes(&self) -> CoreResult<Vec<(u64, u64)>> {
+        self.storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+            .daily_bytes()
+    }
+
+    pub fn reset_stats(&self) -> CoreResult<()> {
+        self.storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+            .reset_stats()
+    }
+
+    /// Pauses (reason `System`) tasks stored as `Active`/`Resolving` that no worker in
+    /// this engine owns, i.e. ones left behind by an unclean shutdown, and returns their
+    /// ids. Call once after `with_storage`, and only from a process that owns the
+    /// database (the daemon): another engine's running tasks look orphaned from here.
+    /// `resume_all` picks them up again.
+    pub fn reconcile_on_start(&self) -> CoreResult<Vec<TaskId>> {
+        let active = self
+            .active
+            .lock()
+            .map_err(|_| CoreError::Storage("active lock poisoned".to_string()))?
+            .clone();
+        let orphaned: Vec<TaskId> = self
+            .list_task_summaries()?
+            .into_iter()
+            .filter(|task| matches!(task.status, TaskStatus::Active | TaskStatus::Resolving))
+            .map(|task| task.id)
+            .filter(|id| !active.contains(id))
+            .collect();
+        let mut recovered = Vec::with_capacity(orphaned.len());
+        {
+            let mut storage = self
+                .storage
+                .lock()
+                .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+            for id in orphaned {
+                let mut task = storage.load_task(&id)?;
+                if !matches!(task.status, TaskStatus::Active | TaskStatus::Resolving) {
+                    continue;
+                }
+                task.status = TaskStatus::Paused;
+                task.pause_reason = Some(PauseReason::System);
+                task.touch();
+                storage.save_task(&task)?;
+                recovered.push(id);
+            }
+        }
+        for id in &recovered {
+            self.events.emit(EngineEvent::TaskPaused { id: *id });
+        }
+        Ok(recovered)
+    }
+
     pub fn enqueue_queued(&self) -> CoreResult<usize> {
-        let tasks = self.list_tasks()?;
-        let mut queued = 0usize;
+        Ok(self.enqueue_queued_detailed()?.queued)
+    }
+
+    /// Pushes stored `Queued` tasks (and running ones left over from a crash) onto the
+    /// in-memory queue. Safe to call repeatedly and while downloads are running.
+    pub fn enqueue_queued_detailed(&self) -> CoreResult<EnqueueReport> {
+        let tasks = self.list_task_summaries()?;
+        let mut report = EnqueueReport::default();
+        let active = self
+            .active
+            .lock()
+            .map_err(|_| CoreError::Storage("active lock poisoned".to_string()))?
+            .clone();
         let mut storage = self
             .storage
             .lock()
@@ -100,23 +544,84 @@ impl DownloadEngine {
             .lock()
             .map_err(|_| CoreError::Storage("queue lock poisoned".to_string()))?;
 
-        for mut task in tasks {
-            let needs_queue = match task.status {
-                TaskStatus::Queued => true,
-                TaskStatus::Active => {
-                    task.status = TaskStatus::Queued;
-                    task.touch();
-                    storage.save_task(&task)?;
-                    true
+        let now = now_epoch();
+        for task in tasks {
+            match task.status {
+                TaskStatus::Queued if !queue.contains(&task.id) => {
+                    let full = storage.load_task(&task.id)?;
+                    if full.restart_at > now {
+                        report.cooling_down += 1;
+                        continue;
+                    }
+                    if dependency_state(storage.as_ref(), &full)? == DependencyState::Waiting {
+                        report.waiting += 1;
+                        continue;
+                    }
                 }
-                _ => false,
-            };
-            if needs_queue {
-                queue.push(QueueItem::new(task.id, task.priority));
-                queued += 1;
+                TaskStatus::Queued => {}
+                TaskStatus::Resolving | TaskStatus::Active if active.contains(&task.id) => {
+                    report.skipped_active += 1;
+                    continue;
+                }
+                TaskStatus::Resolving | TaskStatus::Active => {
+                    // No worker owns it: left behind by a crash or killed process.
+                    let mut full = storage.load_task(&task.id)?;
+                    if full.status != task.status {
+                        // Its worker finished after the listing was taken.
+                        continue;
+                    }
+                    full.status = TaskStatus::Queued;
+                    full.touch();
+                    storage.save_task(&full)?;
+                    report.recovered += 1;
+                }
+                _ => continue,
+            }
+            if queue.contains(&task.id) {
+                report.already_queued += 1;
+                continue;
             }
+            queue.push(QueueItem::new(task.id, task.priority));
+            report.queued += 1;
+        }
+        Ok(report)
+    }
+
+    /// Resolves `url` the way a download would (mirrors, hosting pages, redirects,
+    /// naming) without adding a task or writing anything. Blocks on the network.
+    pub fn probe(&self, url: &str) -> CoreResult<ProbeResult> {
+        self.prober().probe(url)
+    }
+
+    /// A detached copy of what `probe` needs, so a caller holding the engine behind a
+    /// lock can release it before going to the network.
+    pub fn prober(&self) -> Prober {
+        Prober {
+            config: self.config(),
+            net: Arc::clone(&self.net),
+            rename_hook: self.rename_hook.clone(),
         }
-        Ok(queued)
+    }
+
+    /// Tasks already stored for `url`, as their main URL or a mirror. Unfinished tasks
+    /// come first, then the most recently updated.
+    pub fn find_by_url(&self, url: &str) -> CoreResult<Vec<Task>> {
+        // Stored URLs have their credentials moved out; look them up the same way.
+        let mut probe = Task::new(url.trim().to_string(), String::new());
+        probe.extract_url_credentials();
+        let mut tasks = self
+            .storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+            .find_by_url(&probe.url)?;
+        tasks.sort_by_key(|task| {
+            let finished = matches!(
+                task.status,
+                TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Canceled
+            );
+            (finished, std::cmp::Reverse(task.updated_at))
+        });
+        Ok(tasks)
     }
 
     pub fn get_task(&self, id: &TaskId) -> CoreResult<Task> {
@@ -127,24 +632,51 @@ impl DownloadEngine {
         storage.load_task(id)
     }
 
+    /// Up to the last `history::SPEED_HISTORY_LEN` `(epoch milliseconds, downloaded bytes)` samples,
+    /// at most one per second, oldest first. Kept until the task is removed.
+    pub fn speed_history(&self, id: &TaskId) -> Vec<(u64, u64)> {
+        self.history.samples(id)
+    }
+
+    /// Time left for an active download at its smoothed speed, capped at
+    /// `history::MAX_ETA`. `None` when the size is unknown, the task isn't downloading
+    /// or nothing has arrived for a few seconds.
+    pub fn task_eta(&self, id: &TaskId) -> Option<Duration> {
+        let task = self.get_task(id).ok()?;
+        if task.status != TaskStatus::Active || task.total_bytes == 0 {
+            return None;
+        }
+        // Samples are newer than the progress last flushed to storage.
+        let samples = self.history.samples(id);
+        let downloaded = samples
+            .last()
+            .map_or(0, |(_, bytes)| *bytes)
+            .max(task.downloaded_bytes);
+        eta(
+            task.total_bytes.saturating_sub(downloaded),
+            self.history.speed(id)?,
+        )
+    }
+
     pub fn pause_task(&self, id: &TaskId) -> CoreResult<()> {
+        self.pause_with_reason(id, PauseReason::User)
+    }
+
+    fn pause_with_reason(&self, id: &TaskId, reason: PauseReason) -> CoreResult<()> {
         let mut storage = self
             .storage
             .lock()
             .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
         let mut task = storage.load_task(id)?;
-        if task.status != TaskStatus::Active {
-            return Err(CoreError::InvalidState(format!(
-                "cannot pause task in state {}",
-                task.status
-            )));
-        }
+        task.check_transition(&TaskStatus::Paused, "pause")?;
         task.status = TaskStatus::Paused;
+        task.pause_reason = Some(reason);
         task.touch();
         storage.save_task(&task)?;
         if let Ok(mut active) = self.active.lock() {
             active.remove(id);
         }
+        self.events.emit(EngineEvent::TaskPaused { id: *id });
         Ok(())
     }
 
@@ -154,34 +686,84 @@ impl DownloadEngine {
             .lock()
             .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
         let mut task = storage.load_task(id)?;
-        if task.status != TaskStatus::Paused && task.status != TaskStatus::Failed {
-            return Err(CoreError::InvalidState(format!(
-                "cannot resume task in state {}",
-                task.status
-            )));
-        }
+        task.check_transition(&TaskStatus::Queued, "resume")?;
         task.status = TaskStatus::Queued;
+        task.pause_reason = None;
+        task.error = None;
+        task.restart_count = 0;
+        task.restart_at = 0;
         task.touch();
         storage.save_task(&task)?;
         self.queue
             .lock()
             .map_err(|_| CoreError::Storage("queue lock poisoned".to_string()))?
             .push(QueueItem::new(task.id, task.priority));
+        self.events.emit(EngineEvent::TaskResumed { id: *id });
         Ok(())
     }
 
+    /// Re-queues a failed task like `resume_task`, first cutting its recorded progress
+    /// back to what its partial file actually holds.
+    pub fn retry_task(&self, id: &TaskId) -> CoreResult<()> {
+        {
+            let mut storage = self
+                .storage
+                .lock()
+                .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+            let mut task = storage.load_task(id)?;
+            if task.status != TaskStatus::Failed {
+                return Err(CoreError::InvalidState(format!(
+                    "cannot retry task in state {}",
+                    task.status
+                )));
+            }
+            let temp_dir = self.config().temp_dir;
+            let part = part_path(&task.dest_path, &task.id, temp_dir.as_deref());
+            // HLS output and partials from older versions are written in place.
+            let written = fs::metadata(&part)
+                .or_else(|_| fs::metadata(&task.dest_path))
+                .map(|meta| meta.len())
+                .unwrap_or(0);
+            let mut segments = storage.load_segments(id)?;
+            clamp_to_written(&mut segments, written);
+            storage.update_segments(id, &segments)?;
+            task.downloaded_bytes = segments.iter().map(|seg| seg.downloaded_bytes).sum();
+            storage.save_task(&task)?;
+        }
+        self.resume_task(id)
+    }
+
+    /// Resumes tasks paused by `shutdown`; tasks the user paused stay paused.
+    pub fn resume_all(&self) -> CoreResult<usize> {
+        let paused: Vec<TaskId> = self
+            .list_tasks()?
+            .into_iter()
+            .filter(|task| {
+                task.status == TaskStatus::Paused && task.pause_reason == Some(PauseReason::System)
+            })
+            .map(|task| task.id)
+            .collect();
+        for id in &paused {
+            self.resume_task(id)?;
+        }
+        Ok(paused.len())
+    }
+
     pub fn cancel_task(&self, id: &TaskId) -> CoreResult<()> {
         let mut storage = self
             .storage
             .lock()
             .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
         let mut task = storage.load_task(id)?;
+        task.check_transition(&TaskStatus::Canceled, "cancel")?;
         task.status = TaskStatus::Canceled;
         task.touch();
         storage.save_task(&task)?;
+        queue_dependents(storage.as_ref(), &self.queue, *id);
         if let Ok(mut active) = self.active.lock() {
             active.remove(id);
         }
+        self.events.emit(EngineEvent::TaskCanceled { id: *id });
         Ok(())
     }
 
@@ -199,10 +781,45 @@ impl DownloadEngine {
             .lock()
             .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
         storage.delete_task(id)?;
+        self.history.remove(id);
+        self.events.emit(EngineEvent::TaskRemoved { id: *id });
         Ok(())
     }
 
+    /// Removes every task matching `filter`. Running tasks (or ones another process has
+    /// marked active) are skipped and reported rather than failing the whole call.
+    pub fn remove_by(&self, filter: &TaskFilter) -> CoreResult<RemoveReport> {
+        let now = now_epoch();
+        let mut report = RemoveReport::default();
+        for task in self.list_tasks()? {
+            if !filter.matches(&task, now) {
+                continue;
+            }
+            let running = matches!(task.status, TaskStatus::Active | TaskStatus::Resolving)
+                || self
+                    .active
+                    .lock()
+                    .map_err(|_| CoreError::Storage("active lock poisoned".to_string()))?
+                    .contains(&task.id);
+            if running {
+                report.skipped.push(task.id);
+                continue;
+            }
+            self.storage
+                .lock()
+                .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+                .delete_task(&task.id)?;
+            self.history.remove(&task.id);
+            self.events.emit(EngineEvent::TaskRemoved { id: task.id });
+            report.removed += 1;
+        }
+        Ok(report)
+    }
+
     pub fn start_next(&self) -> CoreResult<Option<TaskId>> {
+        if self.stopping.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
         let active_count = self
             .active
             .lock()
@@ -229,10 +846,37 @@ impl DownloadEngine {
             Err(CoreError::NotFound(_)) => return self.start_next(),
             Err(e) => return Err(e),
         };
-        if task.status != TaskStatus::Queued {
+        // Queue entries can outlive a cancel or a start elsewhere.
+        if !task.status.can_transition_to(&TaskStatus::Resolving) {
             return Ok(None);
         }
-        task.status = TaskStatus::Active;
+        if task.restart_at > now_epoch() {
+            // Still cooling down; `enqueue_queued` brings it back once the wait is over.
+            drop(storage);
+            return self.start_next();
+        }
+        match dependency_state(storage.as_ref(), &task)? {
+            DependencyState::Ready => {}
+            DependencyState::Waiting => {
+                // Queued again by `queue_dependents` once the dependency finishes.
+                drop(storage);
+                return self.start_next();
+            }
+            DependencyState::Failed(error) => {
+                task.status = TaskStatus::Failed;
+                task.error = Some(error.clone());
+                task.touch();
+                storage.save_task(&task)?;
+                queue_dependents(storage.as_ref(), &self.queue, task.id);
+                drop(storage);
+                self.events.emit(EngineEvent::TaskFailed {
+                    id: task.id,
+                    error: Some(error),
+                });
+                return self.start_next();
+            }
+        }
+        task.status = TaskStatus::Resolving;
         task.error = None;
         task.touch();
         storage.save_task(&task)?;
@@ -240,33 +884,140 @@ impl DownloadEngine {
         if let Ok(mut active) = self.active.lock() {
             active.insert(task.id);
         }
+        self.events.emit(EngineEvent::TaskStarted { id: task.id });
 
         let task_id = task.id;
+        let list_id = pixeldrain_list_id(&task.url).filter(|_| !task.skip_resolution);
         let storage = Arc::clone(&self.storage);
         let net = Arc::clone(&self.net);
-        let config = self.config.clone();
+        let limits = self.limits.clone();
+        let config = self.config();
+        let queue = Arc::clone(&self.queue);
         let active = Arc::clone(&self.active);
+        let events = self.events.clone();
+        let history = self.history.clone();
+        let rename_hook = self.rename_hook.clone();
+        let verifiers = self.verifiers.clone();
         let handle = thread::spawn(move || {
-            let outcome = download_task(task_id, config, storage.clone(), net);
-            let (status, error) = match outcome {
-                Ok(status) => (status, None),
-                Err(err) => (TaskStatus::Failed, Some(err.to_string())),
+            let completed_dir = config.completed_dir.clone();
+            let run = TaskRun {
+                events: events.clone(),
+                history,
+                rename_hook,
+                fetched: AtomicBool::new(false),
+                transient: AtomicBool::new(false),
+            };
+            let outcome = match list_id {
+                Some(list_id) => expand_pixeldrain_list(
+                    task_id, &list_id, &config, &storage, net, &queue, &events,
+                ),
+                None => download_task(
+                    task_id,
+                    config.clone(),
+                    storage.clone(),
+                    Arc::clone(&net),
+                    limits.clone(),
+                    &run,
+                )
+                .or_else(|err| match err {
+                    // The oversized `.part` was cut back; start over once.
+                    CoreError::RangeNotSatisfiable(_) => {
+                        download_task(task_id, config.clone(), storage.clone(), net, limits, &run)
+                    }
+                    err => Err(err),
+                })
+                .and_then(|status| match status {
+                    TaskStatus::Completed => finish_download(
+                        task_id,
+                        completed_dir.as_deref(),
+                        config.temp_dir.as_deref(),
+                        &verifiers,
+                        &storage,
+                    ),
+                    status => Ok(status),
+                })
+                .and_then(|status| match status {
+                    TaskStatus::Completed => decompress_completed(task_id, &config, &storage),
+                    status => Ok(status),
+                }),
+            };
+            let (status, error, transient) = match outcome {
+                // A failed extraction is reported but leaves the download completed.
+                Ok(TaskStatus::Completed) => (
+                    TaskStatus::Completed,
+                    extract_completed(task_id, &config, &storage)
+                        .err()
+                        .map(|err| err.to_string()),
+                    false,
+                ),
+                Ok(status) => (status, None, run.transient.load(Ordering::Relaxed)),
+                Err(err) => (
+                    TaskStatus::Failed,
+                    Some(err.to_string()),
+                    err.is_retryable(),
+                ),
             };
 
+            let mut final_error = None;
+            let mut restart = None;
             if let Ok(mut storage) = storage.lock() {
                 if let Ok(mut task) = storage.load_task(&task_id) {
-                    task.status = status;
+                    task.status = status.clone();
                     if let Some(error) = error {
                         task.error = Some(error);
                     }
+                    // Only failures a later attempt may get past, e.g. an outage, restart.
+                    if status == TaskStatus::Failed
+                        && transient
+                        && task.restart_count < config.max_task_restarts
+                    {
+                        // The error is kept so the task shows why it is waiting.
+                        task.status = TaskStatus::Queued;
+                        task.restart_count += 1;
+                        task.restart_at = now_epoch() + config.task_restart_cooldown_secs;
+                        restart = Some(task.priority);
+                    }
                     task.touch();
                     let _ = storage.save_task(&task);
+                    if task.status == TaskStatus::Completed && run.fetched.load(Ordering::Relaxed) {
+                        let _ = storage
+                            .add_downloaded_bytes(now_epoch() / 86_400, task.downloaded_bytes);
+                    }
+                    if restart.is_none()
+                        && matches!(
+                            task.status,
+                            TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Canceled
+                        )
+                    {
+                        queue_dependents(storage.as_ref(), &queue, task_id);
+                    }
+                    final_error = task.error;
                 }
             }
 
             if let Ok(mut active) = active.lock() {
                 active.remove(&task_id);
             }
+
+            if let Some(priority) = restart {
+                if config.task_restart_cooldown_secs == 0 {
+                    if let Ok(mut queue) = queue.lock() {
+                        queue.push(QueueItem::new(task_id, priority));
+                    }
+                }
+                events.emit(EngineEvent::TaskResumed { id: task_id });
+                return;
+            }
+
+            // Paused and canceled were already announced by pause_task/cancel_task.
+            match status {
+                TaskStatus::Completed => events.emit(EngineEvent::TaskCompleted { id: task_id }),
+                TaskStatus::Failed => events.emit(EngineEvent::TaskFailed {
+                    id: task_id,
+                    error: final_error,
+                }),
+                _ => {}
+            }
         });
 
         self.handles
@@ -291,7 +1042,7 @@ impl DownloadEngine {
                 .lock()
                 .map_err(|_| CoreError::Storage("active lock poisoned".to_string()))?
                 .is_empty();
-            if queue_empty && active_empty {
+            if active_empty && (queue_empty || self.stopping.load(Ordering::SeqCst)) {
                 break;
             }
             thread::sleep(Duration::from_millis(200));
@@ -308,6 +1059,28 @@ impl DownloadEngine {
         }
     }
 
+    /// Pauses running tasks and waits for their workers to flush and exit. Queued tasks
+    /// stay queued and nothing else starts on this engine afterwards.
+    pub fn shutdown(&self) -> CoreResult<()> {
+        self.stopping.store(true, Ordering::SeqCst);
+        let active: Vec<TaskId> = self
+            .active
+            .lock()
+            .map_err(|_| CoreError::Storage("active lock poisoned".to_string()))?
+            .iter()
+            .copied()
+            .collect();
+        for id in &active {
+            match self.pause_with_reason(id, PauseReason::System) {
+                Ok(()) | Err(CoreError::InvalidState(_)) | Err(CoreError::NotFound(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        // Workers notice the paused status, flush progress and exit.
+        self.wait_all();
+        Ok(())
+    }
+
     fn reap_handles(&self) {
         if let Ok(mut handles) = self.handles.lock() {
             let mut index = 0usize;
@@ -323,15 +1096,29 @@ impl DownloadEngine {
     }
 }
 
+impl Drop for DownloadEngine {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
 struct ProgressTracker {
     task_id: TaskId,
     storage: Arc<Mutex<Box<dyn Storage>>>,
     segments: Arc<Mutex<Vec<Segment>>>,
     downloaded: AtomicU64,
+    total_bytes: u64,
     last_flush: AtomicU64,
+    started: Instant,
+    /// Milliseconds after `started` of the last flush.
+    last_flush_ms: AtomicU64,
     last_status_check: AtomicU64,
     flush_bytes: u64,
+    flush_interval_ms: u64,
     status_check_bytes: u64,
+    events: EventBus,
+    history: SpeedHistory,
+    sync_file: Option<fs::File>,
 }
 
 impl ProgressTracker {
@@ -340,26 +1127,47 @@ impl ProgressTracker {
         storage: Arc<Mutex<Box<dyn Storage>>>,
         segments: Arc<Mutex<Vec<Segment>>>,
         downloaded: u64,
-        flush_bytes: u64,
-        status_check_bytes: u64,
+        total_bytes: u64,
+        config: &EngineConfig,
+        events: EventBus,
     ) -> Self {
         Self {
             task_id,
             storage,
             segments,
             downloaded: AtomicU64::new(downloaded),
+            total_bytes,
             last_flush: AtomicU64::new(downloaded),
+            started: Instant::now(),
+            last_flush_ms: AtomicU64::new(0),
             last_status_check: AtomicU64::new(downloaded),
-            flush_bytes,
-            status_check_bytes,
+            flush_bytes: config.progress_flush_bytes,
+            flush_interval_ms: config.progress_flush_interval_ms,
+            status_check_bytes: config.status_check_bytes,
+            events,
+            history: SpeedHistory::default(),
+            sync_file: None,
         }
     }
 
+    /// Syncs `file` to disk before each flush, so recorded progress never gets ahead
+    /// of bytes that would survive a crash.
+    fn syncing(mut self, file: fs::File) -> Self {
+        self.sync_file = Some(file);
+        self
+    }
+
+    /// Samples the running total into the engine's `history`.
+    fn recording(mut self, history: SpeedHistory) -> Self {
+        self.history = history;
+        self
+    }
+
     fn add_bytes(&self, index: usize, bytes: u64) -> CoreResult<()> {
         if let Ok(mut segments) = self.segments.lock() {
             if let Some(segment) = segments.get_mut(index) {
                 let new_value = segment.downloaded_bytes.saturating_add(bytes);
-                if segment.size() > 0 {
+                if self.total_bytes > 0 && segment.size() > 0 {
                     segment.downloaded_bytes = new_value.min(segment.size());
                 } else {
                     segment.downloaded_bytes = new_value;
@@ -367,38 +1175,52 @@ impl ProgressTracker {
             }
         }
         let total = self.downloaded.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.history.record(self.task_id, total);
         self.maybe_flush(total)?;
         Ok(())
     }
 
     fn maybe_flush(&self, total: u64) -> CoreResult<()> {
         let last = self.last_flush.load(Ordering::Relaxed);
-        if total.saturating_sub(last) >= self.flush_bytes {
-            if self
+        let now_ms = self.started.elapsed().as_millis() as u64;
+        let overdue = self.flush_interval_ms > 0
+            && now_ms.saturating_sub(self.last_flush_ms.load(Ordering::Relaxed))
+                >= self.flush_interval_ms;
+        if total > last
+            && (total - last >= self.flush_bytes || overdue)
+            && self
                 .last_flush
                 .compare_exchange(last, total, Ordering::SeqCst, Ordering::Relaxed)
                 .is_ok()
-            {
-                self.flush(total)?;
-            }
+        {
+            self.last_flush_ms.store(now_ms, Ordering::Relaxed);
+            self.flush(total)?;
         }
         Ok(())
     }
 
     fn flush(&self, total: u64) -> CoreResult<()> {
+        // Snapshot before syncing: every byte counted in the snapshot was already written.
+        let segments = self
+            .segments
+            .lock()
+            .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?
+            .clone();
+        if let Some(file) = &self.sync_file {
+            file.sync_data()
+                .map_err(|err| CoreError::Io(err.to_string()))?;
+        }
         let mut storage = self
             .storage
             .lock()
             .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
-        let mut task = storage.load_task(&self.task_id)?;
-        task.downloaded_bytes = total;
-        task.touch();
-        storage.save_task(&task)?;
-        let segments = self
-            .segments
-            .lock()
-            .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?;
-        storage.save_segments(&self.task_id, &segments)?;
+        storage.update_progress(&self.task_id, total, self.total_bytes)?;
+        storage.update_segments(&self.task_id, &segments)?;
+        self.events.emit(EngineEvent::TaskProgress {
+            id: self.task_id,
+            downloaded_bytes: total,
+            total_bytes: Some(self.total_bytes).filter(|bytes| *bytes > 0),
+        });
         Ok(())
     }
 
@@ -434,16 +1256,105 @@ impl ProgressTracker {
     }
 }
 
-use crate::hls::HlsDownloader;
+use crate::hls::{HlsDownloader, HlsResume};
+
+#[derive(Debug, PartialEq, Eq)]
+enum DependencyState {
+    Ready,
+    Waiting,
+    /// The dependency failed or was canceled; the message becomes the task's error.
+    Failed(String),
+}
+
+/// A removed dependency counts as satisfied.
+fn dependency_state(storage: &dyn Storage, task: &Task) -> CoreResult<DependencyState> {
+    let Some(id) = task.depends_on else {
+        return Ok(DependencyState::Ready);
+    };
+    let dependency = match storage.load_task(&id) {
+        Ok(dependency) => dependency,
+        Err(CoreError::NotFound(_)) => return Ok(DependencyState::Ready),
+        Err(err) => return Err(err),
+    };
+    Ok(match dependency.status {
+        TaskStatus::Completed => DependencyState::Ready,
+        TaskStatus::Failed => DependencyState::Failed(format!("dependency {} failed", id)),
+        TaskStatus::Canceled => DependencyState::Failed(format!("dependency {} was canceled", id)),
+        _ => DependencyState::Waiting,
+    })
+}
+
+/// Rejects a `depends_on` that names an unknown task or leads back to `task`.
+fn check_dependency_chain(storage: &dyn Storage, task: &Task) -> CoreResult<()> {
+    let Some(first) = task.depends_on else {
+        return Ok(());
+    };
+    let mut seen = HashSet::from([task.id]);
+    let mut next = Some(first);
+    while let Some(id) = next {
+        if !seen.insert(id) {
+            return Err(CoreError::InvalidConfig(format!(
+                "dependency cycle: task {} depends on itself through {}",
+                task.id, first
+            )));
+        }
+        next = match storage.load_task(&id) {
+            Ok(dependency) => dependency.depends_on,
+            Err(CoreError::NotFound(_)) if id == first => {
+                return Err(CoreError::InvalidConfig(format!(
+                    "unknown dependency {}",
+                    id
+                )));
+            }
+            Err(CoreError::NotFound(_)) => None,
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(())
+}
+
+/// Pushes the `Queued` tasks waiting on `id` back onto the queue, where `start_next`
+/// either starts them or fails them along with their dependency.
+fn queue_dependents(storage: &dyn Storage, queue: &Mutex<TaskQueue>, id: TaskId) {
+    let Ok(tasks) = storage.list_tasks() else {
+        return;
+    };
+    let Ok(mut queue) = queue.lock() else {
+        return;
+    };
+    for task in tasks {
+        if task.depends_on == Some(id)
+            && task.status == TaskStatus::Queued
+            && !queue.contains(&task.id)
+        {
+            queue.push(QueueItem::new(task.id, task.priority));
+        }
+    }
+}
 
 // ... imports ...
 
+/// What one worker's download attempts share.
+struct TaskRun {
+    events: EventBus,
+    history: SpeedHistory,
+    rename_hook: Option<RenameHook>,
+    /// Set once the task goes active; stays false for completions that fetched nothing
+    /// (skipped files, `data:` URLs).
+    fetched: AtomicBool,
+    /// Set when segments failed only with errors another attempt may get past.
+    transient: AtomicBool,
+}
+
 fn download_task(
     task_id: TaskId,
     config: EngineConfig,
     storage: Arc<Mutex<Box<dyn Storage>>>,
     net: Arc<dyn NetClient>,
+    limits: SharedLimits,
+    run: &TaskRun,
 ) -> CoreResult<TaskStatus> {
+    let events = run.events.clone();
     let mut task = {
         let storage = storage
             .lock()
@@ -451,111 +1362,142 @@ fn download_task(
         storage.load_task(&task_id)?
     };
 
+    if task.torrent.is_some() {
+        return Err(CoreError::Unsupported(
+            "BitTorrent downloads are not supported yet".to_string(),
+        ));
+    }
+
+    if is_data_url(&task.url) {
+        return save_data_url(&mut task, &config, &storage);
+    }
+
     // --- HLS CHECK ---
     if task.url.contains(".m3u8") {
-        let stop_flag = Arc::new(AtomicU8::new(STOP_NONE));
-        let storage_clone = storage.clone();
+        if let Some(status) = mark_active(&storage, &mut task)? {
+            return Ok(status);
+        }
+        run.fetched.store(true, Ordering::Relaxed);
+        let resume = {
+            let storage = storage
+                .lock()
+                .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+            HlsResume::from_segments(&storage.load_segments(&task_id)?)
+        };
+        let stop_flag = Arc::new(AtomicU8::new(STOP_NONE));
+        let storage_clone = storage.clone();
         let tid = task_id;
-        
-        let status = HlsDownloader::download(
-            &mut task,
-            net,
-            stop_flag,
-            move |bytes| {
-                 if let Ok(mut s) = storage_clone.lock() {
-                     if let Ok(mut t) = s.load_task(&tid) {
-                         t.downloaded_bytes = bytes;
-                         // Hack: Update total bytes dynamically for HLS as we go
-                         if t.total_bytes < bytes { t.total_bytes = bytes; } 
-                         let _ = s.save_task(&t);
-                     }
-                 }
-            }
-        )?;
+        let hls_stop = Arc::clone(&stop_flag);
+        let hls_total = AtomicU64::new(task.total_bytes);
+
+        let status =
+            HlsDownloader::download(&mut task, net, stop_flag, &config, resume, move |point| {
+                let bytes = point.bytes;
+                // Hack: Update total bytes dynamically for HLS as we go
+                let total = hls_total.fetch_max(bytes, Ordering::Relaxed).max(bytes);
+                if let Ok(mut s) = storage_clone.lock() {
+                    if let Ok(t) = s.load_task(&tid) {
+                        match t.status {
+                            TaskStatus::Paused => hls_stop.store(STOP_PAUSED, Ordering::SeqCst),
+                            TaskStatus::Canceled => hls_stop.store(STOP_CANCELED, Ordering::SeqCst),
+                            _ => {}
+                        }
+                    }
+                    // Recorded as soon as it is on disk, so a crash resumes from here.
+                    let _ = s.save_segments(&tid, &[point.to_segment()]);
+                    if s.update_progress(&tid, bytes, total).is_ok() {
+                        events.emit(EngineEvent::TaskProgress {
+                            id: tid,
+                            downloaded_bytes: bytes,
+                            total_bytes: None,
+                        });
+                    }
+                }
+            })?;
         return Ok(status);
     }
     // --- END HLS CHECK ---
 
-    let url_candidates = resolve_url_candidates(task.url_candidates());
-    let mut total_bytes = task.total_bytes;
-    let mut accept_ranges = false;
-    let mut selected_url: Option<String> = None;
-    let mut selected_head = None;
-    let mut resolved_candidates = Vec::new();
-
-    for url in &url_candidates {
-        let mut head_req = DownloadRequest::new(url.clone(), config.user_agent.clone());
-        head_req.headers = task.headers.clone();
-        head_req.cookies = task.cookies.clone();
-        head_req.proxy = task.proxy_url.clone();
-        if let (Some(user), Some(pass)) = (task.auth_user.clone(), task.auth_pass.clone()) {
-            head_req.basic_auth = Some((user, pass));
-        }
-
-        if let Ok(resp) = net.head(&head_req) {
-            if resp.status_code >= 200 && resp.status_code < 400 {
-                if is_html_content_type(resp.content_type.as_deref()) {
-                    let provider = detect_provider(url);
-                    if provider == Provider::Mega {
-                        return Err(CoreError::Unsupported(
-                            "mega.nz requires Mega SDK integration".to_string(),
-                        ));
-                    }
-                    let resolved = resolve_html_download(net.as_ref(), &head_req)?;
-                    for resolved_url in resolved {
-                        resolved_candidates.push(resolved_url.clone());
-                        let mut resolved_req =
-                            DownloadRequest::new(resolved_url.clone(), config.user_agent.clone());
-                        resolved_req.headers = task.headers.clone();
-                        resolved_req.cookies = task.cookies.clone();
-                        resolved_req.proxy = task.proxy_url.clone();
-                        if let (Some(user), Some(pass)) =
-                            (task.auth_user.clone(), task.auth_pass.clone())
-                        {
-                            resolved_req.basic_auth = Some((user, pass));
-                        }
-
-                        if let Ok(resolved_resp) = net.head(&resolved_req) {
-                            if resolved_resp.status_code >= 200
-                                && resolved_resp.status_code < 400
-                                && !is_html_content_type(resolved_resp.content_type.as_deref())
-                            {
-                                selected_url = Some(resolved_url.clone());
-                                total_bytes = resolved_resp.total_bytes.unwrap_or(total_bytes);
-                                accept_ranges = resolved_resp.accept_ranges;
-                                selected_head = Some(resolved_resp);
-                                break;
-                            }
-                        }
-                    }
-                    if selected_url.is_some() {
-                        break;
-                    }
-                    if provider != Provider::Unknown {
-                        continue;
-                    }
-                    selected_url = Some(url.clone());
-                    total_bytes = resp.total_bytes.unwrap_or(total_bytes);
-                    accept_ranges = resp.accept_ranges;
-                    break;
-                } else {
-                    selected_url = Some(url.clone());
-                    total_bytes = resp.total_bytes.unwrap_or(total_bytes);
-                    accept_ranges = resp.accept_ranges;
-                    selected_head = Some(resp);
-                    break;
+    let url_candidates = if task.skip_resolution {
+        task.url_candidates()
+    } else {
+        resolve_url_candidates(task.url_candidates())
+    };
+    // Paused or canceled while probing; checked between candidates and while waiting.
+    let stopped_as = || -> CoreResult<Option<TaskStatus>> {
+        let status = storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+            .load_task(&task_id)?
+            .status;
+        Ok(matches!(status, TaskStatus::Paused | TaskStatus::Canceled).then_some(status))
+    };
+    let stopped = || stopped_as().ok().flatten().is_some();
+    let mut attempt = 0;
+    let resolution = loop {
+        match resolve_target(&task, &url_candidates, &config, &net, &stopped) {
+            Ok(resolution) => break resolution,
+            Err(err) => {
+                if let Some(status) = stopped_as()? {
+                    return Ok(status);
+                }
+                if !err.is_retryable() || attempt >= config.task_retry_count {
+                    return Err(err);
+                }
+                attempt += 1;
+                let backoff = Duration::from_secs(config.task_retry_backoff_secs);
+                let deadline = Instant::now() + backoff;
+                while Instant::now() < deadline && !stopped() {
+                    thread::sleep(STOP_POLL_INTERVAL.min(backoff));
+                }
+                if let Some(status) = stopped_as()? {
+                    return Ok(status);
                 }
             }
         }
-    }
-
-    let selected_url = selected_url.ok_or_else(|| {
-        CoreError::Network("no reachable download URL after resolution".to_string())
-    })?;
+    };
+    let Resolution {
+        selected_url,
+        total_bytes,
+        accept_ranges,
+        selected_head,
+        resolved_candidates,
+        referer,
+    } = resolution;
+    let last_modified = selected_head
+        .as_ref()
+        .and_then(|resp| resp.last_modified.clone())
+        .filter(|_| config.preserve_mtime);
     let content_disposition = selected_head
         .as_ref()
         .and_then(|resp| resp.content_disposition.as_deref());
-    let resolved_dest = resolve_dest_path(&task.dest_path, &selected_url, content_disposition);
+    // Name the file after where redirects ended up, not the link that was added.
+    let name_url = selected_head
+        .as_ref()
+        .and_then(|resp| resp.final_url.as_deref())
+        .unwrap_or(selected_url.as_str());
+    let hooked_dest = run.rename_hook.as_ref().and_then(|hook| {
+        let dir = dest_dir(&task.dest_path)?;
+        let suggested = suggested_filename(name_url, content_disposition);
+        let content_type = selected_head
+            .as_ref()
+            .and_then(|resp| resp.content_type.as_deref());
+        let name = hook(name_url, &suggested, content_type)?;
+        Some(
+            dir.join(sanitize_filename(&name))
+                .to_string_lossy()
+                .to_string(),
+        )
+    });
+    let resolved_dest = hooked_dest.unwrap_or_else(|| {
+        resolve_dest_path(
+            &task.dest_path,
+            name_url,
+            content_disposition,
+            config.output_template.as_ref(),
+            &task.id,
+        )
+    });
     if resolved_dest != task.dest_path {
         task.dest_path = resolved_dest;
     }
@@ -574,7 +1516,25 @@ fn download_task(
             download_urls.push(url);
         }
     }
+    let targets = DownloadTargets {
+        urls: download_urls,
+        expect_file: selected_head.is_some(),
+        referer: referer.filter(|_| config.auto_referer),
+    };
 
+    let stop_flag = Arc::new(AtomicU8::new(STOP_NONE));
+    let total_bytes = if total_bytes == 0 && accept_ranges && config.max_segments_per_task > 1 {
+        probe_range_total(&task, &targets, &config, &net, &limits, &stop_flag).unwrap_or(0)
+    } else {
+        total_bytes
+    };
+    // A `byte_limit` prefix is laid out and fetched like a file of that size.
+    let byte_limit = task.byte_limit.filter(|limit| *limit > 0);
+    task.partial = byte_limit.is_some_and(|limit| total_bytes > limit);
+    let total_bytes = match byte_limit {
+        Some(limit) if total_bytes > limit => limit,
+        _ => total_bytes,
+    };
     let use_ranges = accept_ranges && total_bytes > 0 && config.max_segments_per_task > 1;
     let mut segments = {
         let storage = storage
@@ -583,19 +1543,24 @@ fn download_task(
         storage.load_segments(&task_id)?
     };
 
+    // A stored layout that tiles the file is kept even if `max_segments_per_task` changed
+    // since, so resumed offsets line up with the bytes on disk. One that doesn't is
+    // discarded along with its progress rather than overlaid on a fresh layout.
+    let stored_tiles = tiles_file(&segments, total_bytes);
     let rebuild_segments = segments.is_empty()
         || (!use_ranges && segments.len() > 1)
-        || (total_bytes > 0
-            && segments
-                .iter()
-                .map(|seg| seg.range_end)
-                .max()
-                .map(|end| end != total_bytes.saturating_sub(1))
-                .unwrap_or(true));
+        || (total_bytes > 0 && !stored_tiles);
 
     if rebuild_segments {
+        let previous = std::mem::take(&mut segments);
         segments = if use_ranges {
-            build_segments(total_bytes, config.max_segments_per_task, config.min_segment_size_bytes)
+            build_segments(
+                total_bytes,
+                config.segment_strategy,
+                config.max_segments_per_task,
+                config.min_segment_size_bytes,
+                &config.concurrency_breakpoints,
+            )
         } else {
             if total_bytes > 0 {
                 vec![Segment::new(0, 0, total_bytes - 1)]
@@ -603,6 +1568,10 @@ fn download_task(
                 vec![Segment::new(0, 0, 0)]
             }
         };
+        // Only the layout changed, not the file: keep what is already on disk.
+        if stored_tiles {
+            carry_over_progress(&previous, &mut segments);
+        }
     }
 
     for segment in &mut segments {
@@ -615,176 +1584,1385 @@ fn download_task(
         }
     }
 
+    // Write into an id-suffixed `.part` file so tasks that resolve to the same name never
+    // share a partial file; it is moved to `dest_path` once the download completes.
+    let temp_dir = config.temp_dir.as_deref();
+    let mut part = part_path(&task.dest_path, &task.id, temp_dir);
+    if let Some(dir) = temp_dir {
+        fs::create_dir_all(dir).map_err(|err| CoreError::Io(err.to_string()))?;
+        // Started before `temp_dir` was set: carry the partial over.
+        let beside = part_path(&task.dest_path, &task.id, None);
+        if !Path::new(&part).exists() && Path::new(&beside).exists() {
+            move_file(Path::new(&beside), Path::new(&part))
+                .map_err(|err| CoreError::Io(err.to_string()))?;
+        }
+    }
+    let recorded_total: u64 = segments.iter().map(|seg| seg.downloaded_bytes).sum();
+    let fresh = recorded_total == 0 && !Path::new(&part).exists();
+    if fresh && config.on_existing != OnExisting::Overwrite {
+        if let Some(meta) = fs::metadata(&task.dest_path)
+            .ok()
+            .filter(|meta| meta.is_file())
+        {
+            if config.on_existing == OnExisting::Skip
+                && total_bytes > 0
+                && meta.len() == total_bytes
+            {
+                task.total_bytes = total_bytes;
+                task.downloaded_bytes = total_bytes;
+                task.error = None;
+                task.touch();
+                storage
+                    .lock()
+                    .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+                    .save_task(&task)?;
+                return Ok(TaskStatus::Completed);
+            }
+        }
+    }
+    if fresh {
+        task.dest_path = claim_dest_path(&storage, &task, config.on_existing)?
+            .to_string_lossy()
+            .to_string();
+        part = part_path(&task.dest_path, &task.id, temp_dir);
+    }
+    if recorded_total > 0 && !Path::new(&part).exists() && Path::new(&task.dest_path).exists() {
+        // Partial written in place by an older version.
+        move_file(Path::new(&task.dest_path), Path::new(&part))
+            .map_err(|err| CoreError::Io(err.to_string()))?;
+    }
+    let mut written = fs::metadata(&part).map(|meta| meta.len()).unwrap_or(0);
+    if total_bytes > 0 && written > total_bytes {
+        // The file shrank on the server, or the partial is corrupt; drop the excess.
+        OpenOptions::new()
+            .write(true)
+            .open(&part)
+            .and_then(|file| file.set_len(total_bytes))
+            .map_err(|err| CoreError::Io(err.to_string()))?;
+        written = total_bytes;
+    }
+    clamp_to_written(&mut segments, written);
+
     let downloaded_total: u64 = segments.iter().map(|seg| seg.downloaded_bytes).sum();
     task.total_bytes = total_bytes;
     task.downloaded_bytes = downloaded_total;
     task.error = None;
+    if let Some(status) = mark_active(&storage, &mut task)? {
+        return Ok(status);
+    }
+    run.fetched.store(true, Ordering::Relaxed);
+    {
+        let mut storage = storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+        storage.save_segments(&task.id, &segments)?;
+    }
+
+    if let Some(parent) = Path::new(&task.dest_path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)
+                .map_err(|err| CoreError::Io(err.to_string()))?;
+        }
+    }
+
+    task.dest_path = part;
+
+    // Without ranges the whole file comes back, so a `byte_limit` prefix is streamed
+    // and cut off at the limit instead.
+    if total_bytes == 0 || (byte_limit.is_some() && !accept_ranges) {
+        let status = download_unknown_length(&task, &targets, &config, net, &limits, storage, run)?;
+        if status == TaskStatus::Completed {
+            apply_last_modified(&task.dest_path, last_modified.as_deref());
+        }
+        return Ok(status);
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&task.dest_path)
+        .map_err(|err| CoreError::Io(err.to_string()))?;
+    preallocate_file(&file, total_bytes, config.preallocate)
+        .map_err(|err| CoreError::Io(err.to_string()))?;
+
+    let segments_shared = Arc::new(Mutex::new(segments));
+    let progress = Arc::new(
+        ProgressTracker::new(
+            task_id,
+            Arc::clone(&storage),
+            Arc::clone(&segments_shared),
+            downloaded_total,
+            total_bytes,
+            &config,
+            events,
+        )
+        .syncing(file)
+        .recording(run.history.clone()),
+    );
+
+    let throttle = Throttle::new(&limits.bandwidth, config.per_task_speed_limit_bytes_per_sec);
+
+    let errors: Arc<Mutex<Vec<CoreError>>> = Arc::new(Mutex::new(Vec::new()));
+    // Size from a `416`: the server's file ends before the stored layout does.
+    let shrunk: Arc<Mutex<Option<u64>>> = Arc::new(Mutex::new(None));
+    // Where segments found the file to end, if before `total_bytes`.
+    let short: Arc<Mutex<Option<u64>>> = Arc::new(Mutex::new(None));
+
+    let mut handles = Vec::new();
+    let mut segments_to_download = Vec::new();
+    if let Ok(mut segments) = segments_shared.lock() {
+        for (index, segment) in segments.iter_mut().enumerate() {
+            if segment.status != SegmentStatus::Completed {
+                segment.status = SegmentStatus::Active;
+                segments_to_download.push(index);
+            }
+        }
+    }
+
+    {
+        let mut storage = storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+        let segments = segments_shared
+            .lock()
+            .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?;
+        storage.update_segments(&task_id, &segments)?;
+    }
+
+    // Bytes each URL delivered in completed segments, to rank mirrors afterwards.
+    let served: Arc<Mutex<HashMap<String, u64>>> = Arc::new(Mutex::new(HashMap::new()));
+    for index in segments_to_download {
+        let net = Arc::clone(&net);
+        let hosts = limits.hosts.clone();
+        let storage = Arc::clone(&storage);
+        let segments = Arc::clone(&segments_shared);
+        let progress = Arc::clone(&progress);
+        let throttle = throttle.clone();
+        let stop_flag = Arc::clone(&stop_flag);
+        let errors = Arc::clone(&errors);
+        let shrunk = Arc::clone(&shrunk);
+        let short = Arc::clone(&short);
+        let served = Arc::clone(&served);
+        let task_clone = task.clone();
+        let targets = targets.clone();
+        let config = config.clone();
+
+        let handle = thread::spawn(move || {
+            let segment_len = segments
+                .lock()
+                .ok()
+                .and_then(|segments| segments.get(index).map(|segment| segment.size()))
+                .unwrap_or(0);
+            let result = download_segment(
+                index,
+                &task_clone,
+                &targets,
+                &config,
+                net,
+                hosts,
+                storage,
+                segments,
+                progress,
+                throttle,
+                stop_flag.clone(),
+            );
+            match result {
+                Ok(Some(url)) => {
+                    if let Ok(mut served) = served.lock() {
+                        *served.entry(url).or_default() += segment_len;
+                    }
+                }
+                Ok(None) => {}
+                // Not a failure yet: the other segments finish before it is judged.
+                Err(CoreError::ShortFile(end)) => {
+                    if let Ok(mut short) = short.lock() {
+                        *short = Some(short.map_or(end, |seen| seen.min(end)));
+                    }
+                }
+                Err(err) => {
+                    stop_flag.store(STOP_FAILED, Ordering::SeqCst);
+                    if let CoreError::RangeNotSatisfiable(size) = err {
+                        if let Ok(mut shrunk) = shrunk.lock() {
+                            *shrunk = Some(size);
+                        }
+                    }
+                    if let Ok(mut errors) = errors.lock() {
+                        errors.push(err);
+                    }
+                }
+            }
+        });
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    let total_downloaded = progress.downloaded.load(Ordering::Relaxed);
+    progress.flush(total_downloaded)?;
+
+    let shrunk = shrunk.lock().ok().and_then(|size| *size);
+    if let Some(size) = shrunk {
+        // Cut the `.part` back and forget the layout, so the next attempt starts clean
+        // from what the server has now instead of retrying ranges it can't serve.
+        OpenOptions::new()
+            .write(true)
+            .open(&task.dest_path)
+            .and_then(|file| file.set_len(size))
+            .map_err(|err| CoreError::Io(err.to_string()))?;
+        let mut storage = storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+        storage.save_segments(&task_id, &[])?;
+        storage.update_progress(&task_id, 0, size)?;
+        return Err(CoreError::RangeNotSatisfiable(size));
+    }
+
+    match stop_flag.load(Ordering::SeqCst) {
+        STOP_PAUSED => return Ok(TaskStatus::Paused),
+        STOP_CANCELED => return Ok(TaskStatus::Canceled),
+        STOP_FAILED => {
+            if let Ok(mut storage) = storage.lock() {
+                if let Ok(mut task) = storage.load_task(&task_id) {
+                    if let Ok(errors) = errors.lock() {
+                        if !errors.is_empty() {
+                            let messages: Vec<String> =
+                                errors.iter().map(|err| err.to_string()).collect();
+                            task.error = Some(messages.join("; "));
+                            run.transient.store(
+                                errors.iter().all(CoreError::is_retryable),
+                                Ordering::Relaxed,
+                            );
+                        }
+                    }
+                    let _ = storage.save_task(&task);
+                }
+            }
+            return Ok(TaskStatus::Failed);
+        }
+        _ => {}
+    }
+
+    let short = short.lock().ok().and_then(|end| *end);
+    if let Some(end) = short {
+        let segments = segments_shared
+            .lock()
+            .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?
+            .clone();
+        accept_short_file(&task, end, &segments, &storage)?;
+    }
+
+    let best = served.lock().ok().and_then(|served| {
+        served
+            .iter()
+            .max_by_key(|(_, bytes)| **bytes)
+            .map(|(url, _)| url.clone())
+    });
+    if let Some(url) = best {
+        record_resolved_url(&storage, &task_id, &url)?;
+    }
+    apply_last_modified(&task.dest_path, last_modified.as_deref());
+    Ok(TaskStatus::Completed)
+}
+
+/// Accepts a file that ends at `end`, before the size the server announced, if every
+/// byte up to `end` is on disk and nothing was served past it; otherwise fails with
+/// `ShortFile`. Trims the `.part` and records `end` as the real size.
+fn accept_short_file(
+    task: &Task,
+    end: u64,
+    segments: &[Segment],
+    storage: &Mutex<Box<dyn Storage>>,
+) -> CoreResult<()> {
+    let complete = segments.iter().all(|segment| {
+        let reached = segment.range_start + segment.downloaded_bytes;
+        if segment.range_start >= end {
+            segment.downloaded_bytes == 0
+        } else {
+            reached == (segment.range_end + 1).min(end)
+        }
+    });
+    if !complete {
+        return Err(CoreError::ShortFile(end));
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(&task.dest_path)
+        .and_then(|file| file.set_len(end))
+        .map_err(|err| CoreError::Io(err.to_string()))?;
+    let mut segment = Segment::new(0, 0, end.saturating_sub(1));
+    segment.downloaded_bytes = end;
+    segment.status = SegmentStatus::Completed;
+    let mut storage = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+    storage.update_progress(&task.id, end, end)?;
+    storage.save_segments(&task.id, &[segment])
+}
+
+/// Stores the URL that served most of a download as the task's `resolved_url`. A mirror
+/// also moves to the front of the task's stored mirrors, ahead of the others in the
+/// fallback order next time.
+fn record_resolved_url(
+    storage: &Mutex<Box<dyn Storage>>,
+    task_id: &TaskId,
+    url: &str,
+) -> CoreResult<()> {
+    let mut storage = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+    let mut task = storage.load_task(task_id)?;
+    task.resolved_url = Some(url.to_string());
+    if let Some(rank) = task.mirrors.iter().position(|mirror| mirror == url) {
+        let mirror = task.mirrors.remove(rank);
+        task.mirrors.insert(0, mirror);
+    }
+    storage.save_task(&task)
+}
+
+/// Saves `task` as `Active` now that resolution is over and bytes are about to flow.
+/// Returns the stored status instead if the task was paused or canceled meanwhile.
+fn mark_active(
+    storage: &Mutex<Box<dyn Storage>>,
+    task: &mut Task,
+) -> CoreResult<Option<TaskStatus>> {
+    let mut storage = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+    let current = storage.load_task(&task.id)?.status;
+    if matches!(current, TaskStatus::Paused | TaskStatus::Canceled) {
+        return Ok(Some(current));
+    }
+    task.status = TaskStatus::Active;
     task.touch();
+    storage.save_task(task)?;
+    Ok(None)
+}
+
+/// Writes a `data:` URL's payload to the task's `.part`, named after its media type when
+/// the destination is a directory.
+fn save_data_url(
+    task: &mut Task,
+    config: &EngineConfig,
+    storage: &Arc<Mutex<Box<dyn Storage>>>,
+) -> CoreResult<TaskStatus> {
+    let data = parse_data_url(&task.url)?;
+    let len = data.data.len() as u64;
+    let dest = match dest_dir(&task.dest_path) {
+        Some(dir) => dir.join(format!("download.{}", extension_for(&data.media_type))),
+        None => absolute_dest(task.dest_path.trim(), &default_download_dir()),
+    };
+    let mut dest = dest.to_string_lossy().to_string();
+    let skip = config.on_existing == OnExisting::Skip
+        && fs::metadata(&dest).is_ok_and(|meta| meta.is_file() && meta.len() == len);
+    if !skip && config.on_existing != OnExisting::Overwrite {
+        dest = reserve_path(Path::new(&dest), |_| false)?
+            .to_string_lossy()
+            .to_string();
+    }
+    task.dest_path = dest;
+    task.total_bytes = len;
+    task.downloaded_bytes = 0;
+    if let Some(status) = mark_active(storage, task)? {
+        return Ok(status);
+    }
+
+    if !skip {
+        let temp_dir = config.temp_dir.as_deref();
+        let part = part_path(&task.dest_path, &task.id, temp_dir);
+        if let Some(parent) = Path::new(&part).parent() {
+            fs::create_dir_all(parent).map_err(|err| CoreError::Io(err.to_string()))?;
+        }
+        if let Some(parent) = Path::new(&task.dest_path).parent() {
+            fs::create_dir_all(parent).map_err(|err| CoreError::Io(err.to_string()))?;
+        }
+        fs::write(&part, &data.data).map_err(|err| CoreError::Io(err.to_string()))?;
+    }
+    let mut segment = Segment::new(0, 0, len.saturating_sub(1));
+    segment.downloaded_bytes = len;
+    segment.status = SegmentStatus::Completed;
+    let mut storage = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+    storage.update_progress(&task.id, len, len)?;
+    storage.save_segments(&task.id, &[segment])?;
+    Ok(TaskStatus::Completed)
+}
+
+/// Resume never trusts recorded progress past the end of the partial file: a crash can
+/// leave the file shorter than the last flush claimed (lost writes, a truncated or
+/// deleted `.part`), and seeking past that would leave a hole in the output.
+pub(crate) fn clamp_to_written(segments: &mut [Segment], written_len: u64) {
+    for segment in segments {
+        let available = written_len.saturating_sub(segment.range_start);
+        if segment.downloaded_bytes > available {
+            segment.downloaded_bytes = available;
+            segment.status = SegmentStatus::Pending;
+            segment.sha256 = None;
+        }
+    }
+}
+
+/// Sets the file's mtime to a `Last-Modified` value; absent or unparseable values
+/// leave it untouched. The later `.part` rename keeps the timestamp.
+fn apply_last_modified(path: &str, last_modified: Option<&str>) {
+    let Some(time) = last_modified.and_then(|value| httpdate::parse_http_date(value.trim()).ok())
+    else {
+        return;
+    };
+    let _ = filetime::set_file_mtime(path, FileTime::from_system_time(time));
+}
+
+/// Replaces a Pixeldrain list task with one child task per file, queued right away so
+/// each file downloads and reports progress on its own. The list task itself completes
+/// once its children are queued; they are saved into the list task's `dest_path`,
+/// which is treated as a directory (empty means the default download directory).
+fn expand_pixeldrain_list(
+    task_id: TaskId,
+    list_id: &str,
+    config: &EngineConfig,
+    storage: &Arc<Mutex<Box<dyn Storage>>>,
+    net: Arc<dyn NetClient>,
+    queue: &Arc<Mutex<TaskQueue>>,
+    events: &EventBus,
+) -> CoreResult<TaskStatus> {
+    let parent = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+        .load_task(&task_id)?;
+    let req = task_request(parent.url.clone(), config, &parent);
+    let files = resolve_pixeldrain_list(net.as_ref(), &req, list_id)?;
+    if files.is_empty() {
+        return Err(CoreError::Network(format!(
+            "pixeldrain list {} is empty",
+            list_id
+        )));
+    }
+
+    let dir = match parent.dest_path.trim() {
+        "" => default_download_dir(),
+        dest => PathBuf::from(dest),
+    };
+    let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+    let paths = list_child_paths(&dir, &names, config.flat_output);
+    let mut children = Vec::with_capacity(files.len());
+    for (file, dest) in files.iter().zip(paths) {
+        let mut child = Task::new(file.download_url(), dest.to_string_lossy().to_string());
+        child.priority = parent.priority;
+        child.headers = parent.headers.clone();
+        child.cookies = parent.cookies.clone();
+        child.proxy_url = parent.proxy_url.clone();
+        child.auth_user = parent.auth_user.clone();
+        child.auth_pass = parent.auth_pass.clone();
+        child.user_agent = parent.user_agent.clone();
+        child.auto_extract = parent.auto_extract;
+        child.total_bytes = file.size;
+        children.push(child);
+    }
 
     {
         let mut storage = storage
             .lock()
             .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
-        storage.save_task(&task)?;
-        storage.save_segments(&task.id, &segments)?;
+        for child in &children {
+            storage.save_task(child)?;
+        }
+    }
+    let mut queue = queue
+        .lock()
+        .map_err(|_| CoreError::Storage("queue lock poisoned".to_string()))?;
+    for child in &children {
+        queue.push(QueueItem::new(child.id, child.priority));
+        events.emit(EngineEvent::TaskAdded { id: child.id });
+    }
+    Ok(TaskStatus::Completed)
+}
+
+/// Where each file of a multi-file download goes under `dir`. Names may carry the
+/// source's subdirectories (`a/b/file.bin`); every component is sanitized like a file
+/// name and empty, `.` and `..` ones are dropped, so nothing lands outside `dir`. `flat`
+/// keeps only the file name. Names that collide get numbered as `reserve_path` would.
+pub(crate) fn list_child_paths(dir: &Path, names: &[&str], flat: bool) -> Vec<PathBuf> {
+    let mut taken = HashSet::new();
+    names
+        .iter()
+        .map(|name| {
+            let mut components: Vec<&str> = name
+                .split(['/', '\\'])
+                .filter(|part| !matches!(part.trim(), "" | "." | ".."))
+                .collect();
+            if flat && components.len() > 1 {
+                components.drain(..components.len() - 1);
+            }
+            let mut path = dir.to_path_buf();
+            match components.split_last() {
+                Some((file, subdirs)) => {
+                    for subdir in subdirs {
+                        path.push(sanitize_filename(subdir));
+                    }
+                    path.push(sanitize_filename(file));
+                }
+                None => path.push(sanitize_filename("")),
+            }
+            let path = first_free_path(&path, |candidate| taken.contains(candidate));
+            taken.insert(path.clone());
+            path
+        })
+        .collect()
+}
+
+/// Beside `dest_path`, or in `temp_dir` named by task id alone, so a resume finds it
+/// whatever name the file resolves to.
+pub(crate) fn part_path(dest_path: &str, id: &TaskId, temp_dir: Option<&Path>) -> String {
+    match temp_dir {
+        Some(dir) => dir
+            .join(format!("{}.part", id))
+            .to_string_lossy()
+            .to_string(),
+        None => {
+            let path = Path::new(dest_path);
+            match path.file_name().map(|name| name.to_string_lossy()) {
+                // Shorten the name so `.<id>.part` still fits in one path component.
+                Some(name) if name.len() + PART_SUFFIX_BYTES > MAX_FILENAME_BYTES => {
+                    let name = truncate_filename(&name, MAX_FILENAME_BYTES - PART_SUFFIX_BYTES);
+                    path.with_file_name(format!("{}.{}.part", name, id))
+                        .to_string_lossy()
+                        .to_string()
+                }
+                _ => format!("{}.{}.part", dest_path, id),
+            }
+        }
+    }
+}
+
+/// `.<task id>.part`, appended to the destination's filename by `part_path`.
+const PART_SUFFIX_BYTES: usize = 1 + 36 + 5;
+
+/// Runs once every byte is on disk, in order: verify the `.part` against the task's
+/// checksum, move it to `dest_path`, then move it into `completed_dir`. A mismatch
+/// fails the task and leaves the `.part` in place (nothing appears at `dest_path`), with
+/// progress reset so a retry downloads it again.
+fn finish_download(
+    task_id: TaskId,
+    completed_dir: Option<&Path>,
+    temp_dir: Option<&Path>,
+    verifiers: &VerifierRegistry,
+    storage: &Arc<Mutex<Box<dyn Storage>>>,
+) -> CoreResult<TaskStatus> {
+    let task = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+        .load_task(&task_id)?;
+    let part = part_path(&task.dest_path, &task.id, temp_dir);
+    // HLS writes straight to `dest_path`.
+    let written = if Path::new(&part).exists() {
+        &part
+    } else {
+        &task.dest_path
+    };
+
+    // A prefix can't match the checksum of the whole file.
+    if let Some(checksum) = task.checksum.as_ref().filter(|_| !task.partial) {
+        if !verifiers.verify(written, checksum)? {
+            let mut storage = storage
+                .lock()
+                .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+            let mut task = storage.load_task(&task_id)?;
+            let mut segments = storage.load_segments(&task_id)?;
+            for segment in &mut segments {
+                segment.downloaded_bytes = 0;
+                segment.status = SegmentStatus::Pending;
+                segment.sha256 = None;
+            }
+            storage.update_segments(&task_id, &segments)?;
+            task.downloaded_bytes = 0;
+            task.error = Some(format!("checksum mismatch; download kept at {}", written));
+            storage.save_task(&task)?;
+            return Ok(TaskStatus::Failed);
+        }
+    }
+
+    if written == &part {
+        move_file(Path::new(&part), Path::new(&task.dest_path)).map_err(|err| {
+            CoreError::Io(format!(
+                "failed to rename {} to {}: {}",
+                part, task.dest_path, err
+            ))
+        })?;
+    }
+    if let Some(dir) = completed_dir {
+        move_completed(task_id, dir, storage)?;
+    }
+    Ok(TaskStatus::Completed)
+}
+
+fn move_completed(
+    task_id: TaskId,
+    dir: &Path,
+    storage: &Arc<Mutex<Box<dyn Storage>>>,
+) -> CoreResult<()> {
+    let mut storage = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+    let mut task = storage.load_task(&task_id)?;
+    let target = move_completed_file(Path::new(&task.dest_path), dir)?;
+    task.dest_path = target.to_string_lossy().to_string();
+    task.touch();
+    storage.save_task(&task)
+}
+
+/// A corrupt stream fails the task; the compressed download is left in place.
+fn decompress_completed(
+    task_id: TaskId,
+    config: &EngineConfig,
+    storage: &Arc<Mutex<Box<dyn Storage>>>,
+) -> CoreResult<TaskStatus> {
+    if !config.decompress_on_complete {
+        return Ok(TaskStatus::Completed);
+    }
+    let dest_path = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+        .load_task(&task_id)?
+        .dest_path;
+    let compressed = Path::new(&dest_path);
+    let Some(kind) = detect_compression(compressed) else {
+        return Ok(TaskStatus::Completed);
+    };
+    let target = decompress_file(compressed, kind)?;
+
+    let mut storage = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+    let mut task = storage.load_task(&task_id)?;
+    task.dest_path = target.to_string_lossy().to_string();
+    task.touch();
+    storage.save_task(&task)?;
+    Ok(TaskStatus::Completed)
+}
+
+fn extract_completed(
+    task_id: TaskId,
+    config: &EngineConfig,
+    storage: &Arc<Mutex<Box<dyn Storage>>>,
+) -> CoreResult<()> {
+    let task = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?
+        .load_task(&task_id)?;
+    if !config.auto_extract && !task.auto_extract {
+        return Ok(());
+    }
+    let archive = Path::new(&task.dest_path);
+    let Some(kind) = detect_archive(archive) else {
+        return Ok(());
+    };
+    extract_archive(archive, kind)?;
+    if config.delete_archive_after_extract {
+        fs::remove_file(archive).map_err(|err| {
+            CoreError::Extract(format!("failed to remove {}: {}", archive.display(), err))
+        })?;
+    }
+    Ok(())
+}
+
+/// Picks the final name of a task that hasn't written anything yet, skipping names
+/// other unfinished tasks already hold so concurrent downloads into one folder never
+/// finish onto each other. The choice is saved before the storage lock is released.
+fn claim_dest_path(
+    storage: &Mutex<Box<dyn Storage>>,
+    task: &Task,
+    on_existing: OnExisting,
+) -> CoreResult<PathBuf> {
+    let mut storage = storage
+        .lock()
+        .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+    let held: HashSet<PathBuf> = storage
+        .list_task_summaries()?
+        .into_iter()
+        .filter(|other| other.id != task.id)
+        .filter(|other| {
+            matches!(
+                other.status,
+                TaskStatus::Resolving | TaskStatus::Active | TaskStatus::Paused
+            )
+        })
+        .map(|other| PathBuf::from(other.dest_path))
+        .collect();
+    let path = Path::new(&task.dest_path);
+    let claimed = match on_existing {
+        OnExisting::Overwrite => first_free_path(path, |candidate| held.contains(candidate)),
+        _ => reserve_path(path, |candidate| held.contains(candidate))?,
+    };
+    let mut stored = storage.load_task(&task.id)?;
+    stored.dest_path = claimed.to_string_lossy().to_string();
+    storage.save_task(&stored)?;
+    Ok(claimed)
+}
+
+/// Claims `path`, or else the first free `name (n).ext` beside it that isn't `taken`,
+/// by creating it empty. Tasks finishing into one folder thus never pick the same name;
+/// the finished file is renamed onto the placeholder.
+pub(crate) fn reserve_path(path: &Path, taken: impl Fn(&Path) -> bool) -> CoreResult<PathBuf> {
+    let describe = |candidate: &Path, err: std::io::Error| {
+        CoreError::Io(format!("{}: {}", candidate.display(), err))
+    };
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(|err| describe(parent, err))?;
+    }
+    for candidate in numbered_paths(path).filter(|candidate| !taken(candidate)) {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(_) => return Ok(candidate),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(describe(&candidate, err)),
+        }
+    }
+    Err(CoreError::Io(format!(
+        "no free name beside {}",
+        path.display()
+    )))
+}
+
+/// `path` itself unless `taken`, else the first free `name (n).ext` beside it.
+fn first_free_path(path: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    numbered_paths(path)
+        .find(|candidate| !taken(candidate))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// `path`, then `name (1).ext`, `name (2).ext`, … beside it.
+fn numbered_paths(path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    std::iter::once(path.to_path_buf())
+        .chain((1u32..).map(move |n| path.with_file_name(format!("{} ({}){}", stem, n, ext))))
+}
+
+pub(crate) fn move_completed_file(src: &Path, dir: &Path) -> CoreResult<PathBuf> {
+    let describe = |err: std::io::Error| {
+        CoreError::Io(format!(
+            "failed to move {} to {}: {}",
+            src.display(),
+            dir.display(),
+            err
+        ))
+    };
+    let filename = src
+        .file_name()
+        .ok_or_else(|| CoreError::Io(format!("no filename in {}", src.display())))?;
+    let target = dir.join(filename);
+    if target == src {
+        return Ok(target);
+    }
+    fs::create_dir_all(dir).map_err(describe)?;
+    move_file(src, &target).map_err(describe)?;
+    Ok(target)
+}
+
+/// Renames `src` to `target`, copying when they are on different filesystems.
+pub(crate) fn move_file(src: &Path, target: &Path) -> std::io::Result<()> {
+    match fs::rename(src, target) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            // rename(2) cannot cross mounts: copy next to the target, then swap in.
+            let mut staging = target.as_os_str().to_os_string();
+            staging.push(".moving");
+            let staging = PathBuf::from(staging);
+            if let Err(err) = fs::copy(src, &staging) {
+                let _ = fs::remove_file(&staging);
+                return Err(err);
+            }
+            // `fs::copy` does not carry the modification time over.
+            if let Ok(meta) = fs::metadata(src) {
+                let _ = filetime::set_file_mtime(
+                    &staging,
+                    FileTime::from_last_modification_time(&meta),
+                );
+            }
+            fs::rename(&staging, target)?;
+            fs::remove_file(src)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// A request for `url` on `task`'s behalf. Headers apply in order of precedence: the
+/// task's own, then `EngineConfig::default_headers`, then the engine's built-ins.
+pub(crate) fn task_request(url: String, config: &EngineConfig, task: &Task) -> DownloadRequest {
+    let mut req = DownloadRequest::for_task(url, config.user_agent.clone(), task);
+    req.default_headers = config.default_headers.clone();
+    // A stalled connection fails the read, and the retry loop resumes from the offset.
+    req.read_timeout = Some(config.stall_timeout_secs)
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs);
+    req
+}
+
+/// Asks for uncompressed bytes so ranges and `Content-Length` line up; a task's own
+/// `Accept-Encoding` header is left alone.
+fn prefer_identity_encoding(req: &mut DownloadRequest, mode: IdentityEncoding) {
+    let ranged = req.range.is_some() || req.range_from.is_some();
+    let wanted = match mode {
+        IdentityEncoding::Never => false,
+        IdentityEncoding::Ranged => ranged,
+        IdentityEncoding::Always => true,
+    };
+    if wanted && !req.has_header("accept-encoding") {
+        req.headers
+            .insert("Accept-Encoding".to_string(), "identity".to_string());
+    }
+}
+
+/// URLs a task downloads from, best first.
+#[derive(Clone)]
+struct DownloadTargets {
+    urls: Vec<String>,
+    /// The selected URL was probed as a file, so an HTML body means an expired or
+    /// rate-limited link rather than the download itself.
+    expect_file: bool,
+    /// The page the selected URL was scraped from, sent as `Referer`.
+    referer: Option<String>,
+}
+
+impl DownloadTargets {
+    fn add_referer(&self, req: &mut DownloadRequest) {
+        if let Some(page) = &self.referer {
+            set_page_referer(req, page);
+        }
+    }
+
+    fn unexpected_html(&self, url: &str, response: &Response) -> Option<CoreError> {
+        if is_cloudflare_challenge(response.headers(), None) {
+            return Some(cloudflare_challenge_error());
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        let html = response.status().is_success() && is_html_content_type(content_type);
+        if !self.expect_file || !html {
+            return None;
+        }
+        Some(CoreError::Network(format!(
+            "got HTML, expected file from {} (link expired or rate limited?)",
+            url
+        )))
+    }
+}
+
+/// Single-stream path for servers that send no Content-Length or don't accept ranges.
+/// Resumes by appending from the on-disk length with an open-ended `Range` request, and
+/// stops reading at the task's `byte_limit`.
+fn download_unknown_length(
+    task: &Task,
+    targets: &DownloadTargets,
+    config: &EngineConfig,
+    net: Arc<dyn NetClient>,
+    limits: &SharedLimits,
+    storage: Arc<Mutex<Box<dyn Storage>>>,
+    run: &TaskRun,
+) -> CoreResult<TaskStatus> {
+    let throttle = Throttle::new(&limits.bandwidth, config.per_task_speed_limit_bytes_per_sec);
+    let stop_flag = Arc::new(AtomicU8::new(STOP_NONE));
+    let backoff = Duration::from_secs(config.retry_backoff_secs);
+    let mut last_error: Option<CoreError> = None;
+    let mut retry_wait: Option<Duration> = None;
+    let mut rotation = UserAgentRotation::new(config);
+    let byte_limit = task.byte_limit.filter(|limit| *limit > 0);
+    // URLs that answered with a status retrying won't change.
+    let mut permanent = vec![false; targets.urls.len()];
+
+    for attempt in 0..=config.retry_count {
+        for (index, url) in targets.urls.iter().enumerate() {
+            if permanent[index] {
+                continue;
+            }
+            let offset = fs::metadata(&task.dest_path)
+                .map(|meta| meta.len())
+                .unwrap_or(0);
+            if byte_limit.is_some_and(|limit| offset >= limit) {
+                return finish_unknown_length(task, &storage);
+            }
+
+            let mut req = task_request(url.clone(), config, task);
+            targets.add_referer(&mut req);
+            if offset > 0 {
+                req.range_from = Some(offset);
+            }
+            prefer_identity_encoding(&mut req, config.identity_encoding);
+            rotation.apply(&mut req);
+
+            let Some(_permit) = limits
+                .hosts
+                .acquire(url, || stop_flag.load(Ordering::SeqCst) != STOP_NONE)
+            else {
+                return Ok(match stop_flag.load(Ordering::SeqCst) {
+                    STOP_CANCELED => TaskStatus::Canceled,
+                    _ => TaskStatus::Paused,
+                });
+            };
+            let response = match net.get_stream(&req) {
+                Ok(resp) => resp,
+                Err(err) => {
+                    last_error = Some(err);
+                    continue;
+                }
+            };
+            if let Some(err) = targets.unexpected_html(url, &response) {
+                last_error = Some(err);
+                continue;
+            }
+
+            let status = response.status();
+            let start = match status.as_u16() {
+                206 => {
+                    if let Err(err) = check_content_range(&response, offset) {
+                        last_error = Some(err);
+                        continue;
+                    }
+                    offset
+                }
+                416 if offset > 0 => {
+                    let size = response
+                        .headers()
+                        .get(CONTENT_RANGE)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(unsatisfied_range_total);
+                    match size {
+                        // Our bytes reach past the server's file: it shrank, or the
+                        // partial is corrupt. Start the stream over.
+                        Some(size) if size < offset => {
+                            fs::File::create(&task.dest_path)
+                                .map_err(|err| CoreError::Io(err.to_string()))?;
+                            return download_unknown_length(
+                                task, targets, config, net, limits, storage, run,
+                            );
+                        }
+                        // Nothing left past the bytes we already have.
+                        _ => return finish_unknown_length(task, &storage),
+                    }
+                }
+                _ if status.is_success() => {
+                    // Server ignored the range; start the stream over.
+                    OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .truncate(true)
+                        .open(&task.dest_path)
+                        .map_err(|err| CoreError::Io(err.to_string()))?;
+                    0
+                }
+                code => {
+                    retry_wait = retry_after(code, response.headers()).or(retry_wait);
+                    rotation.note_status(code);
+                    let err = CoreError::HttpStatus(code);
+                    permanent[index] = !err.is_retryable() && !rotation.has_alternative(code);
+                    last_error = Some(err);
+                    continue;
+                }
+            };
+
+            let mut segment = Segment::new(0, 0, task.total_bytes.saturating_sub(1));
+            segment.downloaded_bytes = start;
+            segment.status = SegmentStatus::Active;
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(&task.dest_path)
+                .map_err(|err| CoreError::Io(err.to_string()))?;
+            let progress = Arc::new(
+                ProgressTracker::new(
+                    task.id,
+                    Arc::clone(&storage),
+                    Arc::new(Mutex::new(vec![segment])),
+                    start,
+                    task.total_bytes,
+                    config,
+                    run.events.clone(),
+                )
+                .syncing(file)
+                .recording(run.history.clone()),
+            );
+
+            let remaining = byte_limit.map_or(u64::MAX, |limit| limit - start);
+            let result = stream_to_file(
+                response.take(remaining),
+                &task.dest_path,
+                start,
+                Arc::clone(&progress),
+                0,
+                throttle.clone(),
+                Arc::clone(&stop_flag),
+            );
+            progress.flush(progress.downloaded.load(Ordering::Relaxed))?;
+
+            match stop_flag.load(Ordering::SeqCst) {
+                STOP_PAUSED => return Ok(TaskStatus::Paused),
+                STOP_CANCELED => return Ok(TaskStatus::Canceled),
+                _ => {}
+            }
+            if let Err(err) = result {
+                last_error = Some(err);
+                continue;
+            }
+            record_resolved_url(&storage, &task.id, url)?;
+            return finish_unknown_length(task, &storage);
+        }
+
+        if permanent.iter().all(|dead| *dead) {
+            break;
+        }
+        if attempt < config.retry_count {
+            thread::sleep(retry_wait.take().unwrap_or(backoff));
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| CoreError::Network("failed to download stream".to_string())))
+}
+
+pub(crate) fn preallocate_file(
+    file: &fs::File,
+    len: u64,
+    mode: Preallocate,
+) -> std::io::Result<()> {
+    match mode {
+        Preallocate::Always => file.set_len(len),
+        Preallocate::Never => Ok(()),
+        Preallocate::Sparse => {
+            reserve_space(file, len);
+            Ok(())
+        }
     }
+}
 
-    if let Some(parent) = Path::new(&task.dest_path).parent() {
-        if !parent.as_os_str().is_empty() {
-            fs::create_dir_all(parent)
-                .map_err(|err| CoreError::Io(err.to_string()))?;
-        }
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reserve_space(file: &fs::File, len: u64) {
+    use std::os::unix::io::AsRawFd;
+
+    // KEEP_SIZE reserves blocks without zero-filling or changing the visible length.
+    // Failure (EOPNOTSUPP on exFAT/FAT) is fine: segments extend the file on demand.
+    let Ok(len) = libc::off_t::try_from(len) else {
+        return;
+    };
+    unsafe {
+        libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len);
     }
+}
 
-    if total_bytes > 0 {
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&task.dest_path)
-            .map_err(|err| CoreError::Io(err.to_string()))?;
-        file.set_len(total_bytes)
-            .map_err(|err| CoreError::Io(err.to_string()))?;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn reserve_space(_file: &fs::File, _len: u64) {}
+
+struct Resolution {
+    selected_url: String,
+    total_bytes: u64,
+    accept_ranges: bool,
+    selected_head: Option<DownloadResponse>,
+    resolved_candidates: Vec<String>,
+    /// The page `selected_url` was scraped from.
+    referer: Option<String>,
+}
+
+/// Sends `page` as the `Referer` of a link scraped from it, unless the task set one.
+fn set_page_referer(req: &mut DownloadRequest, page: &str) {
+    if !req.has_header("referer") {
+        req.headers.insert("Referer".to_string(), page.to_string());
     }
+}
 
-    let segments_shared = Arc::new(Mutex::new(segments));
-    let progress = Arc::new(ProgressTracker::new(
-        task_id,
-        Arc::clone(&storage),
-        Arc::clone(&segments_shared),
-        downloaded_total,
-        config.progress_flush_bytes,
-        config.status_check_bytes,
-    ));
-
-    let throttle = Throttle::new(
-        config.global_speed_limit_bytes_per_sec,
-        config.per_task_speed_limit_bytes_per_sec,
-    );
+const MAX_PARALLEL_PROBES: usize = 4;
 
-    let stop_flag = Arc::new(AtomicU8::new(STOP_NONE));
-    let errors: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+/// How often resolution checks whether its task was paused or canceled.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
-    let mut handles = Vec::new();
-    let mut segments_to_download = Vec::new();
-    if let Ok(mut segments) = segments_shared.lock() {
-        for (index, segment) in segments.iter_mut().enumerate() {
-            if segment.status != SegmentStatus::Completed {
-                segment.status = SegmentStatus::Active;
-                segments_to_download.push(index);
-            }
-        }
+fn is_good_status(resp: &DownloadResponse) -> bool {
+    resp.status_code >= 200 && resp.status_code < 400
+}
+
+/// The status every candidate failed with, when retrying would not change it.
+fn permanent_status(responses: &[Option<DownloadResponse>]) -> Option<u16> {
+    let mut statuses = responses
+        .iter()
+        .map(|resp| resp.as_ref().map(|resp| resp.status_code));
+    let first = statuses.next()??;
+    let refused = |status: u16| status >= 400 && !CoreError::HttpStatus(status).is_retryable();
+    (refused(first) && statuses.all(|status| status.is_some_and(refused))).then_some(first)
+}
+
+fn is_direct_file(resp: &DownloadResponse) -> bool {
+    is_good_status(resp) && !is_html_content_type(resp.content_type.as_deref())
+}
+
+/// Picks the URL to download from. Candidates are probed in parallel and the first
+/// direct file to answer wins; hosting pages are only resolved when no candidate
+/// serves the file directly. Gives up with `InvalidState` as soon as `stopped` says so.
+fn resolve_target(
+    task: &Task,
+    url_candidates: &[String],
+    config: &EngineConfig,
+    net: &Arc<dyn NetClient>,
+    stopped: &dyn Fn() -> bool,
+) -> CoreResult<Resolution> {
+    let interrupted = || CoreError::InvalidState("resolution interrupted".to_string());
+    let requests: Vec<DownloadRequest> = url_candidates
+        .iter()
+        .map(|url| {
+            let mut req = task_request(url.clone(), config, task);
+            prefer_identity_encoding(&mut req, config.identity_encoding);
+            req
+        })
+        .collect();
+    let skip_head = config.skip_head || task.skip_head;
+    let (direct, mut responses) = probe_candidates(net, requests.clone(), skip_head, stopped);
+    if stopped() {
+        return Err(interrupted());
     }
 
-    {
-        let mut storage = storage
-            .lock()
-            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
-        let segments = segments_shared
-            .lock()
-            .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?;
-        storage.save_segments(&task_id, &segments)?;
+    if let Some(index) = direct {
+        let resp = responses[index]
+            .take()
+            .expect("direct probe has a response");
+        return Ok(Resolution {
+            selected_url: url_candidates[index].clone(),
+            total_bytes: resp.total_bytes.unwrap_or(task.total_bytes),
+            accept_ranges: resp.accept_ranges,
+            selected_head: Some(resp),
+            resolved_candidates: Vec::new(),
+            referer: None,
+        });
     }
 
-    for index in segments_to_download {
-        let net = Arc::clone(&net);
-        let storage = Arc::clone(&storage);
-        let segments = Arc::clone(&segments_shared);
-        let progress = Arc::clone(&progress);
-        let throttle = throttle.clone();
-        let stop_flag = Arc::clone(&stop_flag);
-        let errors = Arc::clone(&errors);
-        let task_clone = task.clone();
-        let url_candidates = download_urls.clone();
-        let config = config.clone();
+    // Every candidate refused with a status retrying won't change, e.g. a 404.
+    let refused = permanent_status(&responses).map(CoreError::HttpStatus);
+    // `skip_resolution`: the first URL that answers is downloaded, whatever it serves.
+    if task.skip_resolution {
+        let answered = url_candidates
+            .iter()
+            .zip(responses)
+            .find_map(|(url, resp)| resp.filter(is_good_status).map(|resp| (url, resp)));
+        return match answered {
+            Some((url, resp)) => Ok(Resolution {
+                selected_url: url.clone(),
+                total_bytes: resp.total_bytes.unwrap_or(task.total_bytes),
+                accept_ranges: resp.accept_ranges,
+                selected_head: None,
+                resolved_candidates: Vec::new(),
+                referer: None,
+            }),
+            None => Err(refused
+                .unwrap_or_else(|| CoreError::Network("no reachable download URL".to_string()))),
+        };
+    }
 
-        let handle = thread::spawn(move || {
-            let result = download_segment(
-                index,
-                &task_clone,
-                &url_candidates,
-                &config,
-                net,
-                storage,
-                segments,
-                progress,
-                throttle,
-                stop_flag.clone(),
-            );
-            if let Err(err) = result {
-                stop_flag.store(STOP_FAILED, Ordering::SeqCst);
-                if let Ok(mut errors) = errors.lock() {
-                    errors.push(err.to_string());
+    let mut resolved_candidates = Vec::new();
+    let mut challenged = false;
+    for ((url, head_req), resp) in url_candidates.iter().zip(&requests).zip(responses) {
+        if resp.as_ref().is_some_and(|resp| resp.cloudflare_challenge) {
+            challenged = true;
+            continue;
+        }
+        let Some(resp) = resp.filter(is_good_status) else {
+            continue;
+        };
+        if stopped() {
+            return Err(interrupted());
+        }
+        let provider = detect_provider(url);
+        if provider == Provider::Mega {
+            return Err(CoreError::Unsupported(
+                "mega.nz requires Mega SDK integration".to_string(),
+            ));
+        }
+        let resolved = resolve_html_download(net.as_ref(), head_req)?;
+        for resolved_url in resolved {
+            if stopped() {
+                return Err(interrupted());
+            }
+            resolved_candidates.push(resolved_url.clone());
+            let mut resolved_req = task_request(resolved_url.clone(), config, task);
+            prefer_identity_encoding(&mut resolved_req, config.identity_encoding);
+            if config.auto_referer {
+                set_page_referer(&mut resolved_req, url);
+            }
+            if let Ok(resolved_resp) = probe(net.as_ref(), &resolved_req, skip_head) {
+                if is_direct_file(&resolved_resp) {
+                    return Ok(Resolution {
+                        selected_url: resolved_url,
+                        total_bytes: resolved_resp.total_bytes.unwrap_or(task.total_bytes),
+                        accept_ranges: resolved_resp.accept_ranges,
+                        selected_head: Some(resolved_resp),
+                        resolved_candidates,
+                        referer: Some(url.clone()),
+                    });
                 }
             }
+        }
+        if provider != Provider::Unknown {
+            continue;
+        }
+        return Ok(Resolution {
+            selected_url: url.clone(),
+            total_bytes: resp.total_bytes.unwrap_or(task.total_bytes),
+            accept_ranges: resp.accept_ranges,
+            selected_head: None,
+            resolved_candidates,
+            referer: None,
         });
-        handles.push(handle);
     }
 
-    for handle in handles {
-        let _ = handle.join();
+    if challenged {
+        return Err(cloudflare_challenge_error());
     }
+    Err(refused.unwrap_or_else(|| {
+        CoreError::Network("no reachable download URL after resolution".to_string())
+    }))
+}
 
-    let total_downloaded = progress.downloaded.load(Ordering::Relaxed);
-    progress.flush(total_downloaded)?;
+/// HEADs `req`, or probes it with a ranged GET when `skip_head` is set or the server
+/// does not implement HEAD. POST requests are always probed with the POST itself.
+fn probe(
+    net: &dyn NetClient,
+    req: &DownloadRequest,
+    skip_head: bool,
+) -> CoreResult<DownloadResponse> {
+    if skip_head || req.method == HttpMethod::Post {
+        return probe_with_get(net, req);
+    }
+    match net.head(req) {
+        Ok(resp) if matches!(resp.status_code, 405 | 501) => probe_with_get(net, req),
+        resp => resp,
+    }
+}
 
-    match stop_flag.load(Ordering::SeqCst) {
-        STOP_PAUSED => return Ok(TaskStatus::Paused),
-        STOP_CANCELED => return Ok(TaskStatus::Canceled),
-        STOP_FAILED => {
-            if let Ok(mut storage) = storage.lock() {
-                if let Ok(mut task) = storage.load_task(&task_id) {
-                    if let Ok(errors) = errors.lock() {
-                        if !errors.is_empty() {
-                            task.error = Some(errors.join("; "));
-                        }
-                    }
-                    let _ = storage.save_task(&task);
-                }
-            }
-            return Ok(TaskStatus::Failed);
-        }
-        _ => {}
+/// Probes `requests` with at most `MAX_PARALLEL_PROBES` in flight. Stops as soon as a
+/// direct file answers, preferring the lowest index among responses received so far, or
+/// once `stopped` returns true; probes still in flight are abandoned and unstarted ones
+/// skipped.
+fn probe_candidates(
+    net: &Arc<dyn NetClient>,
+    requests: Vec<DownloadRequest>,
+    skip_head: bool,
+    stopped: &dyn Fn() -> bool,
+) -> (Option<usize>, Vec<Option<DownloadResponse>>) {
+    let count = requests.len();
+    let mut responses: Vec<Option<DownloadResponse>> = vec![None; count];
+    if count == 0 {
+        return (None, responses);
     }
 
-    if total_bytes == 0 {
-        if let Ok(meta) = fs::metadata(&task.dest_path) {
-            total_bytes = meta.len();
-            if let Ok(mut storage) = storage.lock() {
-                if let Ok(mut task) = storage.load_task(&task_id) {
-                    task.total_bytes = total_bytes;
-                    let _ = storage.save_task(&task);
+    let requests = Arc::new(requests);
+    let next = Arc::new(AtomicUsize::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+    for _ in 0..count.min(MAX_PARALLEL_PROBES) {
+        let (net, requests, next, stop, tx) = (
+            Arc::clone(net),
+            Arc::clone(&requests),
+            Arc::clone(&next),
+            Arc::clone(&stop),
+            tx.clone(),
+        );
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(req) = requests.get(index) else {
+                    break;
+                };
+                if tx
+                    .send((index, probe(net.as_ref(), req, skip_head).ok()))
+                    .is_err()
+                {
+                    break;
                 }
             }
-        }
+        });
     }
+    drop(tx);
 
-    if let Some(checksum) = &task.checksum {
-        if !verify_checksum(&task.dest_path, checksum) {
-            if let Ok(mut storage) = storage.lock() {
-                if let Ok(mut task) = storage.load_task(&task_id) {
-                    task.error = Some("checksum mismatch".to_string());
-                    let _ = storage.save_task(&task);
-                }
+    let mut received = 0usize;
+    loop {
+        let first = match rx.recv_timeout(STOP_POLL_INTERVAL) {
+            Ok(first) => first,
+            Err(RecvTimeoutError::Timeout) if !stopped() => continue,
+            Err(_) => {
+                stop.store(true, Ordering::SeqCst);
+                break;
             }
-            return Ok(TaskStatus::Failed);
+        };
+        for (index, resp) in std::iter::once(first).chain(rx.try_iter()) {
+            responses[index] = resp;
+            received += 1;
+        }
+        let direct = responses
+            .iter()
+            .position(|resp| resp.as_ref().is_some_and(is_direct_file));
+        if direct.is_some() {
+            stop.store(true, Ordering::SeqCst);
+            return (direct, responses);
+        }
+        if received == count {
+            break;
         }
     }
+    (None, responses)
+}
 
+fn finish_unknown_length(
+    task: &Task,
+    storage: &Arc<Mutex<Box<dyn Storage>>>,
+) -> CoreResult<TaskStatus> {
+    let total_bytes = fs::metadata(&task.dest_path)
+        .map(|meta| meta.len())
+        .map_err(|err| CoreError::Io(err.to_string()))?;
+    let mut segment = Segment::new(0, 0, total_bytes.saturating_sub(1));
+    segment.downloaded_bytes = total_bytes;
+    segment.status = SegmentStatus::Completed;
+    {
+        let mut storage = storage
+            .lock()
+            .map_err(|_| CoreError::Storage("storage lock poisoned".to_string()))?;
+        storage.update_progress(&task.id, total_bytes, total_bytes)?;
+        storage.save_segments(&task.id, &[segment])?;
+        // Without a known size, reaching the limit is what marks a prefix.
+        let reached = task
+            .byte_limit
+            .is_some_and(|limit| limit > 0 && total_bytes >= limit);
+        if reached && !task.partial {
+            let mut stored = storage.load_task(&task.id)?;
+            stored.partial = true;
+            storage.save_task(&stored)?;
+        }
+    }
     Ok(TaskStatus::Completed)
 }
 
 fn download_segment(
     index: usize,
     task: &Task,
-    url_candidates: &[String],
+    targets: &DownloadTargets,
     config: &EngineConfig,
     net: Arc<dyn NetClient>,
+    hosts: HostLimiter,
     storage: Arc<Mutex<Box<dyn Storage>>>,
     segments: Arc<Mutex<Vec<Segment>>>,
     progress: Arc<ProgressTracker>,
     throttle: Throttle,
     stop_flag: Arc<AtomicU8>,
-) -> CoreResult<()> {
+) -> CoreResult<Option<String>> {
     let (range_start, range_end, use_ranges) = {
         let segments = segments
             .lock()
@@ -798,113 +2976,317 @@ fn download_segment(
 
     let mut last_error: Option<CoreError> = None;
     let backoff = Duration::from_secs(config.retry_backoff_secs);
-
-    for attempt in 0..=config.retry_count {
-        if stop_flag.load(Ordering::SeqCst) != STOP_NONE {
-            return Ok(());
-        }
-        for url in url_candidates {
-            if stop_flag.load(Ordering::SeqCst) != STOP_NONE {
-                return Ok(());
+    let mut retry_wait: Option<Duration> = None;
+    let mut rotation = UserAgentRotation::new(config);
+
+    // Each URL gets the whole retry budget before the next one is tried, so a mirror that
+    // keeps failing spends its own attempts rather than everyone's.
+    for url in &targets.urls {
+        'attempts: for attempt in 0..=config.retry_count {
+            if attempt > 0 {
+                thread::sleep(retry_wait.take().unwrap_or(backoff));
             }
-            let current_downloaded = {
-                let segments = segments
-                    .lock()
-                    .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?;
-                segments
-                    .get(index)
-                    .map(|segment| segment.downloaded_bytes)
-                    .unwrap_or(0)
-            };
+            loop {
+                if stop_flag.load(Ordering::SeqCst) != STOP_NONE {
+                    return Ok(None);
+                }
+                let current_downloaded = {
+                    let segments = segments
+                        .lock()
+                        .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?;
+                    segments
+                        .get(index)
+                        .map(|segment| segment.downloaded_bytes)
+                        .unwrap_or(0)
+                };
+
+                if use_ranges && current_downloaded >= (range_end - range_start + 1) {
+                    return Ok(None);
+                }
 
-            if use_ranges && current_downloaded >= (range_end - range_start + 1) {
-                return Ok(());
-            }
+                let start = if use_ranges {
+                    range_start.saturating_add(current_downloaded)
+                } else {
+                    0
+                };
+                let end = if use_ranges { range_end } else { 0 };
+
+                let mut req = task_request(url.clone(), config, task);
+                targets.add_referer(&mut req);
+                if use_ranges {
+                    req.range = Some((start, end));
+                }
+                prefer_identity_encoding(&mut req, config.identity_encoding);
+                rotation.apply(&mut req);
+
+                // Held until this attempt's stream is finished.
+                let Some(_permit) =
+                    hosts.acquire(url, || stop_flag.load(Ordering::SeqCst) != STOP_NONE)
+                else {
+                    return Ok(None);
+                };
+                let response = match net.get_stream(&req) {
+                    Ok(resp) => resp,
+                    Err(err) => {
+                        last_error = Some(err);
+                        continue 'attempts;
+                    }
+                };
+
+                let status = response.status();
+                if use_ranges && status.as_u16() == 416 {
+                    // Retrying a range past the end of the file can't succeed.
+                    let size = response
+                        .headers()
+                        .get(CONTENT_RANGE)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(unsatisfied_range_total);
+                    if let Some(size) = size.filter(|size| *size <= range_end) {
+                        if current_downloaded > 0 && start > size {
+                            // We hold bytes the server no longer has.
+                            return Err(CoreError::RangeNotSatisfiable(size));
+                        }
+                        if start >= size {
+                            return Err(CoreError::ShortFile(size));
+                        }
+                    }
+                }
+                retry_wait = retry_after(status.as_u16(), response.headers()).or(retry_wait);
+                rotation.note_status(status.as_u16());
+                if let Some(err) = targets.unexpected_html(url, &response) {
+                    last_error = Some(err);
+                    continue 'attempts;
+                }
+                if !status.is_success() {
+                    let err = CoreError::HttpStatus(status.as_u16());
+                    let retry = err.is_retryable() || rotation.has_alternative(status.as_u16());
+                    last_error = Some(err);
+                    if retry {
+                        continue 'attempts;
+                    }
+                    // Permanent for this URL; move on to the next mirror.
+                    break 'attempts;
+                }
+                // A server ignoring `Range` sends the whole file, which is only usable by
+                // a fresh segment spanning all of it.
+                let whole_file = status.as_u16() == 200
+                    && start == 0
+                    && range_end + 1 == task.total_bytes
+                    && !task.partial;
+                if use_ranges && status.as_u16() != 206 && !whole_file {
+                    last_error = Some(CoreError::Network(format!(
+                        "range not supported (status {})",
+                        status.as_u16()
+                    )));
+                    continue 'attempts;
+                }
 
-            let start = if use_ranges {
-                range_start.saturating_add(current_downloaded)
-            } else {
-                0
-            };
-            let end = if use_ranges { range_end } else { 0 };
-
-            let mut req = DownloadRequest::new(url.clone(), config.user_agent.clone());
-            req.headers = task.headers.clone();
-            req.cookies = task.cookies.clone();
-            req.proxy = task.proxy_url.clone();
-            if let (Some(user), Some(pass)) = (task.auth_user.clone(), task.auth_pass.clone()) {
-                req.basic_auth = Some((user, pass));
-            }
-            if use_ranges {
-                req.range = Some((start, end));
-            }
+                let boundary = if use_ranges {
+                    response
+                        .headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(multipart_byteranges_boundary)
+                } else {
+                    None
+                };
 
-            let response = match net.get_stream(&req) {
-                Ok(resp) => resp,
-                Err(err) => {
+                if use_ranges && boundary.is_none() {
+                    if let Err(err) = check_content_range(&response, start) {
+                        last_error = Some(err);
+                        continue 'attempts;
+                    }
+                }
+                let served_total = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .filter(|_| boundary.is_none())
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(content_range_total);
+
+                let streamed = match boundary {
+                    Some(boundary) => {
+                        match multipart_first_part(BufReader::new(response), &boundary) {
+                            Ok((part_start, _, payload)) if part_start == start => stream_to_file(
+                                payload,
+                                &task.dest_path,
+                                start,
+                                progress.clone(),
+                                index,
+                                throttle.clone(),
+                                stop_flag.clone(),
+                            ),
+                            Ok((part_start, _, _)) => Err(CoreError::Network(format!(
+                                "multipart range starts at {} instead of {}",
+                                part_start, start
+                            ))),
+                            Err(err) => Err(err),
+                        }
+                    }
+                    None => stream_to_file(
+                        response,
+                        &task.dest_path,
+                        start,
+                        progress.clone(),
+                        index,
+                        throttle.clone(),
+                        stop_flag.clone(),
+                    ),
+                };
+                if let Err(err) = streamed {
                     last_error = Some(err);
-                    continue;
+                    continue 'attempts;
                 }
-            };
-
-            let status = response.status();
-            if use_ranges && status.as_u16() != 206 {
-                last_error = Some(CoreError::Network(format!(
-                    "range not supported (status {})",
-                    status.as_u16()
-                )));
-                continue;
-            }
-            if !status.is_success() {
-                last_error = Some(CoreError::Network(format!(
-                    "download failed with status {}",
-                    status.as_u16()
-                )));
-                continue;
-            }
 
-            if let Err(err) = stream_to_file(
-                response,
-                &task.dest_path,
-                start,
-                progress.clone(),
-                index,
-                throttle.clone(),
-                stop_flag.clone(),
-            ) {
-                last_error = Some(err);
-                continue;
-            }
+                if stop_flag.load(Ordering::SeqCst) != STOP_NONE {
+                    return Ok(None);
+                }
 
-            if stop_flag.load(Ordering::SeqCst) != STOP_NONE {
-                return Ok(());
-            }
+                if use_ranges {
+                    let downloaded = {
+                        let segments = segments
+                            .lock()
+                            .map_err(|_| CoreError::Storage("segment lock poisoned".to_string()))?;
+                        segments
+                            .get(index)
+                            .map(|segment| segment.downloaded_bytes)
+                            .unwrap_or(0)
+                    };
+                    if downloaded < range_end - range_start + 1 {
+                        let ended_at = range_start + downloaded;
+                        // The response itself says the file ends here.
+                        if served_total == Some(ended_at) {
+                            return Err(CoreError::ShortFile(ended_at));
+                        }
+                        last_error = Some(CoreError::ShortFile(ended_at));
+                        // A server that clamps ranges still made progress; ask for the rest.
+                        if downloaded > current_downloaded {
+                            continue;
+                        }
+                        continue 'attempts;
+                    }
+                }
 
-            if let Ok(mut segments) = segments.lock() {
-                if let Some(segment) = segments.get_mut(index) {
-                    segment.status = SegmentStatus::Completed;
+                // Read back while the bytes are likely still in the page cache.
+                let sha256 = (config.segment_checksums && use_ranges)
+                    .then(|| {
+                        range_sha256(&task.dest_path, range_start, range_end - range_start + 1)
+                    })
+                    .and_then(Result::ok);
+                if let Ok(mut segments) = segments.lock() {
+                    if let Some(segment) = segments.get_mut(index) {
+                        segment.status = SegmentStatus::Completed;
+                        segment.sha256 = sha256;
+                    }
                 }
-            }
-            if let Ok(mut storage) = storage.lock() {
-                if let Ok(segments) = segments.lock() {
-                    let _ = storage.save_segments(&task.id, &segments);
+                if let Ok(mut storage) = storage.lock() {
+                    if let Ok(segments) = segments.lock() {
+                        let _ = storage.update_segments(&task.id, &segments);
+                    }
                 }
+                return Ok(Some(url.clone()));
             }
-            return Ok(());
-        }
-
-        if attempt < config.retry_count {
-            thread::sleep(backoff);
         }
     }
 
+    // A `ShortFile` here means the last attempt on every URL ended cleanly at the same
+    // byte; `download_task` decides whether the file really ends there.
     Err(last_error.unwrap_or_else(|| {
         CoreError::Network(format!("failed to download segment {}", index))
     }))
 }
 
-fn stream_to_file(
-    mut response: reqwest::blocking::Response,
+/// Asks a server that accepts ranges but sent no `Content-Length` for `bytes=0-` and
+/// reads the size from the `Content-Range` total, so the file can still be split into
+/// segments. `None` leaves the download to the single-stream path.
+fn probe_range_total(
+    task: &Task,
+    targets: &DownloadTargets,
+    config: &EngineConfig,
+    net: &Arc<dyn NetClient>,
+    limits: &SharedLimits,
+    stop_flag: &AtomicU8,
+) -> Option<u64> {
+    let url = targets.urls.first()?;
+    let mut req = task_request(url.clone(), config, task);
+    targets.add_referer(&mut req);
+    req.range_from = Some(0);
+    prefer_identity_encoding(&mut req, config.identity_encoding);
+
+    let _permit = limits
+        .hosts
+        .acquire(url, || stop_flag.load(Ordering::SeqCst) != STOP_NONE)?;
+    // Only the headers are read; dropping the response closes the connection.
+    let response = net.get_stream(&req).ok()?;
+    if response.status().as_u16() != 206 {
+        return None;
+    }
+    response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(content_range_total)
+        .filter(|total| *total > 0)
+}
+
+/// Fails unless a `206` covers the range starting at `start`, so bytes are never written
+/// at the wrong offset. A missing header is trusted; some servers omit it.
+fn check_content_range(response: &Response, start: u64) -> CoreResult<()> {
+    let Some(value) = response.headers().get(CONTENT_RANGE) else {
+        return Ok(());
+    };
+    match value.to_str().ok().and_then(parse_content_range) {
+        Some((served, _)) if served == start => Ok(()),
+        Some((served, _)) => Err(CoreError::Network(format!(
+            "Content-Range starts at {} instead of {}",
+            served, start
+        ))),
+        None => Err(CoreError::Network(format!(
+            "invalid Content-Range {:?}",
+            value
+        ))),
+    }
+}
+
+/// Cycles through `EngineConfig::user_agent_rotation` once a request is refused with
+/// `403`; until then requests keep the task's (or engine's) own User-Agent.
+struct UserAgentRotation<'a> {
+    agents: &'a [String],
+    next: usize,
+    current: Option<&'a String>,
+}
+
+impl<'a> UserAgentRotation<'a> {
+    fn new(config: &'a EngineConfig) -> Self {
+        Self {
+            agents: &config.user_agent_rotation,
+            next: 0,
+            current: None,
+        }
+    }
+
+    fn note_status(&mut self, status: u16) {
+        if status != 403 || self.agents.is_empty() {
+            return;
+        }
+        self.current = self.agents.get(self.next % self.agents.len());
+        self.next += 1;
+    }
+
+    /// Whether a `403` may clear with the next User-Agent.
+    fn has_alternative(&self, status: u16) -> bool {
+        status == 403 && !self.agents.is_empty()
+    }
+
+    fn apply(&self, req: &mut DownloadRequest) {
+        if let Some(agent) = self.current {
+            req.user_agent = agent.clone();
+        }
+    }
+}
+
+fn stream_to_file<R: Read>(
+    mut response: R,
     dest_path: &str,
     start_offset: u64,
     progress: Arc<ProgressTracker>,
@@ -923,6 +3305,11 @@ fn stream_to_file(
     let mut buffer = vec![0u8; 1024 * 64];
     loop {
         if stop_flag.load(Ordering::SeqCst) != STOP_NONE {
+            // The caller records progress next; make sure these bytes outlive a crash
+            // first so a resume never trusts bytes that were only in the page cache.
+            file.flush()
+                .and_then(|()| file.sync_data())
+                .map_err(|err| CoreError::Io(err.to_string()))?;
             return Ok(());
         }
         let read = response
@@ -941,43 +3328,101 @@ fn stream_to_file(
     Ok(())
 }
 
-fn resolve_dest_path(dest_path: &str, url: &str, content_disposition: Option<&str>) -> String {
-    let dest_path = dest_path.trim();
-    let is_empty = dest_path.is_empty();
-    let mut path = PathBuf::from(dest_path);
+pub(crate) fn resolve_dest_path(
+    dest_path: &str,
+    url: &str,
+    content_disposition: Option<&str>,
+    template: Option<&OutputTemplate>,
+    task_id: &TaskId,
+) -> String {
+    match dest_dir(dest_path) {
+        Some(dir) => {
+            let suggested = suggested_filename(url, content_disposition);
+            let filename = match template {
+                Some(template) => template.render(&TemplateVars {
+                    url,
+                    filename: &suggested,
+                    task_id,
+                    now: now_epoch(),
+                }),
+                None => suggested,
+            };
+            dir.join(sanitize_filename(&filename))
+                .to_string_lossy()
+                .to_string()
+        }
+        None => absolute_dest(dest_path.trim(), &default_download_dir())
+            .to_string_lossy()
+            .to_string(),
+    }
+}
 
-    let mut treat_as_dir = is_empty
-        || dest_path.ends_with('/')
-        || dest_path.ends_with('\\')
-        || path.is_dir();
+/// The directory a filename still has to be chosen for, or `None` if `dest_path` is a file.
+fn dest_dir(dest_path: &str) -> Option<PathBuf> {
+    dest_dir_in(dest_path, &default_download_dir())
+}
 
-    if is_empty {
-        path = default_download_dir();
-        treat_as_dir = true;
+/// `dest_dir` with relative paths taken from `base` instead of the download dir.
+pub(crate) fn dest_dir_in(dest_path: &str, base: &Path) -> Option<PathBuf> {
+    let dest_path = dest_path.trim();
+    if dest_path.is_empty() {
+        return Some(absolute_dest("", base));
     }
+    // Decided on the resolved path, so a relative dir or a symlink to one counts.
+    let path = absolute_dest(dest_path, base);
+    if dest_path.ends_with('/') || dest_path.ends_with('\\') || path.is_dir() {
+        return Some(path);
+    }
+    None
+}
 
-    if treat_as_dir {
-        let filename = filename_from_content_disposition(content_disposition)
-            .or_else(|| filename_from_url(url))
-            .unwrap_or_else(|| "download.bin".to_string());
-        let filename = sanitize_filename(&filename);
-        return path.join(filename).to_string_lossy().to_string();
+/// `dest_path` made absolute against `base`, with symlinks resolved in whatever part of
+/// it already exists.
+pub(crate) fn absolute_dest(dest_path: &str, base: &Path) -> PathBuf {
+    let path = base.join(dest_path);
+    if let Ok(real) = fs::canonicalize(&path) {
+        return real;
     }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => match fs::canonicalize(parent) {
+            Ok(parent) => parent.join(name),
+            Err(_) => path,
+        },
+        _ => path,
+    }
+}
 
-    dest_path.to_string()
+/// Unsanitized filename from `Content-Disposition` or the URL.
+fn suggested_filename(url: &str, content_disposition: Option<&str>) -> String {
+    filename_from_content_disposition(content_disposition)
+        .or_else(|| filename_from_url(url))
+        .unwrap_or_else(|| "download.bin".to_string())
 }
 
-fn default_download_dir() -> PathBuf {
+/// Where downloads without a directory go: `IDM_DOWNLOAD_DIR`, else the platform's
+/// Downloads folder, else the temp directory.
+pub fn default_download_dir() -> PathBuf {
     if let Ok(dir) = env::var("IDM_DOWNLOAD_DIR") {
         return PathBuf::from(dir);
     }
-    let shared = Path::new("/storage/emulated/0/Download");
-    if shared.exists() {
-        return shared.to_path_buf();
-    }
-    let sdcard = Path::new("/sdcard/Download");
-    if sdcard.exists() {
-        return sdcard.to_path_buf();
+    platform_download_dir()
+}
+
+#[cfg(windows)]
+fn platform_download_dir() -> PathBuf {
+    env::var_os("USERPROFILE")
+        .map(|profile| PathBuf::from(profile).join("Downloads"))
+        .filter(|downloads| downloads.is_dir())
+        .unwrap_or_else(env::temp_dir)
+}
+
+#[cfg(not(windows))]
+fn platform_download_dir() -> PathBuf {
+    #[cfg(target_os = "android")]
+    for shared in ["/storage/emulated/0/Download", "/sdcard/Download"] {
+        if Path::new(shared).exists() {
+            return PathBuf::from(shared);
+        }
     }
     let home = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
     let downloads = PathBuf::from(&home).join("Downloads");
@@ -1099,6 +3544,36 @@ fn sanitize_filename(name: &str) -> String {
     if trimmed.is_empty() {
         "download.bin".to_string()
     } else {
-        trimmed.to_string()
+        truncate_filename(trimmed, MAX_FILENAME_BYTES)
+    }
+}
+
+/// Most filesystems (ext4, NTFS, exFAT, APFS) cap a single path component at 255 bytes.
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Shortens `name` to at most `max_bytes`, cutting the base name and keeping the
+/// extension. An extension too long to keep is truncated along with the rest.
+pub(crate) fn truncate_filename(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+    if let Some(dot) = name.rfind('.').filter(|&dot| dot > 0) {
+        let (base, ext) = name.split_at(dot);
+        if ext.len() < max_bytes {
+            let base = &base[..floor_char_boundary(base, max_bytes - ext.len())];
+            let base = base.trim_end_matches(&[' ', '.', '_'][..]);
+            if !base.is_empty() {
+                return format!("{}{}", base, ext);
+            }
+        }
+    }
+    name[..floor_char_boundary(name, max_bytes)].to_string()
+}
+
+fn floor_char_boundary(value: &str, index: usize) -> usize {
+    let mut index = index.min(value.len());
+    while !value.is_char_boundary(index) {
+        index -= 1;
     }
+    index
 }
@@ -8,12 +8,48 @@ pub enum CoreError {
     NotFound(String),
     #[error("network error: {0}")]
     Network(String),
+    #[error("download failed with status {0}")]
+    HttpStatus(u16),
     #[error("storage error: {0}")]
     Storage(String),
     #[error("io error: {0}")]
     Io(String),
     #[error("unsupported: {0}")]
     Unsupported(String),
+    #[error("extraction failed: {0}")]
+    Extract(String),
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("serialization error: {0}")]
+    Serialization(String),
+    /// A `416` for a range past the end of the server's file, which is this many bytes.
+    #[error("range not satisfiable: server file is {0} bytes")]
+    RangeNotSatisfiable(u64),
+    /// The server's file ends at this byte, before the size it announced.
+    #[error("server file ends at byte {0}, before its announced size")]
+    ShortFile(u64),
+}
+
+impl CoreError {
+    /// Whether the same request may succeed if tried again: transient network failures
+    /// and timeouts, and statuses for temporary conditions (408, 425, 429, most 5xx).
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            CoreError::Network(_) => true,
+            CoreError::HttpStatus(501 | 505) => false,
+            CoreError::HttpStatus(status) => matches!(status, 408 | 425 | 429 | 500..=599),
+            CoreError::InvalidState(_)
+            | CoreError::NotFound(_)
+            | CoreError::Storage(_)
+            | CoreError::Io(_)
+            | CoreError::Unsupported(_)
+            | CoreError::Extract(_)
+            | CoreError::InvalidConfig(_)
+            | CoreError::Serialization(_)
+            | CoreError::RangeNotSatisfiable(_)
+            | CoreError::ShortFile(_) => false,
+        }
+    }
 }
 
 pub type CoreResult<T> = Result<T, CoreError>;
@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::task::TaskId;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineEvent {
+    TaskAdded {
+        id: TaskId,
+    },
+    TaskStarted {
+        id: TaskId,
+    },
+    TaskProgress {
+        id: TaskId,
+        downloaded_bytes: u64,
+        /// `None` while the size is unknown (no Content-Length, HLS).
+        total_bytes: Option<u64>,
+    },
+    TaskPaused {
+        id: TaskId,
+    },
+    TaskResumed {
+        id: TaskId,
+    },
+    TaskCompleted {
+        id: TaskId,
+    },
+    /// Terminal: sent once `EngineConfig::max_task_restarts` automatic re-queues are used up.
+    TaskFailed {
+        id: TaskId,
+        error: Option<String>,
+    },
+    TaskCanceled {
+        id: TaskId,
+    },
+    TaskRemoved {
+        id: TaskId,
+    },
+}
+
+impl EngineEvent {
+    pub fn task_id(&self) -> &TaskId {
+        match self {
+            EngineEvent::TaskAdded { id }
+            | EngineEvent::TaskStarted { id }
+            | EngineEvent::TaskProgress { id, .. }
+            | EngineEvent::TaskPaused { id }
+            | EngineEvent::TaskResumed { id }
+            | EngineEvent::TaskCompleted { id }
+            | EngineEvent::TaskFailed { id, .. }
+            | EngineEvent::TaskCanceled { id }
+            | EngineEvent::TaskRemoved { id } => id,
+        }
+    }
+}
+
+/// Fans events out to subscribers. `TaskProgress` is rate limited per task: at most one
+/// per interval is sent and intermediate values are dropped, but the newest held-back
+/// update is always sent just before that task's next state-change event, which is
+/// never delayed.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Sender<EngineEvent>>>>,
+    progress: Arc<Mutex<ProgressGate>>,
+}
+
+#[derive(Default)]
+struct ProgressGate {
+    interval: Duration,
+    /// When each task's progress was last sent, plus the newest update held back since.
+    tasks: HashMap<TaskId, (Instant, Option<EngineEvent>)>,
+}
+
+impl ProgressGate {
+    fn admit(&mut self, event: EngineEvent) -> Vec<EngineEvent> {
+        if self.interval.is_zero() {
+            return vec![event];
+        }
+        let id = *event.task_id();
+        if !matches!(event, EngineEvent::TaskProgress { .. }) {
+            let held = self.tasks.remove(&id).and_then(|(_, held)| held);
+            return held.into_iter().chain(std::iter::once(event)).collect();
+        }
+        let now = Instant::now();
+        match self.tasks.get_mut(&id) {
+            Some((sent_at, held)) if now.duration_since(*sent_at) < self.interval => {
+                *held = Some(event);
+                Vec::new()
+            }
+            _ => {
+                self.tasks.insert(id, (now, None));
+                vec![event]
+            }
+        }
+    }
+}
+
+impl EventBus {
+    /// A bus sending each task's progress at most once per `interval`; zero sends all.
+    pub fn with_progress_interval(interval: Duration) -> Self {
+        let bus = Self::default();
+        if let Ok(mut gate) = bus.progress.lock() {
+            gate.interval = interval;
+        }
+        bus
+    }
+
+    pub fn subscribe(&self) -> Receiver<EngineEvent> {
+        let (tx, rx) = channel();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(tx);
+        }
+        rx
+    }
+
+    pub fn emit(&self, event: EngineEvent) {
+        let events = match self.progress.lock() {
+            Ok(mut gate) => gate.admit(event),
+            Err(_) => vec![event],
+        };
+        if events.is_empty() {
+            return;
+        }
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            // Drop subscribers whose receiver has gone away.
+            subscribers.retain(|tx| events.iter().all(|event| tx.send(event.clone()).is_ok()));
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use bzip2::read::MultiBzDecoder;
+use filetime::FileTime;
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use xz2::read::XzDecoder;
+
+use crate::error::{CoreError, CoreResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+/// Detects a supported archive by extension, falling back to magic bytes.
+pub fn detect_archive(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        return Some(ArchiveKind::Zip);
+    }
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        return Some(ArchiveKind::TarGz);
+    }
+
+    let mut magic = [0u8; 4];
+    let mut file = File::open(path).ok()?;
+    file.read_exact(&mut magic).ok()?;
+    if magic == *b"PK\x03\x04" {
+        return Some(ArchiveKind::Zip);
+    }
+    if magic[..2] == [0x1f, 0x8b] && is_gzipped_tar(path) {
+        return Some(ArchiveKind::TarGz);
+    }
+    None
+}
+
+/// A single compressed file, as opposed to an archive of several.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionKind {
+    Gzip,
+    Bzip2,
+    Xz,
+}
+
+impl CompressionKind {
+    fn extension(self) -> &'static str {
+        match self {
+            CompressionKind::Gzip => ".gz",
+            CompressionKind::Bzip2 => ".bz2",
+            CompressionKind::Xz => ".xz",
+        }
+    }
+}
+
+/// Detects gzip, bzip2 or xz compression by extension. The file's magic bytes must
+/// agree when they can be read; they never decide on their own. Files `detect_archive`
+/// recognises, such as `.tar.gz`, are not reported.
+pub fn detect_compression(path: &Path) -> Option<CompressionKind> {
+    if detect_archive(path).is_some() {
+        return None;
+    }
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    let kind = [
+        CompressionKind::Gzip,
+        CompressionKind::Bzip2,
+        CompressionKind::Xz,
+    ]
+    .into_iter()
+    .find(|kind| name.ends_with(kind.extension()))?;
+
+    let mut magic = [0u8; 6];
+    let readable = File::open(path).and_then(|mut file| file.read_exact(&mut magic));
+    let agrees = match kind {
+        CompressionKind::Gzip => magic[..2] == [0x1f, 0x8b],
+        CompressionKind::Bzip2 => magic[..3] == *b"BZh",
+        CompressionKind::Xz => magic == [0xfd, b'7', b'z', b'X', b'Z', 0x00],
+    };
+    (readable.is_err() || agrees).then_some(kind)
+}
+
+/// Path `decompress_file` writes to: `path` without its compression extension, or
+/// `path` itself when it doesn't carry that extension.
+pub fn decompressed_path(path: &Path, kind: CompressionKind) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = kind.extension();
+    if name.len() > ext.len() && name.to_ascii_lowercase().ends_with(ext) {
+        path.with_file_name(&name[..name.len() - ext.len()])
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Decodes `path` into `decompressed_path(path, kind)`, removes the compressed file and
+/// returns the new path. Nothing is replaced if the stream turns out to be corrupt.
+pub fn decompress_file(path: &Path, kind: CompressionKind) -> CoreResult<PathBuf> {
+    let target = decompressed_path(path, kind);
+    let mut staging = target.clone().into_os_string();
+    staging.push(".decompressing");
+    let staging = PathBuf::from(staging);
+
+    let decode = || -> io::Result<()> {
+        let file = File::open(path)?;
+        let mut reader: Box<dyn Read> = match kind {
+            CompressionKind::Gzip => Box::new(MultiGzDecoder::new(file)),
+            CompressionKind::Bzip2 => Box::new(MultiBzDecoder::new(file)),
+            CompressionKind::Xz => Box::new(XzDecoder::new_multi_decoder(file)),
+        };
+        let mut out = File::create(&staging)?;
+        io::copy(&mut reader, &mut out)?;
+        out.sync_all()
+    };
+    if let Err(err) = decode() {
+        let _ = fs::remove_file(&staging);
+        return Err(CoreError::Extract(format!(
+            "{}: failed to decompress: {}",
+            path.display(),
+            err
+        )));
+    }
+    // Keep a modification time taken from `Last-Modified`.
+    if let Ok(meta) = fs::metadata(path) {
+        let _ = filetime::set_file_mtime(&staging, FileTime::from_last_modification_time(&meta));
+    }
+    fs::rename(&staging, &target).map_err(|err| extract_error(path, err))?;
+    if target != path {
+        fs::remove_file(path).map_err(|err| extract_error(path, err))?;
+    }
+    Ok(target)
+}
+
+fn is_gzipped_tar(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let mut header = [0u8; 512];
+    if GzDecoder::new(file).read_exact(&mut header).is_err() {
+        return false;
+    }
+    &header[257..262] == b"ustar"
+}
+
+/// Directory next to `archive` that its contents are extracted into.
+pub fn extract_dir(archive: &Path) -> PathBuf {
+    let name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let lower = name.to_ascii_lowercase();
+    let stem = [".tar.gz", ".tgz", ".zip"]
+        .iter()
+        .find(|ext| lower.ends_with(*ext))
+        .map(|ext| &name[..name.len() - ext.len()])
+        .unwrap_or("");
+    let stem = if stem.is_empty() {
+        format!("{}_extracted", name)
+    } else {
+        stem.to_string()
+    };
+    archive.with_file_name(stem)
+}
+
+/// Extracts `archive` into `extract_dir(archive)` and returns that directory.
+/// Entries that would land outside the target directory abort the extraction.
+pub fn extract_archive(archive: &Path, kind: ArchiveKind) -> CoreResult<PathBuf> {
+    let target = extract_dir(archive);
+    fs::create_dir_all(&target).map_err(|err| extract_error(archive, err))?;
+    match kind {
+        ArchiveKind::Zip => extract_zip(archive, &target)?,
+        ArchiveKind::TarGz => extract_tar_gz(archive, &target)?,
+    }
+    Ok(target)
+}
+
+fn extract_zip(archive: &Path, target: &Path) -> CoreResult<()> {
+    let file = File::open(archive).map_err(|err| extract_error(archive, err))?;
+    let mut zip = zip::ZipArchive::new(file)
+        .map_err(|err| CoreError::Extract(format!("{}: {}", archive.display(), err)))?;
+
+    for index in 0..zip.len() {
+        let mut entry = zip
+            .by_index(index)
+            .map_err(|err| CoreError::Extract(format!("{}: {}", archive.display(), err)))?;
+        let relative = entry
+            .enclosed_name()
+            .ok_or_else(|| unsafe_entry(archive, entry.name()))?;
+        let out_path = target.join(relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&out_path).map_err(|err| extract_error(archive, err))?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent).map_err(|err| extract_error(archive, err))?;
+        }
+        let mut out = File::create(&out_path).map_err(|err| extract_error(archive, err))?;
+        io::copy(&mut entry, &mut out).map_err(|err| extract_error(archive, err))?;
+    }
+    Ok(())
+}
+
+fn extract_tar_gz(archive: &Path, target: &Path) -> CoreResult<()> {
+    let file = File::open(archive).map_err(|err| extract_error(archive, err))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    let entries = tar.entries().map_err(|err| extract_error(archive, err))?;
+
+    for entry in entries {
+        let mut entry = entry.map_err(|err| extract_error(archive, err))?;
+        let path = entry
+            .path()
+            .map_err(|err| extract_error(archive, err))?
+            .into_owned();
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(unsafe_entry(archive, &path.to_string_lossy()));
+        }
+        if !entry
+            .unpack_in(target)
+            .map_err(|err| extract_error(archive, err))?
+        {
+            return Err(unsafe_entry(archive, &path.to_string_lossy()));
+        }
+    }
+    Ok(())
+}
+
+fn unsafe_entry(archive: &Path, name: &str) -> CoreError {
+    CoreError::Extract(format!(
+        "{}: entry {} escapes the extraction directory",
+        archive.display(),
+        name
+    ))
+}
+
+fn extract_error(archive: &Path, err: io::Error) -> CoreError {
+    CoreError::Extract(format!("{}: {}", archive.display(), err))
+}
@@ -0,0 +1,118 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::task::TaskId;
+
+/// Samples kept per task; at one per second this covers the last minute.
+pub const SPEED_HISTORY_LEN: usize = 60;
+
+/// Minimum spacing between two samples of the same task.
+const SAMPLE_INTERVAL_MS: u64 = 1_000;
+
+/// Weight of the newest interval in the smoothed speed.
+const SPEED_SMOOTHING: f64 = 0.3;
+
+/// Without a new sample for this long a download counts as stalled.
+const STALE_AFTER_MS: u64 = 5_000;
+
+/// Longer estimates are reported as this; `99:59:59` is as far as they mean anything.
+pub const MAX_ETA: Duration = Duration::from_secs(100 * 3600 - 1);
+
+type Samples = HashMap<TaskId, VecDeque<(u64, u64)>>;
+
+/// Recent `(epoch milliseconds, downloaded bytes)` samples per task, for drawing a speed
+/// graph. Samples are only taken while bytes arrive, so a gap between timestamps means
+/// nothing was received in between.
+#[derive(Clone, Default)]
+pub struct SpeedHistory {
+    tasks: Arc<Mutex<Samples>>,
+}
+
+impl SpeedHistory {
+    pub fn record(&self, id: TaskId, downloaded_bytes: u64) {
+        self.record_at(id, epoch_millis(), downloaded_bytes);
+    }
+
+    pub(crate) fn record_at(&self, id: TaskId, at_ms: u64, downloaded_bytes: u64) {
+        let Ok(mut tasks) = self.tasks.lock() else {
+            return;
+        };
+        let samples = tasks.entry(id).or_default();
+        if samples
+            .back()
+            .is_some_and(|(last, _)| at_ms.saturating_sub(*last) < SAMPLE_INTERVAL_MS)
+        {
+            return;
+        }
+        if samples.len() == SPEED_HISTORY_LEN {
+            samples.pop_front();
+        }
+        samples.push_back((at_ms, downloaded_bytes));
+    }
+
+    /// Oldest sample first; empty for unknown tasks.
+    pub fn samples(&self, id: &TaskId) -> Vec<(u64, u64)> {
+        self.tasks
+            .lock()
+            .ok()
+            .and_then(|tasks| {
+                tasks
+                    .get(id)
+                    .map(|samples| samples.iter().copied().collect())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Smoothed bytes per second right now; see `smoothed_speed`.
+    pub fn speed(&self, id: &TaskId) -> Option<f64> {
+        smoothed_speed(&self.samples(id), epoch_millis())
+    }
+
+    pub fn remove(&self, id: &TaskId) {
+        if let Ok(mut tasks) = self.tasks.lock() {
+            tasks.remove(id);
+        }
+    }
+}
+
+/// Bytes per second as an exponentially weighted average of the rates between
+/// consecutive samples, so a single fast or slow second barely moves it. `None` with
+/// fewer than two samples, or when the newest is more than five seconds before `now_ms`.
+pub(crate) fn smoothed_speed(samples: &[(u64, u64)], now_ms: u64) -> Option<f64> {
+    let (last_ms, _) = samples.last()?;
+    if now_ms.saturating_sub(*last_ms) > STALE_AFTER_MS {
+        return None;
+    }
+    let mut speed: Option<f64> = None;
+    for pair in samples.windows(2) {
+        let ((from_ms, from_bytes), (to_ms, to_bytes)) = (pair[0], pair[1]);
+        let secs = to_ms.saturating_sub(from_ms) as f64 / 1_000.0;
+        if secs <= 0.0 {
+            continue;
+        }
+        let rate = to_bytes.saturating_sub(from_bytes) as f64 / secs;
+        speed = Some(match speed {
+            Some(speed) => SPEED_SMOOTHING * rate + (1.0 - SPEED_SMOOTHING) * speed,
+            None => rate,
+        });
+    }
+    speed
+}
+
+/// Time to fetch `remaining` bytes at `bytes_per_sec`, capped at `MAX_ETA`; `None` for
+/// a speed of zero.
+pub(crate) fn eta(remaining: u64, bytes_per_sec: f64) -> Option<Duration> {
+    if !bytes_per_sec.is_finite() || bytes_per_sec <= 0.0 {
+        return None;
+    }
+    let secs = remaining as f64 / bytes_per_sec;
+    Some(Duration::from_secs_f64(secs.min(MAX_ETA.as_secs_f64())))
+}
+
+fn epoch_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
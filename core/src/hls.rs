@@ -1,9 +1,13 @@
+use crate::config::EngineConfig;
+use crate::engine::{task_request, STOP_CANCELED, STOP_PAUSED};
 use crate::error::{CoreError, CoreResult};
 use crate::net::NetClient;
+use crate::segment::{Segment, SegmentStatus};
 use crate::task::{Task, TaskStatus};
-use m3u8_rs::Playlist;
-use std::fs::OpenOptions;
-use std::io::Write;
+use m3u8_rs::{ByteRange, MediaPlaylist, Playlist};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::thread;
@@ -13,16 +17,189 @@ use bytes::Bytes;
 
 pub struct HlsDownloader;
 
+/// Buffers segment payloads and writes them to `inner` strictly in index order,
+/// so segments fetched out of order still land contiguously on disk.
+pub struct SegmentWriter<W: Write> {
+    inner: BufWriter<W>,
+    next_index: usize,
+    pending: BTreeMap<usize, Bytes>,
+}
+
+impl<W: Write> SegmentWriter<W> {
+    pub fn new(inner: W, capacity: usize) -> Self {
+        Self {
+            inner: BufWriter::with_capacity(capacity.max(1), inner),
+            next_index: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Queues segment `index`; returns how many bytes became contiguous and were written.
+    pub fn push(&mut self, index: usize, data: Bytes) -> io::Result<u64> {
+        self.pending.insert(index, data);
+        let mut written = 0u64;
+        while let Some(data) = self.pending.remove(&self.next_index) {
+            self.inner.write_all(&data)?;
+            written += data.len() as u64;
+            self.next_index += 1;
+        }
+        Ok(written)
+    }
+
+    /// Writes continue from segment `index`, for output that already holds the earlier ones.
+    pub fn starting_at(mut self, index: usize) -> Self {
+        self.next_index = index;
+        self
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+}
+
+impl SegmentWriter<File> {
+    /// Flushes buffered segments and fsyncs so the partial output is durable.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.inner.get_ref().sync_all()
+    }
+}
+
+/// Where an interrupted HLS download picks up: fetches `0..next` fill exactly the first
+/// `bytes` bytes of the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HlsResume {
+    pub next: usize,
+    pub bytes: u64,
+}
+
+impl HlsResume {
+    /// Stored in the segments table as one completed segment whose index is `next`.
+    pub fn to_segment(self) -> Segment {
+        let mut segment = Segment::new(self.next as u32, 0, self.bytes.saturating_sub(1));
+        segment.downloaded_bytes = self.bytes;
+        segment.status = SegmentStatus::Completed;
+        segment
+    }
+
+    /// The point saved by `to_segment`; the start for anything else.
+    pub fn from_segments(segments: &[Segment]) -> Self {
+        match segments {
+            [segment] if segment.status == SegmentStatus::Completed => Self {
+                next: segment.index as usize,
+                bytes: segment.downloaded_bytes,
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+fn stop_status(stop_flag: &AtomicU8) -> Option<TaskStatus> {
+    match stop_flag.load(Ordering::SeqCst) {
+        STOP_PAUSED => Some(TaskStatus::Paused),
+        STOP_CANCELED => Some(TaskStatus::Canceled),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HlsFetch {
+    pub url: String,
+    pub range: Option<(u64, u64)>,
+}
+
+/// Expands a media playlist into the ordered list of requests to issue,
+/// including `#EXT-X-MAP` init sections and `#EXT-X-BYTERANGE` sub-ranges. Zero-length
+/// ranges hold no bytes and are skipped.
+pub fn plan_fetches(media: &MediaPlaylist, base_url: &Url) -> Vec<HlsFetch> {
+    let mut fetches = Vec::with_capacity(media.segments.len());
+    // A byterange without an offset continues where the previous range of the same resource ended.
+    let mut next_offsets: HashMap<String, u64> = HashMap::new();
+    let mut last_map: Option<HlsFetch> = None;
+
+    for segment in &media.segments {
+        let map = segment
+            .map
+            .as_ref()
+            .filter(|map| !is_empty(&map.byte_range));
+        if let Some(map) = map {
+            let url = join_uri(base_url, &map.uri);
+            let range = map.byte_range.as_ref().map(|byte_range| {
+                let start = byte_range.offset.unwrap_or(0);
+                (
+                    start,
+                    start.saturating_add(byte_range.length.saturating_sub(1)),
+                )
+            });
+            let fetch = HlsFetch { url, range };
+            if last_map.as_ref() != Some(&fetch) {
+                fetches.push(fetch.clone());
+                last_map = Some(fetch);
+            }
+        }
+
+        let url = join_uri(base_url, &segment.uri);
+        let range = segment
+            .byte_range
+            .as_ref()
+            .map(|byte_range| slice_range(byte_range, &url, &mut next_offsets));
+        if is_empty(&segment.byte_range) {
+            continue;
+        }
+        fetches.push(HlsFetch { url, range });
+    }
+
+    fetches
+}
+
+fn join_uri(base_url: &Url, uri: &str) -> String {
+    if uri.starts_with("http") {
+        uri.to_string()
+    } else {
+        base_url
+            .join(uri)
+            .map(|u| u.to_string())
+            .unwrap_or_else(|_| uri.to_string())
+    }
+}
+
+fn is_empty(byte_range: &Option<ByteRange>) -> bool {
+    byte_range
+        .as_ref()
+        .is_some_and(|byte_range| byte_range.length == 0)
+}
+
+fn slice_range(
+    byte_range: &ByteRange,
+    url: &str,
+    next_offsets: &mut HashMap<String, u64>,
+) -> (u64, u64) {
+    let start = byte_range
+        .offset
+        .unwrap_or_else(|| next_offsets.get(url).copied().unwrap_or(0));
+    let end = start.saturating_add(byte_range.length.saturating_sub(1));
+    next_offsets.insert(url.to_string(), start.saturating_add(byte_range.length));
+    (start, end)
+}
+
 impl HlsDownloader {
+    /// Appends the playlist's segments to `task.dest_path`, skipping those `resume` says
+    /// are already there. `progress_updater` gets the resume point after each segment is
+    /// synced to disk and before each retry, and may set `stop_flag` to pause or cancel.
     pub fn download(
         task: &mut Task,
         net: Arc<dyn NetClient>,
         stop_flag: Arc<AtomicU8>,
-        progress_updater: impl Fn(u64) + Send + 'static,
+        config: &EngineConfig,
+        resume: HlsResume,
+        progress_updater: impl Fn(HlsResume) + Send + 'static,
     ) -> CoreResult<TaskStatus> {
         // 1. Fetch Playlist
-        let mut req = crate::net::DownloadRequest::new(task.url.clone(), "IDM-Open/1.0".to_string());
-        req.headers = task.headers.clone();
+        let req = task_request(task.url.clone(), config, task);
         
         let response = net.get(&req)?;
         let bytes: Bytes = response.bytes().map_err(|e| CoreError::Network(e.to_string()))?;
@@ -51,7 +228,7 @@ impl HlsDownloader {
                 };
 
                 // Fetch media playlist
-                let var_req = crate::net::DownloadRequest::new(variant_url.clone(), "IDM-Open/1.0".to_string());
+                let var_req = task_request(variant_url.clone(), config, task);
                 let var_resp = net.get(&var_req)?;
                 let var_bytes: Bytes = var_resp.bytes().map_err(|e| CoreError::Network(e.to_string()))?;
                 
@@ -64,42 +241,94 @@ impl HlsDownloader {
         };
 
         // 2. Prepare Destination File
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .create(true)
             .write(true)
             .append(true) // HLS appends segments
             .open(&task.dest_path)
             .map_err(|e| CoreError::Io(e.to_string()))?;
+        // Cut back to the resume point, dropping anything written after it; start over
+        // if the output no longer holds that much.
+        let on_disk = file
+            .metadata()
+            .map_err(|e| CoreError::Io(e.to_string()))?
+            .len();
+        let resume = if on_disk >= resume.bytes {
+            resume
+        } else {
+            HlsResume::default()
+        };
+        file.set_len(resume.bytes)
+            .map_err(|e| CoreError::Io(e.to_string()))?;
+        let mut writer =
+            SegmentWriter::new(file, config.hls_write_buffer_bytes).starting_at(resume.next);
 
         // 3. Download Segments
         let base_url = Url::parse(&task.url).map_err(|e| CoreError::Network(e.to_string()))?;
-        let mut downloaded_bytes = 0u64;
+        let mut downloaded_bytes = resume.bytes;
+        let mut next = resume.next;
 
-        for (i, segment) in media_playlist.segments.iter().enumerate() {
-             if stop_flag.load(Ordering::SeqCst) != 0 {
-                return Ok(TaskStatus::Paused); // Simplify stop handling for now
+        for (i, fetch) in plan_fetches(&media_playlist, &base_url)
+            .iter()
+            .enumerate()
+            .skip(resume.next)
+        {
+            if let Some(status) = stop_status(&stop_flag) {
+                writer.sync().map_err(|e| CoreError::Io(e.to_string()))?;
+                return Ok(status);
             }
 
-            let seg_url = if segment.uri.starts_with("http") {
-                segment.uri.clone()
-            } else {
-                base_url.join(&segment.uri).map(|u| u.to_string()).unwrap_or(segment.uri.clone())
-            };
-
             // Retry logic for segment
             let mut success = false;
-            for _ in 0..3 {
-                let seg_req = crate::net::DownloadRequest::new(seg_url.clone(), "IDM-Open/1.0".to_string());
+            for attempt in 0..3 {
+                if attempt > 0 {
+                    progress_updater(HlsResume {
+                        next,
+                        bytes: downloaded_bytes,
+                    });
+                    if let Some(status) = stop_status(&stop_flag) {
+                        writer.sync().map_err(|e| CoreError::Io(e.to_string()))?;
+                        return Ok(status);
+                    }
+                }
+                let mut seg_req = task_request(fetch.url.clone(), config, task);
+                seg_req.range = fetch.range;
                 if let Ok(resp) = net.get(&seg_req) {
+                    let status = resp.status().as_u16();
+                    if !resp.status().is_success() {
+                        thread::sleep(Duration::from_millis(500));
+                        continue;
+                    }
                     let data: Bytes = match resp.bytes() {
                         Ok(b) => b,
                         Err(_) => continue,
                     };
-                    if let Err(e) = file.write_all(&data) {
-                         return Err(CoreError::Io(e.to_string()));
+                    let data = match fetch.range {
+                        // Server ignored the Range header and sent the whole resource.
+                        Some((start, end)) if status == 200 => {
+                            let end = end.saturating_add(1) as usize;
+                            if end > data.len() {
+                                return Err(CoreError::Network(format!(
+                                    "segment {} byterange exceeds resource size",
+                                    i
+                                )));
+                            }
+                            data.slice(start as usize..end)
+                        }
+                        _ => data,
+                    };
+                    match writer.push(i, data) {
+                        Ok(written) => downloaded_bytes += written,
+                        Err(e) => return Err(CoreError::Io(e.to_string())),
                     }
-                    downloaded_bytes += data.len() as u64;
-                    progress_updater(downloaded_bytes);
+                    // On disk before it is reported, so a saved resume point never
+                    // claims bytes a crash could lose.
+                    writer.sync().map_err(|e| CoreError::Io(e.to_string()))?;
+                    next = i + 1;
+                    progress_updater(HlsResume {
+                        next,
+                        bytes: downloaded_bytes,
+                    });
                     success = true;
                     break;
                 }
@@ -111,6 +340,7 @@ impl HlsDownloader {
             }
         }
 
+        writer.sync().map_err(|e| CoreError::Io(e.to_string()))?;
         Ok(TaskStatus::Completed)
     }
 }
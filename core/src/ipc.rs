@@ -0,0 +1,192 @@
+//! Control socket for a running engine: `idm-daemon --socket <path>` serves it and
+//! `idm-cli --daemon <path>` talks to it. Every message is a little-endian `u32` length
+//! followed by that many bytes of JSON, the framing the browser native host uses.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{DownloadEngine, EngineStats};
+use crate::error::{CoreError, CoreResult};
+use crate::task::{TaskId, TaskSummary};
+
+/// Larger frames are refused rather than buffered.
+pub const MAX_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum IpcRequest {
+    Status,
+    List,
+    Add {
+        url: String,
+        /// Empty to name the file from the response.
+        #[serde(default)]
+        dest_path: String,
+    },
+    Pause {
+        id: TaskId,
+    },
+    Resume {
+        id: TaskId,
+    },
+    Cancel {
+        id: TaskId,
+    },
+    Remove {
+        id: TaskId,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpcResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The task created by `add`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<TaskId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<EngineStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tasks: Option<Vec<TaskSummary>>,
+}
+
+impl IpcResponse {
+    fn done() -> Self {
+        Self {
+            ok: true,
+            ..Self::default()
+        }
+    }
+
+    fn failed(error: impl ToString) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+pub fn handle_request(engine: &DownloadEngine, request: IpcRequest) -> IpcResponse {
+    let result = match request {
+        IpcRequest::Status => engine.stats().map(|stats| IpcResponse {
+            stats: Some(stats),
+            ..IpcResponse::done()
+        }),
+        IpcRequest::List => engine.list_task_summaries().map(|tasks| IpcResponse {
+            tasks: Some(tasks),
+            ..IpcResponse::done()
+        }),
+        IpcRequest::Add { url, dest_path } => {
+            engine.add_task(url, dest_path).map(|id| IpcResponse {
+                id: Some(id),
+                ..IpcResponse::done()
+            })
+        }
+        IpcRequest::Pause { id } => engine.pause_task(&id).map(|_| IpcResponse::done()),
+        IpcRequest::Resume { id } => engine.resume_task(&id).map(|_| IpcResponse::done()),
+        IpcRequest::Cancel { id } => engine.cancel_task(&id).map(|_| IpcResponse::done()),
+        IpcRequest::Remove { id } => engine.remove_task(&id).map(|_| IpcResponse::done()),
+    };
+    result.unwrap_or_else(IpcResponse::failed)
+}
+
+/// `None` once the peer has closed the connection.
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    if let Err(err) = reader.read_exact(&mut len_buf) {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(err);
+    }
+    let len = u32::from_le_bytes(len_buf) as usize;
+    if len > MAX_FRAME_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds {}", len, MAX_FRAME_BYTES),
+        ));
+    }
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Answers requests until the peer disconnects or sends a malformed frame.
+fn serve_connection(engine: &DownloadEngine, mut stream: impl Read + Write) {
+    while let Ok(Some(frame)) = read_frame(&mut stream) {
+        let response = match serde_json::from_slice(&frame) {
+            Ok(request) => handle_request(engine, request),
+            Err(err) => IpcResponse::failed(format!("invalid request: {}", err)),
+        };
+        let payload = serde_json::to_vec(&response).unwrap_or_else(|_| b"{}".to_vec());
+        if write_frame(&mut stream, &payload).is_err() {
+            break;
+        }
+    }
+}
+
+/// Listens on `path` in the background, one thread per connection. A socket file left
+/// behind by a dead daemon is replaced; one that still answers is an error.
+#[cfg(unix)]
+pub fn serve(engine: Arc<DownloadEngine>, path: &Path) -> CoreResult<JoinHandle<()>> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let io_error = |err: io::Error| CoreError::Io(format!("{}: {}", path.display(), err));
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(CoreError::InvalidState(format!(
+                "a daemon is already listening on {}",
+                path.display()
+            )));
+        }
+        std::fs::remove_file(path).map_err(io_error)?;
+    }
+    let listener = UnixListener::bind(path).map_err(io_error)?;
+    Ok(std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let engine = Arc::clone(&engine);
+            std::thread::spawn(move || serve_connection(&engine, stream));
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+pub fn serve(_engine: Arc<DownloadEngine>, _path: &Path) -> CoreResult<JoinHandle<()>> {
+    Err(CoreError::Unsupported(
+        "control sockets need Unix domain sockets".to_string(),
+    ))
+}
+
+/// Sends one request to the daemon listening on `path` and waits for its answer.
+#[cfg(unix)]
+pub fn send_request(path: &Path, request: &IpcRequest) -> CoreResult<IpcResponse> {
+    use std::os::unix::net::UnixStream;
+
+    let io_error = |err: io::Error| CoreError::Io(format!("{}: {}", path.display(), err));
+    let mut stream = UnixStream::connect(path).map_err(io_error)?;
+    let payload =
+        serde_json::to_vec(request).map_err(|err| CoreError::Serialization(err.to_string()))?;
+    write_frame(&mut stream, &payload).map_err(io_error)?;
+    let frame = read_frame(&mut stream)
+        .map_err(io_error)?
+        .ok_or_else(|| CoreError::Io(format!("{}: connection closed", path.display())))?;
+    serde_json::from_slice(&frame).map_err(|err| CoreError::Serialization(err.to_string()))
+}
+
+#[cfg(not(unix))]
+pub fn send_request(_path: &Path, _request: &IpcRequest) -> CoreResult<IpcResponse> {
+    Err(CoreError::Unsupported(
+        "control sockets need Unix domain sockets".to_string(),
+    ))
+}
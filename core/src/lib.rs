@@ -1,8 +1,14 @@
 pub mod checksum;
 pub mod config;
+pub mod cookies;
+pub mod data_url;
 pub mod engine;
 pub mod error;
+pub mod event;
+pub mod extract;
+pub mod history;
 pub mod hls;
+pub mod ipc;
 pub mod net;
 pub mod queue;
 pub mod resolver;
@@ -10,13 +16,17 @@ pub mod scheduler;
 pub mod segment;
 pub mod storage;
 pub mod task;
+pub mod template;
 pub mod throttle;
 pub mod torrent;
 
 #[cfg(test)]
 pub mod tests;
+#[cfg(test)]
+pub mod test_server;
 
 
-pub use crate::engine::DownloadEngine;
+pub use crate::engine::{default_download_dir, DownloadEngine};
 pub use crate::error::CoreError;
-pub use crate::task::{Task, TaskId, TaskStatus};
\ No newline at end of file
+pub use crate::event::EngineEvent;
+pub use crate::task::{Task, TaskBuilder, TaskFilter, TaskId, TaskStatus, TaskSummary};
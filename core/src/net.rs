@@ -1,22 +1,46 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Cursor, Read, Take};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
 
-use reqwest::blocking::{Client, Response};
-use reqwest::header::{
+use bytes::Bytes;
+use http::header::{
     HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH,
-    CONTENT_TYPE, RANGE,
+    CONTENT_RANGE, CONTENT_TYPE, COOKIE, LAST_MODIFIED, RANGE, RETRY_AFTER, USER_AGENT,
 };
+use http::StatusCode;
+#[cfg(feature = "net-reqwest")]
+use reqwest::blocking::Client;
+
+use url::Url;
 
 use crate::error::{CoreError, CoreResult};
+use crate::resolver::is_cloudflare_challenge;
+use crate::task::{HttpMethod, RequestBody, Task};
 
 #[derive(Debug, Clone)]
 pub struct DownloadRequest {
     pub url: String,
     pub headers: HashMap<String, String>,
+    /// Engine-wide headers, sent unless `headers` names the same one (case-insensitively).
+    pub default_headers: HashMap<String, String>,
     pub cookies: HashMap<String, String>,
     pub range: Option<(u64, u64)>,
+    pub range_from: Option<u64>,
     pub proxy: Option<String>,
     pub basic_auth: Option<(String, String)>,
     pub user_agent: String,
+    pub method: HttpMethod,
+    /// Sent with `HttpMethod::Post`.
+    pub body: Option<RequestBody>,
+    /// Deadline for the whole exchange, body included; `None` waits as long as the
+    /// server keeps sending.
+    pub timeout: Option<Duration>,
+    /// Longest silence on the connection, e.g. while reading the body, before the read
+    /// fails and the connection is dropped.
+    pub read_timeout: Option<Duration>,
 }
 
 impl DownloadRequest {
@@ -24,12 +48,44 @@ impl DownloadRequest {
         Self {
             url,
             headers: HashMap::new(),
+            default_headers: HashMap::new(),
             cookies: HashMap::new(),
             range: None,
+            range_from: None,
             proxy: None,
             basic_auth: None,
             user_agent,
+            method: HttpMethod::Get,
+            body: None,
+            timeout: None,
+            read_timeout: None,
+        }
+    }
+
+    /// `user_agent` is the engine default; the task's own `user_agent` wins if set. The
+    /// task's method and body only apply to its own URL and mirrors, not to resolved links.
+    pub fn for_task(url: String, user_agent: String, task: &Task) -> Self {
+        let user_agent = task.user_agent.clone().unwrap_or(user_agent);
+        let mut req = Self::new(url, user_agent);
+        req.headers = task.headers.clone();
+        req.cookies = task.cookies.clone();
+        req.proxy = task.proxy_url.clone();
+        if let (Some(user), Some(pass)) = (task.auth_user.clone(), task.auth_pass.clone()) {
+            req.basic_auth = Some((user, pass));
         }
+        if req.url == task.url || task.mirrors.contains(&req.url) {
+            req.method = task.method;
+            req.body = task.body.clone();
+        }
+        req
+    }
+
+    /// Whether `headers` or `default_headers` sets `name`, ignoring case.
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers
+            .keys()
+            .chain(self.default_headers.keys())
+            .any(|key| key.eq_ignore_ascii_case(name))
     }
 }
 
@@ -40,6 +96,99 @@ pub struct DownloadResponse {
     pub accept_ranges: bool,
     pub content_type: Option<String>,
     pub content_disposition: Option<String>,
+    /// URL after following redirects, when known.
+    pub final_url: Option<String>,
+    pub last_modified: Option<String>,
+    /// Answered with Cloudflare's browser challenge instead of the resource.
+    pub cloudflare_challenge: bool,
+}
+
+/// A response whose body has not been read yet, whichever client fetched it. Reading
+/// it streams the body.
+pub struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    url: String,
+    body: Box<dyn Read + Send>,
+}
+
+impl Response {
+    /// `url` is where the response came from, after any redirects.
+    pub fn new(
+        status: StatusCode,
+        headers: HeaderMap,
+        url: String,
+        body: Box<dyn Read + Send>,
+    ) -> Self {
+        Self {
+            status,
+            headers,
+            url,
+            body,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Reads the rest of the body into memory.
+    pub fn bytes(mut self) -> io::Result<Bytes> {
+        let mut data = Vec::new();
+        self.body.read_to_end(&mut data)?;
+        Ok(Bytes::from(data))
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "net-reqwest")]
+impl From<reqwest::blocking::Response> for Response {
+    fn from(resp: reqwest::blocking::Response) -> Self {
+        Self::new(
+            resp.status(),
+            resp.headers().clone(),
+            resp.url().to_string(),
+            Box::new(resp),
+        )
+    }
+}
+
+#[cfg(feature = "net-ureq")]
+impl From<http::Response<ureq::Body>> for Response {
+    fn from(resp: http::Response<ureq::Body>) -> Self {
+        use ureq::ResponseExt;
+
+        let url = resp.get_uri().to_string();
+        let (parts, body) = resp.into_parts();
+        Self::new(
+            parts.status,
+            parts.headers,
+            url,
+            Box::new(body.into_reader()),
+        )
+    }
 }
 
 pub trait NetClient: Send + Sync {
@@ -48,110 +197,148 @@ pub trait NetClient: Send + Sync {
     fn get_stream(&self, req: &DownloadRequest) -> CoreResult<Response>;
 }
 
+#[cfg(all(feature = "net-reqwest", feature = "net-ureq"))]
+compile_error!(
+    "`net-reqwest` and `net-ureq` are mutually exclusive; build with \
+     `--no-default-features --features sqlite,net-ureq` for the ureq client"
+);
+
+#[cfg(not(any(feature = "net-reqwest", feature = "net-ureq")))]
+compile_error!("enable an HTTP client: the `net-reqwest` (default) or `net-ureq` feature");
+
+/// The client `DownloadEngine::new` uses, picked by the `net-*` feature.
+#[cfg(feature = "net-reqwest")]
+pub type DefaultNetClient = ReqwestNetClient;
+
+/// The client `DownloadEngine::new` uses, picked by the `net-*` feature.
+#[cfg(all(feature = "net-ureq", not(feature = "net-reqwest")))]
+pub type DefaultNetClient = UreqNetClient;
+
+/// Headers every client sends: User-Agent, default and custom headers, cookies, Range.
+fn request_headers(req: &DownloadRequest) -> CoreResult<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    // The shared client carries the engine default; send the request's own UA
+    // (per-task override or rotation) explicitly. A custom header still wins below.
+    if !req.user_agent.is_empty() {
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_str(&req.user_agent)
+                .map_err(|err| CoreError::Network(err.to_string()))?,
+        );
+    }
+    // Defaults first, so the request's own headers replace them.
+    for (key, value) in req.default_headers.iter().chain(&req.headers) {
+        let name = HeaderName::from_bytes(key.as_bytes())
+            .map_err(|err| CoreError::Network(err.to_string()))?;
+        let value =
+            HeaderValue::from_str(value).map_err(|err| CoreError::Network(err.to_string()))?;
+        headers.insert(name, value);
+    }
+    if !req.cookies.is_empty() {
+        let cookie_value = req
+            .cookies
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<String>>()
+            .join("; ");
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(&cookie_value)
+                .map_err(|err| CoreError::Network(err.to_string()))?,
+        );
+    }
+    let range = match (req.range, req.range_from) {
+        (Some((start, end)), _) => Some(format!("bytes={}-{}", start, end)),
+        (None, Some(start)) => Some(format!("bytes={}-", start)),
+        (None, None) => None,
+    };
+    if let Some(value) = range {
+        headers.insert(
+            RANGE,
+            HeaderValue::from_str(&value).map_err(|err| CoreError::Network(err.to_string()))?,
+        );
+    }
+    Ok(headers)
+}
+
+#[cfg(feature = "net-reqwest")]
 #[derive(Clone)]
 pub struct ReqwestNetClient {
     client: Client,
+    /// Clients for each `read_timeout` seen so far, sharing their connection pools.
+    timed: Arc<Mutex<HashMap<Duration, Client>>>,
 }
 
+#[cfg(feature = "net-reqwest")]
 impl ReqwestNetClient {
     pub fn new(user_agent: &str) -> CoreResult<Self> {
         let client = Client::builder()
             .user_agent(user_agent)
             .build()
             .map_err(|err| CoreError::Network(err.to_string()))?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            timed: Arc::new(Mutex::new(HashMap::new())),
+        })
     }
 
-    fn build_client(&self, user_agent: &str, proxy: Option<&str>) -> CoreResult<Client> {
+    fn build_client(
+        &self,
+        user_agent: &str,
+        proxy: Option<&str>,
+        read_timeout: Option<Duration>,
+    ) -> CoreResult<Client> {
         let mut builder = Client::builder().user_agent(user_agent);
         if let Some(proxy_url) = proxy {
             let proxy = reqwest::Proxy::all(proxy_url)
                 .map_err(|err| CoreError::Network(err.to_string()))?;
             builder = builder.proxy(proxy);
         }
+        // The blocking client applies this to each wait for the response and to
+        // each body read.
+        if let Some(timeout) = read_timeout {
+            builder = builder.timeout(timeout);
+        }
         builder
             .build()
             .map_err(|err| CoreError::Network(err.to_string()))
     }
 
-    fn request_headers(&self, req: &DownloadRequest) -> CoreResult<HeaderMap> {
-        let mut headers = HeaderMap::new();
-        for (key, value) in &req.headers {
-            let name = HeaderName::from_bytes(key.as_bytes())
-                .map_err(|err| CoreError::Network(err.to_string()))?;
-            let value = HeaderValue::from_str(value)
-                .map_err(|err| CoreError::Network(err.to_string()))?;
-            headers.insert(name, value);
-        }
-        if !req.cookies.is_empty() {
-            let cookie_value = req
-                .cookies
-                .iter()
-                .map(|(k, v)| format!("{}={}", k, v))
-                .collect::<Vec<String>>()
-                .join("; ");
-            headers.insert(
-                reqwest::header::COOKIE,
-                HeaderValue::from_str(&cookie_value)
-                    .map_err(|err| CoreError::Network(err.to_string()))?,
-            );
-        }
-        if let Some((start, end)) = req.range {
-            let value = format!("bytes={}-{}", start, end);
-            headers.insert(
-                RANGE,
-                HeaderValue::from_str(&value).map_err(|err| CoreError::Network(err.to_string()))?,
-            );
-        }
-        Ok(headers)
-    }
-
     fn pick_client(&self, req: &DownloadRequest) -> CoreResult<Client> {
         if req.proxy.is_some() {
-            self.build_client(&req.user_agent, req.proxy.as_deref())
-        } else {
-            Ok(self.client.clone())
+            return self.build_client(&req.user_agent, req.proxy.as_deref(), req.read_timeout);
+        }
+        let Some(timeout) = req.read_timeout else {
+            return Ok(self.client.clone());
+        };
+        let mut timed = self
+            .timed
+            .lock()
+            .map_err(|_| CoreError::Network("client lock poisoned".to_string()))?;
+        if let Some(client) = timed.get(&timeout) {
+            return Ok(client.clone());
         }
+        let client = self.build_client(&req.user_agent, None, Some(timeout))?;
+        timed.insert(timeout, client.clone());
+        Ok(client)
     }
 }
 
+#[cfg(feature = "net-reqwest")]
 impl NetClient for ReqwestNetClient {
     fn head(&self, req: &DownloadRequest) -> CoreResult<DownloadResponse> {
         let client = self.pick_client(req)?;
-        let mut request = client.head(&req.url).headers(self.request_headers(req)?);
+        let mut request = client.head(&req.url).headers(request_headers(req)?);
         if let Some((user, pass)) = &req.basic_auth {
             request = request.basic_auth(user, Some(pass));
         }
+        if let Some(timeout) = req.timeout {
+            request = request.timeout(timeout);
+        }
         let resp = request
             .send()
             .map_err(|err| CoreError::Network(err.to_string()))?;
-        let status = resp.status();
-        let headers = resp.headers();
-        let total_bytes = headers
-            .get(CONTENT_LENGTH)
-            .and_then(|value| value.to_str().ok())
-            .and_then(|value| value.parse::<u64>().ok());
-        let accept_ranges = headers
-            .get(ACCEPT_RANGES)
-            .and_then(|value| value.to_str().ok())
-            .map(|value| value.eq_ignore_ascii_case("bytes"))
-            .unwrap_or(false);
-        let content_type = headers
-            .get(CONTENT_TYPE)
-            .and_then(|value| value.to_str().ok())
-            .map(|value| value.to_string());
-        let content_disposition = headers
-            .get(CONTENT_DISPOSITION)
-            .and_then(|value| value.to_str().ok())
-            .map(|value| value.to_string());
-
-        Ok(DownloadResponse {
-            status_code: status.as_u16(),
-            total_bytes,
-            accept_ranges,
-            content_type,
-            content_disposition,
-        })
+        Ok(response_info(&Response::from(resp)))
     }
 
     fn get(&self, req: &DownloadRequest) -> CoreResult<Response> {
@@ -160,12 +347,582 @@ impl NetClient for ReqwestNetClient {
 
     fn get_stream(&self, req: &DownloadRequest) -> CoreResult<Response> {
         let client = self.pick_client(req)?;
-        let mut request = client.get(&req.url).headers(self.request_headers(req)?);
+        let mut request = match req.method {
+            HttpMethod::Get => client.get(&req.url),
+            HttpMethod::Post => {
+                let request = client.post(&req.url);
+                match &req.body {
+                    Some(body) => request
+                        .header(CONTENT_TYPE, body.content_type.as_str())
+                        .body(body.data.clone()),
+                    None => request,
+                }
+            }
+        };
+        request = request.headers(request_headers(req)?);
         if let Some((user, pass)) = &req.basic_auth {
             request = request.basic_auth(user, Some(pass));
         }
+        if let Some(timeout) = req.timeout {
+            request = request.timeout(timeout);
+        }
         request
             .send()
+            .map(Response::from)
             .map_err(|err| CoreError::Network(err.to_string()))
     }
 }
+
+/// `NetClient` on `ureq`, for builds that leave out reqwest and the async runtime it
+/// pulls in.
+#[cfg(feature = "net-ureq")]
+#[derive(Clone)]
+pub struct UreqNetClient {
+    agent: ureq::Agent,
+    /// Agents for each `read_timeout` seen so far, sharing their connection pools.
+    timed: Arc<Mutex<HashMap<Duration, ureq::Agent>>>,
+}
+
+#[cfg(feature = "net-ureq")]
+impl UreqNetClient {
+    pub fn new(user_agent: &str) -> CoreResult<Self> {
+        let config = ureq::Agent::config_builder()
+            // Statuses are the engine's to interpret, as with reqwest.
+            .http_status_as_error(false)
+            .user_agent(user_agent)
+            .build();
+        Ok(Self {
+            agent: ureq::Agent::new_with_config(config),
+            timed: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    fn pick_agent(&self, req: &DownloadRequest) -> CoreResult<ureq::Agent> {
+        use ureq::unversioned::resolver::DefaultResolver;
+        use ureq::unversioned::transport::{Connector, DefaultConnector};
+
+        let Some(timeout) = req.read_timeout else {
+            return Ok(self.agent.clone());
+        };
+        let mut timed = self
+            .timed
+            .lock()
+            .map_err(|_| CoreError::Network("client lock poisoned".to_string()))?;
+        let agent = timed.entry(timeout).or_insert_with(|| {
+            ureq::Agent::with_parts(
+                self.agent.config().clone(),
+                DefaultConnector::default().chain(ReadTimeoutConnector(timeout)),
+                DefaultResolver::default(),
+            )
+        });
+        Ok(agent.clone())
+    }
+
+    fn prepare<B>(
+        &self,
+        mut request: ureq::RequestBuilder<B>,
+        req: &DownloadRequest,
+    ) -> CoreResult<ureq::RequestBuilder<B>> {
+        let mut headers = request_headers(req)?;
+        if let Some((user, pass)) = &req.basic_auth {
+            use base64::Engine;
+
+            let credentials =
+                base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+            headers.insert(
+                http::header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("Basic {}", credentials))
+                    .map_err(|err| CoreError::Network(err.to_string()))?,
+            );
+        }
+        for (name, value) in &headers {
+            request = request.header(name, value);
+        }
+        let proxy = req
+            .proxy
+            .as_deref()
+            .map(ureq::Proxy::new)
+            .transpose()
+            .map_err(|err| CoreError::Network(err.to_string()))?;
+        let mut config = request.config();
+        if proxy.is_some() {
+            config = config.proxy(proxy);
+        }
+        Ok(config.timeout_global(req.timeout).build())
+    }
+}
+
+#[cfg(feature = "net-ureq")]
+impl NetClient for UreqNetClient {
+    fn head(&self, req: &DownloadRequest) -> CoreResult<DownloadResponse> {
+        let resp = self
+            .prepare(self.pick_agent(req)?.head(&req.url), req)?
+            .call()
+            .map_err(|err| CoreError::Network(err.to_string()))?;
+        Ok(response_info(&Response::from(resp)))
+    }
+
+    fn get(&self, req: &DownloadRequest) -> CoreResult<Response> {
+        self.get_stream(req)
+    }
+
+    fn get_stream(&self, req: &DownloadRequest) -> CoreResult<Response> {
+        let agent = self.pick_agent(req)?;
+        let result = match req.method {
+            HttpMethod::Get => self.prepare(agent.get(&req.url), req)?.call(),
+            HttpMethod::Post => {
+                let request = self.prepare(agent.post(&req.url), req)?;
+                match &req.body {
+                    Some(body) => request
+                        .content_type(body.content_type.as_str())
+                        .send(body.data.as_str()),
+                    None => request.send_empty(),
+                }
+            }
+        };
+        result
+            .map(Response::from)
+            .map_err(|err| CoreError::Network(err.to_string()))
+    }
+}
+
+/// Wraps each connection so no wait for input outlasts the given timeout; ureq only
+/// bounds whole phases, such as receiving the entire body.
+#[cfg(feature = "net-ureq")]
+#[derive(Debug)]
+struct ReadTimeoutConnector(Duration);
+
+#[cfg(feature = "net-ureq")]
+impl ureq::unversioned::transport::Connector<Box<dyn ureq::unversioned::transport::Transport>>
+    for ReadTimeoutConnector
+{
+    type Out = Box<dyn ureq::unversioned::transport::Transport>;
+
+    fn connect(
+        &self,
+        _details: &ureq::unversioned::transport::ConnectionDetails,
+        chained: Option<Self::Out>,
+    ) -> Result<Option<Self::Out>, ureq::Error> {
+        Ok(chained.map(|inner| {
+            Box::new(ReadTimeoutTransport {
+                inner,
+                timeout: self.0,
+            }) as Self::Out
+        }))
+    }
+}
+
+#[cfg(feature = "net-ureq")]
+#[derive(Debug)]
+struct ReadTimeoutTransport {
+    inner: Box<dyn ureq::unversioned::transport::Transport>,
+    timeout: Duration,
+}
+
+#[cfg(feature = "net-ureq")]
+impl ureq::unversioned::transport::Transport for ReadTimeoutTransport {
+    fn buffers(&mut self) -> &mut dyn ureq::unversioned::transport::Buffers {
+        self.inner.buffers()
+    }
+
+    fn transmit_output(
+        &mut self,
+        amount: usize,
+        timeout: ureq::unversioned::transport::NextTimeout,
+    ) -> Result<(), ureq::Error> {
+        self.inner.transmit_output(amount, timeout)
+    }
+
+    fn await_input(
+        &mut self,
+        timeout: ureq::unversioned::transport::NextTimeout,
+    ) -> Result<bool, ureq::Error> {
+        use ureq::unversioned::transport::{time, NextTimeout};
+
+        let timeout = if *timeout.after > self.timeout {
+            NextTimeout {
+                after: time::Duration::Exact(self.timeout),
+                reason: ureq::Timeout::RecvBody,
+            }
+        } else {
+            timeout
+        };
+        self.inner.await_input(timeout)
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.inner.is_open()
+    }
+}
+
+/// What a HEAD tells about a resource, read from any response's status and headers.
+fn response_info(resp: &Response) -> DownloadResponse {
+    let headers = resp.headers();
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    DownloadResponse {
+        status_code: resp.status().as_u16(),
+        total_bytes: header(CONTENT_LENGTH).and_then(|value| value.parse::<u64>().ok()),
+        accept_ranges: header(ACCEPT_RANGES)
+            .is_some_and(|value| value.eq_ignore_ascii_case("bytes")),
+        content_type: header(CONTENT_TYPE),
+        content_disposition: header(CONTENT_DISPOSITION),
+        final_url: Some(resp.url().to_string()),
+        last_modified: header(LAST_MODIFIED),
+        cloudflare_challenge: is_cloudflare_challenge(headers, None),
+    }
+}
+
+/// Learns what a HEAD would from a `Range: bytes=0-0` GET, for servers that reject or
+/// misreport HEAD. The body is left unread. A `206` gives the size from `Content-Range`
+/// and proves ranges work; a `200` means the range was ignored.
+pub fn probe_with_get(net: &dyn NetClient, req: &DownloadRequest) -> CoreResult<DownloadResponse> {
+    let mut probe = req.clone();
+    probe.range = Some((0, 0));
+    probe.range_from = None;
+    let resp = net.get(&probe)?;
+    let mut info = response_info(&resp);
+    let content_range = resp
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok());
+    match info.status_code {
+        206 => {
+            info.status_code = 200;
+            info.total_bytes = content_range.and_then(content_range_total);
+            info.accept_ranges = true;
+        }
+        // Only an empty file has no byte 0.
+        416 if content_range.and_then(unsatisfied_range_total) == Some(0) => {
+            info.status_code = 200;
+            info.total_bytes = Some(0);
+        }
+        _ => info.accept_ranges = false,
+    }
+    Ok(info)
+}
+
+#[derive(Debug, Clone)]
+struct CannedResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+/// `NetClient` that serves registered bodies by exact URL instead of touching the
+/// network, so embedders can exercise the engine offline. Range requests get `206`
+/// for successful responses; unknown URLs get `404`. Clones share registrations.
+#[derive(Debug, Clone, Default)]
+pub struct StaticNetClient {
+    responses: Arc<Mutex<HashMap<String, CannedResponse>>>,
+}
+
+impl StaticNetClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(
+        &self,
+        url: impl Into<String>,
+        status: u16,
+        content_type: Option<String>,
+        body: Vec<u8>,
+    ) {
+        if let Ok(mut responses) = self.responses.lock() {
+            responses.insert(
+                url.into(),
+                CannedResponse {
+                    status,
+                    content_type,
+                    body,
+                },
+            );
+        }
+    }
+
+    fn lookup(&self, url: &str) -> CoreResult<Option<CannedResponse>> {
+        let responses = self
+            .responses
+            .lock()
+            .map_err(|_| CoreError::Network("static responses lock poisoned".to_string()))?;
+        Ok(responses.get(url).cloned())
+    }
+}
+
+impl NetClient for StaticNetClient {
+    fn head(&self, req: &DownloadRequest) -> CoreResult<DownloadResponse> {
+        let canned = self.lookup(&req.url)?;
+        Ok(DownloadResponse {
+            status_code: canned.as_ref().map(|canned| canned.status).unwrap_or(404),
+            total_bytes: canned.as_ref().map(|canned| canned.body.len() as u64),
+            accept_ranges: canned.is_some(),
+            content_type: canned.and_then(|canned| canned.content_type),
+            content_disposition: None,
+            final_url: Some(req.url.clone()),
+            last_modified: None,
+            cloudflare_challenge: false,
+        })
+    }
+
+    fn get(&self, req: &DownloadRequest) -> CoreResult<Response> {
+        self.get_stream(req)
+    }
+
+    fn get_stream(&self, req: &DownloadRequest) -> CoreResult<Response> {
+        let Some(canned) = self.lookup(&req.url)? else {
+            return static_response(&req.url, 404, Vec::new(), Vec::new());
+        };
+        let mut headers = Vec::new();
+        if let Some(content_type) = &canned.content_type {
+            headers.push((CONTENT_TYPE, content_type.clone()));
+        }
+        let len = canned.body.len() as u64;
+        let range = match (req.range, req.range_from) {
+            (Some((start, end)), _) => Some((start, end)),
+            (None, Some(start)) => Some((start, len.saturating_sub(1))),
+            (None, None) => None,
+        };
+        match range {
+            Some((start, end)) if (200..300).contains(&canned.status) => {
+                let end = end.min(len.saturating_sub(1));
+                if start >= len || start > end {
+                    headers.push((CONTENT_RANGE, format!("bytes */{}", len)));
+                    return static_response(&req.url, 416, headers, Vec::new());
+                }
+                headers.push((CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len)));
+                let body = canned.body[start as usize..=end as usize].to_vec();
+                static_response(&req.url, 206, headers, body)
+            }
+            _ => static_response(&req.url, canned.status, headers, canned.body),
+        }
+    }
+}
+
+fn static_response(
+    url: &str,
+    status: u16,
+    headers: Vec<(HeaderName, String)>,
+    body: Vec<u8>,
+) -> CoreResult<Response> {
+    let status = StatusCode::from_u16(status).map_err(|err| CoreError::Network(err.to_string()))?;
+    let mut header_map = HeaderMap::new();
+    header_map.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    for (name, value) in headers {
+        let value =
+            HeaderValue::from_str(&value).map_err(|err| CoreError::Network(err.to_string()))?;
+        header_map.insert(name, value);
+    }
+    Ok(Response::new(
+        status,
+        header_map,
+        url.to_string(),
+        Box::new(Cursor::new(body)),
+    ))
+}
+
+/// Caps simultaneous connections per hostname. Clones share the same slots, so
+/// segment workers of every task draw from one pool per host.
+#[derive(Debug, Clone)]
+pub struct HostLimiter {
+    limit: Arc<AtomicUsize>,
+    slots: Arc<(Mutex<HashMap<String, usize>>, Condvar)>,
+}
+
+/// Holds one connection slot for a host until dropped.
+pub struct HostPermit {
+    host: Option<String>,
+    slots: Arc<(Mutex<HashMap<String, usize>>, Condvar)>,
+}
+
+impl HostLimiter {
+    /// `limit` of `0` means unlimited.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: Arc::new(AtomicUsize::new(limit)),
+            slots: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+        }
+    }
+
+    /// Changes the cap for every clone. Connections already open keep their slots, and
+    /// ones opened while the cap was `0` aren't counted against the new one.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+        self.slots.1.notify_all();
+    }
+
+    /// Waits for a free slot on `url`'s host. Returns `None` if `cancelled` turns
+    /// true while waiting, so paused or canceled workers don't stay parked.
+    pub fn acquire(&self, url: &str, cancelled: impl Fn() -> bool) -> Option<HostPermit> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|parsed| parsed.host_str().map(|host| host.to_ascii_lowercase()));
+        let (Some(host), true) = (host, self.limit.load(Ordering::Relaxed) > 0) else {
+            return Some(HostPermit {
+                host: None,
+                slots: Arc::clone(&self.slots),
+            });
+        };
+
+        let (lock, freed) = &*self.slots;
+        let mut in_use = lock.lock().ok()?;
+        loop {
+            if cancelled() {
+                return None;
+            }
+            let count = in_use.entry(host.clone()).or_insert(0);
+            let limit = self.limit.load(Ordering::Relaxed);
+            if limit == 0 || *count < limit {
+                *count += 1;
+                break;
+            }
+            in_use = freed
+                .wait_timeout(in_use, Duration::from_millis(200))
+                .ok()?
+                .0;
+        }
+        Some(HostPermit {
+            host: Some(host),
+            slots: Arc::clone(&self.slots),
+        })
+    }
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        let Some(host) = self.host.take() else {
+            return;
+        };
+        let (lock, freed) = &*self.slots;
+        if let Ok(mut in_use) = lock.lock() {
+            if let Some(count) = in_use.get_mut(&host) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    in_use.remove(&host);
+                }
+            }
+        }
+        freed.notify_all();
+    }
+}
+
+/// Longest wait honored from a `Retry-After` header.
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Parses a `Retry-After` value given as delta-seconds or an HTTP-date, relative to
+/// `now`. Dates in the past give zero; anything beyond `MAX_RETRY_AFTER` is clamped.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let at = httpdate::parse_http_date(value).ok()?;
+            at.duration_since(now).unwrap_or(Duration::ZERO)
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// The `Retry-After` delay of a `429`/`503` response, if it sent one.
+pub fn retry_after(status: u16, headers: &HeaderMap) -> Option<Duration> {
+    if status != 429 && status != 503 {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// Returns the MIME boundary when `content_type` is `multipart/byteranges`.
+pub fn multipart_byteranges_boundary(content_type: &str) -> Option<String> {
+    let mut parts = content_type.split(';');
+    let mime = parts.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/byteranges") {
+        return None;
+    }
+    parts.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case("boundary") {
+            Some(value.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Skips to the first part of a `multipart/byteranges` body and returns its
+/// inclusive `Content-Range` plus a reader limited to exactly that payload.
+pub fn multipart_first_part<R: BufRead>(
+    mut reader: R,
+    boundary: &str,
+) -> CoreResult<(u64, u64, Take<R>)> {
+    let delimiter = format!("--{}", boundary);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|err| CoreError::Network(err.to_string()))?;
+        if read == 0 {
+            return Err(CoreError::Network(
+                "multipart boundary not found".to_string(),
+            ));
+        }
+        if line.trim_end() == delimiter {
+            break;
+        }
+    }
+
+    let mut range = None;
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|err| CoreError::Network(err.to_string()))?;
+        let header = line.trim_end();
+        if read == 0 || header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-range") {
+                range = parse_content_range(value);
+            }
+        }
+    }
+
+    let (start, end) = range
+        .ok_or_else(|| CoreError::Network("multipart part without Content-Range".to_string()))?;
+    let len = end.saturating_sub(start).saturating_add(1);
+    Ok((start, end, reader.take(len)))
+}
+
+/// The inclusive `(start, end)` of a `Content-Range: bytes start-end/total` value.
+pub fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let value = value.trim();
+    let value = value.strip_prefix("bytes").unwrap_or(value).trim();
+    let range = value.split('/').next()?;
+    let (start, end) = range.split_once('-')?;
+    let start = start.trim().parse::<u64>().ok()?;
+    let end = end.trim().parse::<u64>().ok()?;
+    if end < start {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// The `total` of a `Content-Range: bytes start-end/total` value; `None` for `*`.
+pub fn content_range_total(value: &str) -> Option<u64> {
+    let (range, total) = value.trim().split_once('/')?;
+    parse_content_range(range)?;
+    total.trim().parse::<u64>().ok()
+}
+
+/// The size a `416` reports in `Content-Range: bytes */total`.
+pub fn unsatisfied_range_total(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let value = value.strip_prefix("bytes").unwrap_or(value).trim();
+    value.strip_prefix("*/")?.trim().parse::<u64>().ok()
+}
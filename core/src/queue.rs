@@ -58,6 +58,10 @@ impl TaskQueue {
         self.heap.pop()
     }
 
+    pub fn contains(&self, id: &TaskId) -> bool {
+        self.heap.iter().any(|item| item.id == *id)
+    }
+
     pub fn len(&self) -> usize {
         self.heap.len()
     }
@@ -1,14 +1,33 @@
 use std::collections::HashSet;
 use std::io::Read;
+use std::time::Duration;
 
-use reqwest::header::CONTENT_TYPE;
-use reqwest::Url;
+use http::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
+use serde::Deserialize;
+use url::Url;
 
 use crate::error::{CoreError, CoreResult};
 use crate::net::{DownloadRequest, NetClient};
+use crate::task::HttpMethod;
 
 const MAX_HTML_BYTES: usize = 1024 * 1024;
 
+/// Pages declaring a larger `Content-Length` are left unread and not scraped; no landing
+/// page is this big, so the "HTML" is almost certainly something else.
+const MAX_HTML_CONTENT_LENGTH: u64 = 10 * 1024 * 1024;
+
+/// Upper bound on fetching a page, so a server trickling bytes can't hold a worker.
+const HTML_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Strings found on Cloudflare's "Just a moment..." JS challenge page.
+const CHALLENGE_MARKERS: &[&str] = &[
+    "challenge-platform",
+    "cf_chl_opt",
+    "cf-chl-",
+    "<title>Just a moment...</title>",
+    "Checking your browser",
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Provider {
     Pixeldrain,
@@ -52,6 +71,22 @@ pub fn is_html_content_type(content_type: Option<&str>) -> bool {
     value.contains("text/html") || value.contains("application/xhtml")
 }
 
+/// `cf-mitigated: challenge` is decisive; a page that merely came through Cloudflare
+/// (`cf-ray`) only counts when its body carries challenge markers.
+pub fn is_cloudflare_challenge(headers: &HeaderMap, body: Option<&str>) -> bool {
+    let mitigated = headers
+        .get("cf-mitigated")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("challenge"));
+    let marked = headers.contains_key("cf-ray")
+        && body.is_some_and(|body| CHALLENGE_MARKERS.iter().any(|marker| body.contains(marker)));
+    mitigated || marked
+}
+
+pub fn cloudflare_challenge_error() -> CoreError {
+    CoreError::Unsupported("Cloudflare challenge; browser/cookies required".to_string())
+}
+
 pub fn resolve_url_candidates(urls: Vec<String>) -> Vec<String> {
     let mut out = Vec::new();
     let mut seen = HashSet::new();
@@ -117,8 +152,11 @@ pub fn resolve_html_download(
 fn fetch_html(net: &dyn NetClient, base_req: &DownloadRequest) -> CoreResult<Option<String>> {
     let mut req = base_req.clone();
     req.range = None;
+    req.range_from = None;
+    req.timeout = Some(HTML_FETCH_TIMEOUT);
 
     let mut response = net.get_stream(&req)?;
+    let headers = response.headers().clone();
     let content_type = response
         .headers()
         .get(CONTENT_TYPE)
@@ -127,6 +165,13 @@ fn fetch_html(net: &dyn NetClient, base_req: &DownloadRequest) -> CoreResult<Opt
     if !is_html_content_type(content_type.as_deref()) {
         return Ok(None);
     }
+    let declared = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    if declared.is_some_and(|len| len > MAX_HTML_CONTENT_LENGTH) {
+        return Ok(None);
+    }
 
     let mut buf = Vec::new();
     let mut total = 0usize;
@@ -146,6 +191,10 @@ fn fetch_html(net: &dyn NetClient, base_req: &DownloadRequest) -> CoreResult<Opt
     }
 
     let html = String::from_utf8_lossy(&buf).to_string();
+    // Scraping a challenge page would only find its own script links.
+    if is_cloudflare_challenge(&headers, Some(&html)) {
+        return Err(cloudflare_challenge_error());
+    }
     Ok(Some(html))
 }
 
@@ -166,6 +215,86 @@ fn resolve_pixeldrain(url: &str) -> Option<String> {
     None
 }
 
+/// Returns the list id of a `pixeldrain.com/l/<id>` album URL.
+pub fn pixeldrain_list_id(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?.to_ascii_lowercase();
+    if host != "pixeldrain.com" && host != "www.pixeldrain.com" {
+        return None;
+    }
+
+    let segments: Vec<&str> = parsed.path().split('/').filter(|s| !s.is_empty()).collect();
+    if segments.len() >= 2 && segments[0] == "l" {
+        return Some(segments[1].to_string());
+    }
+
+    None
+}
+
+pub fn pixeldrain_list_api_url(list_id: &str) -> String {
+    format!("https://pixeldrain.com/api/list/{}", list_id)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PixeldrainFile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl PixeldrainFile {
+    pub fn download_url(&self) -> String {
+        format!("https://pixeldrain.com/api/file/{}?download", self.id)
+    }
+}
+
+#[derive(Deserialize)]
+struct PixeldrainList {
+    #[serde(default)]
+    success: bool,
+    #[serde(default)]
+    files: Vec<PixeldrainFile>,
+}
+
+pub fn parse_pixeldrain_list(body: &[u8]) -> CoreResult<Vec<PixeldrainFile>> {
+    let list: PixeldrainList = serde_json::from_slice(body)
+        .map_err(|err| CoreError::Network(format!("invalid pixeldrain list: {}", err)))?;
+    if !list.success {
+        return Err(CoreError::Network(
+            "pixeldrain list lookup failed".to_string(),
+        ));
+    }
+    Ok(list.files)
+}
+
+/// Fetches the files of a Pixeldrain list; `base_req` carries the task's headers/proxy.
+pub fn resolve_pixeldrain_list(
+    net: &dyn NetClient,
+    base_req: &DownloadRequest,
+    list_id: &str,
+) -> CoreResult<Vec<PixeldrainFile>> {
+    let mut req = base_req.clone();
+    req.url = pixeldrain_list_api_url(list_id);
+    req.method = HttpMethod::Get;
+    req.body = None;
+    req.range = None;
+    req.range_from = None;
+
+    let response = net.get(&req)?;
+    if !response.status().is_success() {
+        return Err(CoreError::Network(format!(
+            "pixeldrain list {} returned {}",
+            list_id,
+            response.status()
+        )));
+    }
+    let body = response
+        .bytes()
+        .map_err(|err| CoreError::Network(err.to_string()))?;
+    parse_pixeldrain_list(&body)
+}
+
 fn resolve_google_drive_id(url: &str) -> Option<String> {
     let parsed = Url::parse(url).ok()?;
     let host = parsed.host_str()?.to_ascii_lowercase();
@@ -1,14 +1,34 @@
-#[derive(Debug, Clone)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Admission control for active tasks. The limit can be changed while the engine runs;
+/// lowering it lets running tasks finish rather than pausing them.
+#[derive(Debug)]
 pub struct Scheduler {
-    pub max_active: usize,
+    max_active: AtomicUsize,
 }
 
 impl Scheduler {
     pub fn new(max_active: usize) -> Self {
-        Self { max_active }
+        Self {
+            max_active: AtomicUsize::new(max_active),
+        }
+    }
+
+    pub fn max_active(&self) -> usize {
+        self.max_active.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_active(&self, max_active: usize) {
+        self.max_active.store(max_active, Ordering::Relaxed);
     }
 
     pub fn can_start(&self, active_count: usize) -> bool {
-        active_count < self.max_active
+        active_count < self.max_active()
+    }
+}
+
+impl Clone for Scheduler {
+    fn clone(&self) -> Self {
+        Self::new(self.max_active())
     }
 }
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::config::SegmentStrategy;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum SegmentStatus {
     Pending,
@@ -43,6 +45,10 @@ pub struct Segment {
     pub range_end: u64,
     pub downloaded_bytes: u64,
     pub status: SegmentStatus,
+    /// Hex SHA-256 of the segment's bytes, recorded when it completes if
+    /// `EngineConfig::segment_checksums` is on.
+    #[serde(default)]
+    pub sha256: Option<String>,
 }
 
 impl Segment {
@@ -53,6 +59,7 @@ impl Segment {
             range_end,
             downloaded_bytes: 0,
             status: SegmentStatus::Pending,
+            sha256: None,
         }
     }
 
@@ -65,28 +72,69 @@ impl Segment {
     }
 }
 
+/// `(max_bytes, connections)`: files up to `max_bytes` get `connections` segments.
+pub const DEFAULT_CONCURRENCY_BREAKPOINTS: &[(u64, u32)] = &[
+    (20_971_520, 1),    // up to 20MB: 1 connection
+    (209_715_200, 4),   // 20MB - 200MB: 4 connections
+    (2_147_483_648, 8), // 200MB - 2GB: 8 connections
+    (u64::MAX, 16),     // > 2GB: 16 connections
+];
+
 pub fn calculate_smart_concurrency(total_bytes: u64) -> u32 {
-    match total_bytes {
-        0..=20_971_520 => 1,             // < 20MB: 1 connection
-        20_971_521..=209_715_200 => 4,   // 20MB - 200MB: 4 connections
-        209_715_201..=2_147_483_648 => 8, // 200MB - 2GB: 8 connections
-        _ => 16,                         // > 2GB: 16 connections
+    concurrency_for(total_bytes, DEFAULT_CONCURRENCY_BREAKPOINTS)
+}
+
+/// Connections for the first breakpoint whose `max_bytes` covers `total_bytes`; files
+/// past the last breakpoint get its count too.
+pub fn concurrency_for(total_bytes: u64, breakpoints: &[(u64, u32)]) -> u32 {
+    breakpoints
+        .iter()
+        .find(|(max_bytes, _)| total_bytes <= *max_bytes)
+        .or(breakpoints.last())
+        .map_or(1, |(_, connections)| *connections)
+}
+
+/// Breakpoints must be non-empty, sorted by strictly increasing `max_bytes`, and give
+/// every range at least one connection.
+pub fn validate_breakpoints(breakpoints: &[(u64, u32)]) -> Result<(), String> {
+    if breakpoints.is_empty() {
+        return Err("concurrency breakpoints must not be empty".to_string());
+    }
+    if breakpoints.iter().any(|(_, connections)| *connections == 0) {
+        return Err("concurrency breakpoints need at least 1 connection each".to_string());
+    }
+    if breakpoints.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return Err("concurrency breakpoints must be sorted without overlaps".to_string());
     }
+    Ok(())
 }
 
-pub fn build_segments(total_bytes: u64, max_segments: u32, min_segment_size: u64) -> Vec<Segment> {
+/// Splits `total_bytes` into segments. `max_segments` and `breakpoints` only apply to
+/// the `Smart` (and, for now, `Adaptive`) strategy; `min_segment_size` to all of them.
+pub fn build_segments(
+    total_bytes: u64,
+    strategy: SegmentStrategy,
+    max_segments: u32,
+    min_segment_size: u64,
+    breakpoints: &[(u64, u32)],
+) -> Vec<Segment> {
     if total_bytes == 0 {
         return vec![Segment::new(0, 0, 0)];
     }
 
-    // 1. Determine smart concurrency based on file size
-    let smart_count = calculate_smart_concurrency(total_bytes);
-
-    // 2. Clamp by user configuration (max_segments)
-    let mut target_count = if smart_count > max_segments {
-        max_segments
-    } else {
-        smart_count
+    let mut target_count = match strategy {
+        SegmentStrategy::Fixed(count) => count,
+        SegmentStrategy::Smart | SegmentStrategy::Adaptive => {
+            // 1. Determine smart concurrency based on file size
+            let smart_count = concurrency_for(total_bytes, breakpoints);
+
+            // 2. Clamp by user configuration (max_segments)
+            if smart_count > max_segments {
+                max_segments
+            } else {
+                smart_count
+            }
+        }
     };
 
     // 3. Ensure we don't violate min_segment_size (unless it forces 1 segment)
@@ -124,3 +172,49 @@ pub fn build_segments(total_bytes: u64, max_segments: u32, min_segment_size: u64
 
     segments
 }
+
+/// Whether `segments` cover `0..total_bytes` exactly once, with no gaps or overlaps and
+/// no segment claiming more bytes than its range. Only such a layout can be resumed.
+pub fn tiles_file(segments: &[Segment], total_bytes: u64) -> bool {
+    if total_bytes == 0 || segments.is_empty() {
+        return false;
+    }
+    let mut ranges: Vec<&Segment> = segments.iter().collect();
+    ranges.sort_unstable_by_key(|segment| segment.range_start);
+    let mut next = 0u64;
+    for segment in ranges {
+        if segment.range_start != next
+            || segment.range_end < segment.range_start
+            || segment.downloaded_bytes > segment.size()
+        {
+            return false;
+        }
+        next = segment.range_end + 1;
+    }
+    next == total_bytes
+}
+
+/// Carries progress from an old layout into a rebuilt one for the same file: each new
+/// segment keeps the bytes already written contiguously from its start, so only the
+/// rest is fetched again.
+pub fn carry_over_progress(old: &[Segment], new: &mut [Segment]) {
+    let mut written: Vec<(u64, u64)> = old
+        .iter()
+        .filter(|segment| segment.downloaded_bytes > 0)
+        .map(|segment| {
+            let end = segment.range_start + segment.downloaded_bytes.min(segment.size());
+            (segment.range_start, end)
+        })
+        .collect();
+    written.sort_unstable();
+
+    for segment in new {
+        let mut reach = segment.range_start;
+        for &(start, end) in &written {
+            if start <= reach && end > reach {
+                reach = end;
+            }
+        }
+        segment.downloaded_bytes = (reach - segment.range_start).min(segment.size());
+    }
+}
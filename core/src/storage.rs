@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::checksum::{ChecksumRequest, ChecksumType};
 use crate::error::{CoreError, CoreResult};
 use crate::segment::{Segment, SegmentStatus};
-use crate::task::{Task, TaskId, TaskStatus};
+use crate::task::{
+    now_epoch, HttpMethod, PauseReason, Task, TaskId, TaskStatus, TaskSummary, TASK_SCHEMA_VERSION,
+};
 
 #[cfg(feature = "sqlite")]
 use rusqlite::params;
@@ -11,17 +13,89 @@ use rusqlite::params;
 pub trait Storage: Send + Sync {
     fn save_task(&mut self, task: &Task) -> CoreResult<()>;
     fn load_task(&self, id: &TaskId) -> CoreResult<Task>;
+    /// Oldest first, by `created_at` and then id, so repeated listings agree.
     fn list_tasks(&self) -> CoreResult<Vec<Task>>;
+
+    /// Id, status and progress of every task, without headers/cookies/mirrors.
+    fn list_task_summaries(&self) -> CoreResult<Vec<TaskSummary>> {
+        Ok(self.list_tasks()?.iter().map(TaskSummary::from).collect())
+    }
+
+    /// Tasks whose `updated_at` is strictly later than `since` (epoch seconds).
+    fn list_tasks_updated_since(&self, since: u64) -> CoreResult<Vec<Task>> {
+        Ok(self
+            .list_tasks()?
+            .into_iter()
+            .filter(|task| task.updated_at > since)
+            .collect())
+    }
+
+    /// Tasks whose `url` or one of whose mirrors is exactly `url`.
+    fn find_by_url(&self, url: &str) -> CoreResult<Vec<Task>> {
+        Ok(self
+            .list_tasks()?
+            .into_iter()
+            .filter(|task| task.url == url || task.mirrors.iter().any(|mirror| mirror == url))
+            .collect())
+    }
+
     fn delete_task(&mut self, id: &TaskId) -> CoreResult<()>;
 
+    /// Records download progress without rewriting the rest of the task.
+    fn update_progress(
+        &mut self,
+        id: &TaskId,
+        downloaded_bytes: u64,
+        total_bytes: u64,
+    ) -> CoreResult<()> {
+        let mut task = self.load_task(id)?;
+        task.downloaded_bytes = downloaded_bytes;
+        task.total_bytes = total_bytes;
+        task.touch();
+        self.save_task(&task)
+    }
+
+    /// Replaces the task's whole segment layout.
     fn save_segments(&mut self, task_id: &TaskId, segments: &[Segment]) -> CoreResult<()>;
+
+    /// Records progress and status of segments whose layout is already saved. Storage
+    /// may update rows in place; segments it doesn't know are saved as a new layout.
+    fn update_segments(&mut self, task_id: &TaskId, segments: &[Segment]) -> CoreResult<()> {
+        self.save_segments(task_id, segments)
+    }
+
     fn load_segments(&self, task_id: &TaskId) -> CoreResult<Vec<Segment>>;
+
+    /// Adds `bytes` to the total for `day` (days since the Unix epoch, UTC). Totals are
+    /// independent of tasks and survive their removal. Storage without a stats table
+    /// keeps nothing.
+    fn add_downloaded_bytes(&mut self, _day: u64, _bytes: u64) -> CoreResult<()> {
+        Ok(())
+    }
+
+    /// `(day, bytes)` for every day with recorded downloads, oldest first.
+    fn daily_bytes(&self) -> CoreResult<Vec<(u64, u64)>> {
+        Ok(Vec::new())
+    }
+
+    fn reset_stats(&mut self) -> CoreResult<()> {
+        Ok(())
+    }
 }
 
 #[derive(Default)]
 pub struct MemoryStorage {
     tasks: HashMap<TaskId, Task>,
     segments: HashMap<TaskId, Vec<Segment>>,
+    daily_bytes: BTreeMap<u64, u64>,
+}
+
+impl MemoryStorage {
+    fn sorted_tasks(&self) -> Vec<&Task> {
+        let mut tasks: Vec<&Task> = self.tasks.values().collect();
+        tasks.sort_by_key(|task| (task.created_at, task.id));
+        tasks
+    }
 }
 
 impl Storage for MemoryStorage {
@@ -38,7 +112,15 @@ impl Storage for MemoryStorage {
     }
 
     fn list_tasks(&self) -> CoreResult<Vec<Task>> {
-        Ok(self.tasks.values().cloned().collect())
+        Ok(self.sorted_tasks().into_iter().cloned().collect())
+    }
+
+    fn list_task_summaries(&self) -> CoreResult<Vec<TaskSummary>> {
+        Ok(self
+            .sorted_tasks()
+            .into_iter()
+            .map(TaskSummary::from)
+            .collect())
     }
 
     fn delete_task(&mut self, id: &TaskId) -> CoreResult<()> {
@@ -47,6 +129,22 @@ impl Storage for MemoryStorage {
         Ok(())
     }
 
+    fn update_progress(
+        &mut self,
+        id: &TaskId,
+        downloaded_bytes: u64,
+        total_bytes: u64,
+    ) -> CoreResult<()> {
+        let task = self
+            .tasks
+            .get_mut(id)
+            .ok_or_else(|| CoreError::NotFound(id.to_string()))?;
+        task.downloaded_bytes = downloaded_bytes;
+        task.total_bytes = total_bytes;
+        task.touch();
+        Ok(())
+    }
+
     fn save_segments(&mut self, task_id: &TaskId, segments: &[Segment]) -> CoreResult<()> {
         self.segments.insert(*task_id, segments.to_vec());
         Ok(())
@@ -59,6 +157,24 @@ impl Storage for MemoryStorage {
             .cloned()
             .unwrap_or_default())
     }
+
+    fn add_downloaded_bytes(&mut self, day: u64, bytes: u64) -> CoreResult<()> {
+        *self.daily_bytes.entry(day).or_default() += bytes;
+        Ok(())
+    }
+
+    fn daily_bytes(&self) -> CoreResult<Vec<(u64, u64)>> {
+        Ok(self
+            .daily_bytes
+            .iter()
+            .map(|(day, bytes)| (*day, *bytes))
+            .collect())
+    }
+
+    fn reset_stats(&mut self) -> CoreResult<()> {
+        self.daily_bytes.clear();
+        Ok(())
+    }
 }
 
 #[cfg(feature = "sqlite")]
@@ -98,7 +214,21 @@ impl SqliteStorage {
                 checksum_hex TEXT,
                 proxy_url TEXT,
                 auth_user TEXT,
-                auth_pass TEXT
+                auth_pass TEXT,
+                auto_extract INTEGER NOT NULL DEFAULT 0,
+                pause_reason TEXT,
+                user_agent TEXT,
+                torrent TEXT,
+                skip_resolution INTEGER NOT NULL DEFAULT 0,
+                restart_count INTEGER NOT NULL DEFAULT 0,
+                restart_at INTEGER NOT NULL DEFAULT 0,
+                byte_limit INTEGER,
+                partial INTEGER NOT NULL DEFAULT 0,
+                resolved_url TEXT,
+                skip_head INTEGER NOT NULL DEFAULT 0,
+                depends_on TEXT,
+                method TEXT NOT NULL DEFAULT 'GET',
+                body TEXT
             );
             CREATE TABLE IF NOT EXISTS segments (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -108,6 +238,7 @@ impl SqliteStorage {
                 range_end INTEGER NOT NULL,
                 downloaded_bytes INTEGER NOT NULL DEFAULT 0,
                 status TEXT NOT NULL,
+                sha256 TEXT,
                 FOREIGN KEY(task_id) REFERENCES tasks(id)
             );
             CREATE TABLE IF NOT EXISTS headers (
@@ -141,13 +272,105 @@ impl SqliteStorage {
                 created_at INTEGER NOT NULL,
                 FOREIGN KEY(task_id) REFERENCES tasks(id)
             );
+            CREATE TABLE IF NOT EXISTS stats (
+                day INTEGER PRIMARY KEY,
+                bytes INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE INDEX IF NOT EXISTS idx_tasks_updated_at ON tasks(updated_at);
+            CREATE INDEX IF NOT EXISTS idx_tasks_url ON tasks(url);
+            CREATE INDEX IF NOT EXISTS idx_mirrors_url ON mirrors(url);
+            CREATE INDEX IF NOT EXISTS idx_segments_task ON segments(task_id, segment_index);
             ",
         )
         .map_err(|err| CoreError::Storage(err.to_string()))?;
+        ensure_column(&conn, "tasks", "auto_extract", "INTEGER NOT NULL DEFAULT 0")?;
+        ensure_column(&conn, "tasks", "pause_reason", "TEXT")?;
+        ensure_column(&conn, "tasks", "user_agent", "TEXT")?;
+        ensure_column(&conn, "tasks", "torrent", "TEXT")?;
+        ensure_column(
+            &conn,
+            "tasks",
+            "skip_resolution",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        ensure_column(
+            &conn,
+            "tasks",
+            "restart_count",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        ensure_column(&conn, "tasks", "restart_at", "INTEGER NOT NULL DEFAULT 0")?;
+        ensure_column(&conn, "tasks", "byte_limit", "INTEGER")?;
+        ensure_column(&conn, "tasks", "partial", "INTEGER NOT NULL DEFAULT 0")?;
+        ensure_column(&conn, "tasks", "resolved_url", "TEXT")?;
+        ensure_column(&conn, "tasks", "skip_head", "INTEGER NOT NULL DEFAULT 0")?;
+        ensure_column(&conn, "tasks", "depends_on", "TEXT")?;
+        ensure_column(&conn, "tasks", "method", "TEXT NOT NULL DEFAULT 'GET'")?;
+        ensure_column(&conn, "tasks", "body", "TEXT")?;
+        ensure_column(&conn, "segments", "sha256", "TEXT")?;
         Ok(())
     }
 }
 
+/// Deletes the task's segment rows and inserts `segments` in their place.
+#[cfg(feature = "sqlite")]
+fn rewrite_segments(
+    tx: &rusqlite::Transaction,
+    task_id: &TaskId,
+    segments: &[Segment],
+) -> CoreResult<()> {
+    tx.execute(
+        "DELETE FROM segments WHERE task_id = ?1",
+        params![task_id.to_string()],
+    )
+    .map_err(|err| CoreError::Storage(err.to_string()))?;
+    for segment in segments {
+        tx.execute(
+            "
+            INSERT INTO segments (task_id, segment_index, range_start, range_end, downloaded_bytes, status, sha256)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ",
+            params![
+                task_id.to_string(),
+                segment.index as i64,
+                segment.range_start as i64,
+                segment.range_end as i64,
+                segment.downloaded_bytes as i64,
+                segment.status.as_str(),
+                segment.sha256,
+            ],
+        )
+        .map_err(|err| CoreError::Storage(err.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Adds `column` to databases created before it existed.
+#[cfg(feature = "sqlite")]
+fn ensure_column(
+    conn: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> CoreResult<()> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|err| CoreError::Storage(err.to_string()))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|err| CoreError::Storage(err.to_string()))?
+        .filter_map(Result::ok)
+        .any(|name| name == column);
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .map_err(|err| CoreError::Storage(err.to_string()))?;
+    }
+    Ok(())
+}
+
 #[cfg(feature = "sqlite")]
 impl Storage for SqliteStorage {
     fn save_task(&mut self, task: &Task) -> CoreResult<()> {
@@ -160,14 +383,28 @@ impl Storage for SqliteStorage {
             Some(req) => (Some(req.checksum_type.as_str()), Some(req.expected_hex.as_str())),
             None => (None, None),
         };
+        let torrent = task
+            .torrent
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|err| CoreError::Serialization(err.to_string()))?;
+        let body = task
+            .body
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|err| CoreError::Serialization(err.to_string()))?;
 
         tx.execute(
             "
             INSERT INTO tasks (
                 id, url, dest_path, status, priority, total_bytes, downloaded_bytes,
                 created_at, updated_at, error, checksum_type, checksum_hex, proxy_url,
-                auth_user, auth_pass
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+                auth_user, auth_pass, auto_extract, pause_reason, user_agent, torrent,
+                skip_resolution, restart_count, restart_at, byte_limit, partial, resolved_url,
+                skip_head, depends_on, method, body
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29)
             ON CONFLICT(id) DO UPDATE SET
                 url=excluded.url,
                 dest_path=excluded.dest_path,
@@ -182,7 +419,21 @@ impl Storage for SqliteStorage {
                 checksum_hex=excluded.checksum_hex,
                 proxy_url=excluded.proxy_url,
                 auth_user=excluded.auth_user,
-                auth_pass=excluded.auth_pass
+                auth_pass=excluded.auth_pass,
+                auto_extract=excluded.auto_extract,
+                pause_reason=excluded.pause_reason,
+                user_agent=excluded.user_agent,
+                torrent=excluded.torrent,
+                skip_resolution=excluded.skip_resolution,
+                restart_count=excluded.restart_count,
+                restart_at=excluded.restart_at,
+                byte_limit=excluded.byte_limit,
+                partial=excluded.partial,
+                resolved_url=excluded.resolved_url,
+                skip_head=excluded.skip_head,
+                depends_on=excluded.depends_on,
+                method=excluded.method,
+                body=excluded.body
             ",
             params![
                 task.id.to_string(),
@@ -200,6 +451,20 @@ impl Storage for SqliteStorage {
                 task.proxy_url.as_deref(),
                 task.auth_user.as_deref(),
                 task.auth_pass.as_deref(),
+                task.auto_extract,
+                task.pause_reason.map(|reason| reason.as_str()),
+                task.user_agent.as_deref(),
+                torrent,
+                task.skip_resolution,
+                task.restart_count,
+                task.restart_at as i64,
+                task.byte_limit.map(|limit| limit as i64),
+                task.partial,
+                task.resolved_url.as_deref(),
+                task.skip_head,
+                task.depends_on.map(|id| id.to_string()),
+                task.method.as_str(),
+                body,
             ],
         )
         .map_err(|err| CoreError::Storage(err.to_string()))?;
@@ -248,7 +513,9 @@ impl Storage for SqliteStorage {
                 "
                 SELECT id, url, dest_path, status, priority, total_bytes, downloaded_bytes,
                        created_at, updated_at, error, checksum_type, checksum_hex, proxy_url,
-                       auth_user, auth_pass
+                       auth_user, auth_pass, auto_extract, pause_reason, user_agent, torrent,
+                       skip_resolution, restart_count, restart_at, byte_limit, partial,
+                       resolved_url, skip_head, depends_on, method, body
                 FROM tasks WHERE id = ?1
                 ",
             )
@@ -262,15 +529,15 @@ impl Storage for SqliteStorage {
                 let checksum_type: Option<String> = row.get(10)?;
                 let checksum_hex: Option<String> = row.get(11)?;
                 let checksum = match (checksum_type, checksum_hex) {
-                    (Some(t), Some(hex)) => ChecksumType::from_str(&t)
-                        .map(|checksum_type| ChecksumRequest {
-                            checksum_type,
-                            expected_hex: hex,
-                        }),
+                    (Some(t), Some(hex)) => Some(ChecksumRequest {
+                        checksum_type: ChecksumType::from_name(&t),
+                        expected_hex: hex,
+                    }),
                     _ => None,
                 };
 
                 Ok(Task {
+                    schema_version: TASK_SCHEMA_VERSION,
                     id: TaskId::parse_str(row.get::<_, String>(0)?.as_str())
                         .map_err(|_| rusqlite::Error::InvalidQuery)?,
                     url: row.get(1)?,
@@ -286,6 +553,28 @@ impl Storage for SqliteStorage {
                     proxy_url: row.get(12)?,
                     auth_user: row.get(13)?,
                     auth_pass: row.get(14)?,
+                    user_agent: row.get(17)?,
+                    auto_extract: row.get(15)?,
+                    skip_resolution: row.get(19)?,
+                    restart_count: row.get(20)?,
+                    restart_at: row.get::<_, i64>(21)? as u64,
+                    byte_limit: row.get::<_, Option<i64>>(22)?.map(|limit| limit as u64),
+                    partial: row.get(23)?,
+                    resolved_url: row.get(24)?,
+                    skip_head: row.get(25)?,
+                    depends_on: row
+                        .get::<_, Option<String>>(26)?
+                        .and_then(|id| TaskId::parse_str(&id).ok()),
+                    method: HttpMethod::from_str(&row.get::<_, String>(27)?).unwrap_or_default(),
+                    body: row
+                        .get::<_, Option<String>>(28)?
+                        .and_then(|json| serde_json::from_str(&json).ok()),
+                    torrent: row
+                        .get::<_, Option<String>>(18)?
+                        .and_then(|json| serde_json::from_str(&json).ok()),
+                    pause_reason: row
+                        .get::<_, Option<String>>(16)?
+                        .and_then(|reason| PauseReason::from_str(&reason)),
                     created_at: row.get::<_, i64>(7)? as u64,
                     updated_at: row.get::<_, i64>(8)? as u64,
                     error: row.get(9)?,
@@ -339,7 +628,7 @@ impl Storage for SqliteStorage {
     fn list_tasks(&self) -> CoreResult<Vec<Task>> {
         let conn = self.conn()?;
         let mut stmt = conn
-            .prepare("SELECT id FROM tasks")
+            .prepare("SELECT id FROM tasks ORDER BY created_at, id")
             .map_err(|err| CoreError::Storage(err.to_string()))?;
         let ids = stmt
             .query_map([], |row| row.get::<_, String>(0))
@@ -354,11 +643,81 @@ impl Storage for SqliteStorage {
         Ok(tasks)
     }
 
+    fn list_tasks_updated_since(&self, since: u64) -> CoreResult<Vec<Task>> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT id FROM tasks WHERE updated_at > ?1 ORDER BY created_at, id")
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        let ids = stmt
+            .query_map(params![since as i64], |row| row.get::<_, String>(0))
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+
+        let mut tasks = Vec::new();
+        for id in ids {
+            let id = id.map_err(|err| CoreError::Storage(err.to_string()))?;
+            let task_id = TaskId::parse_str(&id).map_err(|_| CoreError::Storage(id))?;
+            tasks.push(self.load_task(&task_id)?);
+        }
+        Ok(tasks)
+    }
+
+    fn find_by_url(&self, url: &str) -> CoreResult<Vec<Task>> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id FROM tasks WHERE url = ?1
+                 UNION SELECT task_id FROM mirrors WHERE url = ?1",
+            )
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        let ids = stmt
+            .query_map(params![url], |row| row.get::<_, String>(0))
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+
+        let mut tasks = Vec::new();
+        for id in ids {
+            let id = id.map_err(|err| CoreError::Storage(err.to_string()))?;
+            let task_id = TaskId::parse_str(&id).map_err(|_| CoreError::Storage(id))?;
+            tasks.push(self.load_task(&task_id)?);
+        }
+        Ok(tasks)
+    }
+
+    fn list_task_summaries(&self) -> CoreResult<Vec<TaskSummary>> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, status, url, dest_path, priority, downloaded_bytes, total_bytes
+                 FROM tasks ORDER BY created_at, id",
+            )
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        let rows = stmt
+            .query_map([], |row| {
+                let status: String = row.get(1)?;
+                Ok(TaskSummary {
+                    id: TaskId::parse_str(row.get::<_, String>(0)?.as_str())
+                        .map_err(|_| rusqlite::Error::InvalidQuery)?,
+                    status: TaskStatus::from_str(&status).ok_or(rusqlite::Error::InvalidQuery)?,
+                    url: row.get(2)?,
+                    dest_path: row.get(3)?,
+                    priority: row.get(4)?,
+                    downloaded_bytes: row.get::<_, i64>(5)? as u64,
+                    total_bytes: row.get::<_, i64>(6)? as u64,
+                })
+            })
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|err| CoreError::Storage(err.to_string()))
+    }
+
     fn delete_task(&mut self, id: &TaskId) -> CoreResult<()> {
         let mut conn = self.conn()?;
         let tx = conn
             .transaction()
             .map_err(|err| CoreError::Storage(err.to_string()))?;
+        // Child rows still reference the task until the transaction commits.
+        tx.execute_batch("PRAGMA defer_foreign_keys = ON")
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
         tx.execute("DELETE FROM tasks WHERE id = ?1", params![id.to_string()])
             .map_err(|err| CoreError::Storage(err.to_string()))?;
         tx.execute("DELETE FROM headers WHERE task_id = ?1", params![id.to_string()])
@@ -374,32 +733,79 @@ impl Storage for SqliteStorage {
         Ok(())
     }
 
+    fn update_progress(
+        &mut self,
+        id: &TaskId,
+        downloaded_bytes: u64,
+        total_bytes: u64,
+    ) -> CoreResult<()> {
+        let conn = self.conn()?;
+        let updated = conn
+            .execute(
+                "UPDATE tasks SET downloaded_bytes = ?1, total_bytes = ?2, updated_at = ?3 WHERE id = ?4",
+                params![
+                    downloaded_bytes as i64,
+                    total_bytes as i64,
+                    now_epoch() as i64,
+                    id.to_string(),
+                ],
+            )
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        if updated == 0 {
+            return Err(CoreError::NotFound(id.to_string()));
+        }
+        Ok(())
+    }
+
     fn save_segments(&mut self, task_id: &TaskId, segments: &[Segment]) -> CoreResult<()> {
         let mut conn = self.conn()?;
         let tx = conn
             .transaction()
             .map_err(|err| CoreError::Storage(err.to_string()))?;
-        tx.execute(
-            "DELETE FROM segments WHERE task_id = ?1",
-            params![task_id.to_string()],
-        )
-        .map_err(|err| CoreError::Storage(err.to_string()))?;
-        for segment in segments {
-            tx.execute(
-                "
-                INSERT INTO segments (task_id, segment_index, range_start, range_end, downloaded_bytes, status)
-                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-                ",
-                params![
-                    task_id.to_string(),
-                    segment.index as i64,
-                    segment.range_start as i64,
-                    segment.range_end as i64,
-                    segment.downloaded_bytes as i64,
-                    segment.status.as_str(),
-                ],
-            )
+        rewrite_segments(&tx, task_id, segments)?;
+        tx.commit()
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        Ok(())
+    }
+
+    /// Updates each row in place, so concurrent flushes of one task don't delete and
+    /// re-insert the whole layout every time.
+    fn update_segments(&mut self, task_id: &TaskId, segments: &[Segment]) -> CoreResult<()> {
+        let mut conn = self.conn()?;
+        let tx = conn
+            .transaction()
             .map_err(|err| CoreError::Storage(err.to_string()))?;
+        let mut complete = true;
+        {
+            let mut stmt = tx
+                .prepare_cached(
+                    "
+                    UPDATE segments SET downloaded_bytes = ?1, status = ?2, sha256 = ?7
+                    WHERE task_id = ?3 AND segment_index = ?4
+                      AND range_start = ?5 AND range_end = ?6
+                    ",
+                )
+                .map_err(|err| CoreError::Storage(err.to_string()))?;
+            for segment in segments {
+                let updated = stmt
+                    .execute(params![
+                        segment.downloaded_bytes as i64,
+                        segment.status.as_str(),
+                        task_id.to_string(),
+                        segment.index as i64,
+                        segment.range_start as i64,
+                        segment.range_end as i64,
+                        segment.sha256,
+                    ])
+                    .map_err(|err| CoreError::Storage(err.to_string()))?;
+                if updated != 1 {
+                    complete = false;
+                    break;
+                }
+            }
+        }
+        if !complete {
+            rewrite_segments(&tx, task_id, segments)?;
         }
         tx.commit()
             .map_err(|err| CoreError::Storage(err.to_string()))?;
@@ -411,7 +817,7 @@ impl Storage for SqliteStorage {
         let mut stmt = conn
             .prepare(
                 "
-                SELECT segment_index, range_start, range_end, downloaded_bytes, status
+                SELECT segment_index, range_start, range_end, downloaded_bytes, status, sha256
                 FROM segments WHERE task_id = ?1 ORDER BY segment_index ASC
                 ",
             )
@@ -427,6 +833,7 @@ impl Storage for SqliteStorage {
                     range_end: row.get::<_, i64>(2)? as u64,
                     downloaded_bytes: row.get::<_, i64>(3)? as u64,
                     status,
+                    sha256: row.get(5)?,
                 })
             })
             .map_err(|err| CoreError::Storage(err.to_string()))?;
@@ -437,4 +844,38 @@ impl Storage for SqliteStorage {
         }
         Ok(segments)
     }
+
+    fn add_downloaded_bytes(&mut self, day: u64, bytes: u64) -> CoreResult<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "
+            INSERT INTO stats (day, bytes) VALUES (?1, ?2)
+            ON CONFLICT(day) DO UPDATE SET bytes = bytes + excluded.bytes
+            ",
+            params![day as i64, bytes as i64],
+        )
+        .map_err(|err| CoreError::Storage(err.to_string()))?;
+        Ok(())
+    }
+
+    fn daily_bytes(&self) -> CoreResult<Vec<(u64, u64)>> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT day, bytes FROM stats ORDER BY day ASC")
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64))
+            })
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|err| CoreError::Storage(err.to_string()))
+    }
+
+    fn reset_stats(&mut self) -> CoreResult<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM stats", [])
+            .map_err(|err| CoreError::Storage(err.to_string()))?;
+        Ok(())
+    }
 }
@@ -1,16 +1,29 @@
+use percent_encoding::percent_decode_str;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use url::Url;
 use uuid::Uuid;
 
 use crate::checksum::ChecksumRequest;
+use crate::error::{CoreError, CoreResult};
+use crate::torrent::TorrentInfo;
 
 pub type TaskId = Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// Version of the serialized `Task` layout. Bump when a field's meaning changes and
+/// teach `Task::upgrade` how to bring older payloads forward.
+pub const TASK_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TaskStatus {
+    #[default]
     Queued,
+    /// Started, but still probing mirrors / resolving pages; no bytes have flowed yet.
+    Resolving,
     Active,
     Paused,
     Completed,
@@ -22,6 +35,7 @@ impl TaskStatus {
     pub fn as_str(&self) -> &'static str {
         match self {
             TaskStatus::Queued => "queued",
+            TaskStatus::Resolving => "resolving",
             TaskStatus::Active => "active",
             TaskStatus::Paused => "paused",
             TaskStatus::Completed => "completed",
@@ -33,6 +47,7 @@ impl TaskStatus {
     pub fn from_str(value: &str) -> Option<Self> {
         match value {
             "queued" => Some(TaskStatus::Queued),
+            "resolving" => Some(TaskStatus::Resolving),
             "active" => Some(TaskStatus::Active),
             "paused" => Some(TaskStatus::Paused),
             "completed" => Some(TaskStatus::Completed),
@@ -41,6 +56,76 @@ impl TaskStatus {
             _ => None,
         }
     }
+
+    /// Whether a task may move from this status to `next`. `Completed` and `Canceled` are
+    /// final; `Paused` and `Failed` only go back to the queue or get canceled. Recovering a
+    /// task whose worker died is not a transition and bypasses this.
+    pub fn can_transition_to(&self, next: &TaskStatus) -> bool {
+        use TaskStatus::*;
+        match self {
+            Queued => matches!(next, Resolving | Failed | Canceled),
+            Resolving => matches!(next, Active | Paused | Completed | Failed | Canceled),
+            Active => matches!(next, Paused | Completed | Failed | Canceled),
+            Paused | Failed => matches!(next, Queued | Canceled),
+            Completed | Canceled => false,
+        }
+    }
+}
+
+/// Why a task is `Paused`; only `System` pauses are resumed automatically.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PauseReason {
+    User,
+    System,
+}
+
+impl PauseReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PauseReason::User => "user",
+            PauseReason::System => "system",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "user" => Some(PauseReason::User),
+            "system" => Some(PauseReason::System),
+            _ => None,
+        }
+    }
+}
+
+/// How a task's own URL and mirrors are requested.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HttpMethod {
+    #[default]
+    Get,
+    /// Sends `Task::body`, for hosts that only hand out files to a form submission.
+    Post,
+}
+
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "GET" => Some(HttpMethod::Get),
+            "POST" => Some(HttpMethod::Post),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RequestBody {
+    pub content_type: String,
+    pub data: String,
 }
 
 impl fmt::Display for TaskStatus {
@@ -49,31 +134,139 @@ impl fmt::Display for TaskStatus {
     }
 }
 
+/// Only `id`, `url` and `dest_path` are required when deserializing; every other
+/// field falls back to its default so older or trimmed-down JSON still loads.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Task {
+    /// `0` for payloads written before versioning; see `Task::upgrade`.
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: TaskId,
     pub url: String,
     pub dest_path: String,
+    #[serde(default)]
     pub status: TaskStatus,
+    #[serde(default)]
     pub priority: i32,
+    #[serde(default)]
     pub total_bytes: u64,
+    #[serde(default)]
     pub downloaded_bytes: u64,
+    #[serde(default)]
     pub headers: HashMap<String, String>,
+    #[serde(default)]
     pub cookies: HashMap<String, String>,
+    #[serde(default)]
     pub mirrors: Vec<String>,
+    #[serde(default)]
     pub checksum: Option<ChecksumRequest>,
+    #[serde(default)]
     pub proxy_url: Option<String>,
+    #[serde(default)]
     pub auth_user: Option<String>,
+    #[serde(default)]
     pub auth_pass: Option<String>,
+    /// Overrides `EngineConfig::user_agent` for this task's requests.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// Extract this download on completion even if `EngineConfig::auto_extract` is off.
+    #[serde(default)]
+    pub auto_extract: bool,
+    /// Download `url` and `mirrors` exactly as given: no hosting-page or provider
+    /// resolution, no pixeldrain list expansion.
+    #[serde(default)]
+    pub skip_resolution: bool,
+    /// Probe with a ranged GET even if `EngineConfig::skip_head` is off.
+    #[serde(default)]
+    pub skip_head: bool,
+    /// Download only the first this-many bytes; see `partial`.
+    #[serde(default)]
+    pub byte_limit: Option<u64>,
+    /// Completed with `byte_limit` short of the full file. Checksums are not verified.
+    #[serde(default)]
+    pub partial: bool,
+    /// The URL (`url` or a mirror) that served most of the bytes of the last download.
+    #[serde(default)]
+    pub resolved_url: Option<String>,
+    /// Not started until this task completes; failed if it fails or is canceled.
+    #[serde(default)]
+    pub depends_on: Option<TaskId>,
+    #[serde(default)]
+    pub method: HttpMethod,
+    /// Sent with `HttpMethod::Post` requests.
+    #[serde(default)]
+    pub body: Option<RequestBody>,
+    /// Automatic re-queues after failure so far; see `EngineConfig::max_task_restarts`.
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Epoch seconds before which an automatically re-queued task is not started; `0` when
+    /// no restart is pending.
+    #[serde(default)]
+    pub restart_at: u64,
+    /// Metadata of a `.torrent` task; see `DownloadEngine::add_torrent`.
+    #[serde(default)]
+    pub torrent: Option<TorrentInfo>,
+    #[serde(default)]
+    pub pause_reason: Option<PauseReason>,
+    /// Epoch seconds; serialized as an RFC 3339 string.
+    #[serde(default, with = "rfc3339_secs")]
     pub created_at: u64,
+    #[serde(default, with = "rfc3339_secs")]
     pub updated_at: u64,
+    #[serde(default)]
     pub error: Option<String>,
 }
 
+/// Lightweight view of a task for polling; see `Storage::list_task_summaries`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TaskSummary {
+    pub id: TaskId,
+    pub status: TaskStatus,
+    pub url: String,
+    pub dest_path: String,
+    pub priority: i32,
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+}
+
+impl From<&Task> for TaskSummary {
+    fn from(task: &Task) -> Self {
+        Self {
+            id: task.id,
+            status: task.status.clone(),
+            url: task.url.clone(),
+            dest_path: task.dest_path.clone(),
+            priority: task.priority,
+            downloaded_bytes: task.downloaded_bytes,
+            total_bytes: task.total_bytes,
+        }
+    }
+}
+
+/// Selects tasks for bulk operations such as `DownloadEngine::remove_by`. An empty
+/// `statuses` matches every status.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskFilter {
+    pub statuses: Vec<TaskStatus>,
+    /// Only tasks last updated at least this many seconds ago.
+    pub older_than_secs: Option<u64>,
+}
+
+impl TaskFilter {
+    pub fn matches(&self, task: &Task, now: u64) -> bool {
+        let status_ok = self.statuses.is_empty() || self.statuses.contains(&task.status);
+        let age_ok = self
+            .older_than_secs
+            .is_none_or(|secs| task.updated_at.saturating_add(secs) <= now);
+        status_ok && age_ok
+    }
+}
+
 impl Task {
     pub fn new(url: String, dest_path: String) -> Self {
         let now = now_epoch();
         Self {
+            schema_version: TASK_SCHEMA_VERSION,
             id: Uuid::new_v4(),
             url,
             dest_path,
@@ -88,16 +281,87 @@ impl Task {
             proxy_url: None,
             auth_user: None,
             auth_pass: None,
+            user_agent: None,
+            auto_extract: false,
+            skip_resolution: false,
+            skip_head: false,
+            byte_limit: None,
+            partial: false,
+            resolved_url: None,
+            depends_on: None,
+            method: HttpMethod::Get,
+            body: None,
+            restart_count: 0,
+            restart_at: 0,
+            torrent: None,
+            pause_reason: None,
             created_at: now,
             updated_at: now,
             error: None,
         }
     }
 
+    /// Parses a task serialized by this or an older version and upgrades it.
+    pub fn from_json(json: &str) -> CoreResult<Self> {
+        let mut task: Task =
+            serde_json::from_str(json).map_err(|err| CoreError::Serialization(err.to_string()))?;
+        task.upgrade();
+        Ok(task)
+    }
+
+    /// Fills in what older payloads could not carry and stamps the current schema version.
+    pub fn upgrade(&mut self) {
+        if self.schema_version < 1 {
+            if self.created_at == 0 {
+                self.created_at = now_epoch();
+            }
+            if self.updated_at == 0 {
+                self.updated_at = self.created_at;
+            }
+        }
+        self.schema_version = TASK_SCHEMA_VERSION;
+    }
+
     pub fn touch(&mut self) {
         self.updated_at = now_epoch();
     }
 
+    /// `CoreError::InvalidState` naming `action` unless the status can move to `next`.
+    pub fn check_transition(&self, next: &TaskStatus, action: &str) -> CoreResult<()> {
+        if self.status.can_transition_to(next) {
+            return Ok(());
+        }
+        Err(CoreError::InvalidState(format!(
+            "cannot {} task in state {}",
+            action, self.status
+        )))
+    }
+
+    /// Moves `user:pass@` credentials out of the URL into `auth_user`/`auth_pass`.
+    pub fn extract_url_credentials(&mut self) {
+        let Ok(mut parsed) = Url::parse(&self.url) else {
+            return;
+        };
+        if parsed.username().is_empty() && parsed.password().is_none() {
+            return;
+        }
+        let user = percent_decode_str(parsed.username())
+            .decode_utf8_lossy()
+            .to_string();
+        let pass = parsed
+            .password()
+            .map(|value| percent_decode_str(value).decode_utf8_lossy().to_string())
+            .unwrap_or_default();
+        if parsed.set_username("").is_err() || parsed.set_password(None).is_err() {
+            return;
+        }
+        self.url = parsed.to_string();
+        if self.auth_user.is_none() {
+            self.auth_user = Some(user);
+            self.auth_pass = Some(pass);
+        }
+    }
+
     pub fn url_candidates(&self) -> Vec<String> {
         let mut urls = Vec::with_capacity(1 + self.mirrors.len());
         urls.push(self.url.clone());
@@ -110,9 +374,272 @@ impl Task {
     }
 }
 
-fn now_epoch() -> u64 {
+/// Chainable construction of a `Task`; see `DownloadEngine::add_task_with`.
+#[derive(Debug, Clone)]
+pub struct TaskBuilder {
+    task: Task,
+}
+
+impl TaskBuilder {
+    pub fn new(url: impl Into<String>, dest_path: impl Into<String>) -> Self {
+        Self {
+            task: Task::new(url.into(), dest_path.into()),
+        }
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.task.priority = priority;
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.task.headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn cookie(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.task.cookies.insert(name.into(), value.into());
+        self
+    }
+
+    /// Tried in the order added when `url` fails.
+    pub fn mirror(mut self, url: impl Into<String>) -> Self {
+        self.task.mirrors.push(url.into());
+        self
+    }
+
+    pub fn checksum(mut self, checksum: ChecksumRequest) -> Self {
+        self.task.checksum = Some(checksum);
+        self
+    }
+
+    pub fn proxy(mut self, proxy_url: impl Into<String>) -> Self {
+        self.task.proxy_url = Some(proxy_url.into());
+        self
+    }
+
+    pub fn auth(mut self, user: impl Into<String>, pass: impl Into<String>) -> Self {
+        self.task.auth_user = Some(user.into());
+        self.task.auth_pass = Some(pass.into());
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.task.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn auto_extract(mut self, enabled: bool) -> Self {
+        self.task.auto_extract = enabled;
+        self
+    }
+
+    pub fn skip_resolution(mut self, enabled: bool) -> Self {
+        self.task.skip_resolution = enabled;
+        self
+    }
+
+    pub fn skip_head(mut self, enabled: bool) -> Self {
+        self.task.skip_head = enabled;
+        self
+    }
+
+    /// Waits for task `id` to complete before starting.
+    pub fn depends_on(mut self, id: TaskId) -> Self {
+        self.task.depends_on = Some(id);
+        self
+    }
+
+    /// Fetches the file with a POST of `data`, e.g. `application/x-www-form-urlencoded`.
+    pub fn post(mut self, content_type: impl Into<String>, data: impl Into<String>) -> Self {
+        self.task.method = HttpMethod::Post;
+        self.task.body = Some(RequestBody {
+            content_type: content_type.into(),
+            data: data.into(),
+        });
+        self
+    }
+
+    /// Stops after the first `bytes` of the file; `None` downloads all of it.
+    pub fn byte_limit(mut self, bytes: Option<u64>) -> Self {
+        self.task.byte_limit = bytes;
+        self
+    }
+
+    /// Adds the task paused by the user, so it waits for `resume_task`.
+    pub fn paused(mut self) -> Self {
+        self.task.status = TaskStatus::Paused;
+        self.task.pause_reason = Some(PauseReason::User);
+        self
+    }
+
+    /// Fails on a URL, mirror or proxy that doesn't parse. Credentials in the URL move
+    /// into `auth_user`/`auth_pass` unless `auth` was set.
+    pub fn build(self) -> CoreResult<Task> {
+        let mut task = self.task;
+        let urls = task.url_candidates();
+        for url in urls.iter().chain(task.proxy_url.iter()) {
+            Url::parse(url)
+                .map_err(|err| CoreError::InvalidConfig(format!("invalid url {}: {}", url, err)))?;
+        }
+        task.extract_url_credentials();
+        task.touch();
+        Ok(task)
+    }
+}
+
+/// Version of the `idm-cli export` file layout.
+pub const TASK_EXPORT_VERSION: u32 = 1;
+
+/// Portable, hand-editable description of a task, independent of the storage format.
+/// Carries no ids, progress or credentials.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TaskDefinition {
+    pub url: String,
+    #[serde(default)]
+    pub dest_path: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub cookies: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// `<type>:<hex>`, e.g. `sha256:9f86d0…`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    #[serde(default)]
+    pub priority: i32,
+    /// Status at export time, for reference; imported tasks always start queued.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
+
+impl From<&Task> for TaskDefinition {
+    fn from(task: &Task) -> Self {
+        Self {
+            url: task.url.clone(),
+            dest_path: task.dest_path.clone(),
+            headers: task.headers.clone(),
+            cookies: task.cookies.clone(),
+            mirrors: task.mirrors.clone(),
+            checksum: task.checksum.as_ref().map(|checksum| {
+                format!(
+                    "{}:{}",
+                    checksum.checksum_type.as_str(),
+                    checksum.expected_hex
+                )
+            }),
+            priority: task.priority,
+            status: Some(task.status.as_str().to_string()),
+        }
+    }
+}
+
+impl TaskDefinition {
+    /// A fresh queued task with a new id and no progress.
+    pub fn into_task(self) -> CoreResult<Task> {
+        let checksum = match self.checksum {
+            Some(spec) => Some(ChecksumRequest::parse(&spec).ok_or_else(|| {
+                CoreError::Serialization(format!("invalid checksum {:?} for {}", spec, self.url))
+            })?),
+            None => None,
+        };
+        let mut task = Task::new(self.url, self.dest_path);
+        task.headers = self.headers;
+        task.cookies = self.cookies;
+        task.mirrors = self.mirrors;
+        task.checksum = checksum;
+        task.priority = self.priority;
+        Ok(task)
+    }
+}
+
+/// The file written by `idm-cli export`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TaskExport {
+    #[serde(default)]
+    pub version: u32,
+    pub tasks: Vec<TaskDefinition>,
+}
+
+impl TaskExport {
+    pub fn new(tasks: &[Task]) -> Self {
+        Self {
+            version: TASK_EXPORT_VERSION,
+            tasks: tasks.iter().map(TaskDefinition::from).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> CoreResult<String> {
+        serde_json::to_string_pretty(self).map_err(|err| CoreError::Serialization(err.to_string()))
+    }
+
+    pub fn from_json(json: &str) -> CoreResult<Self> {
+        let export: TaskExport =
+            serde_json::from_str(json).map_err(|err| CoreError::Serialization(err.to_string()))?;
+        if export.version > TASK_EXPORT_VERSION {
+            return Err(CoreError::Serialization(format!(
+                "export version {} is newer than supported version {}",
+                export.version, TASK_EXPORT_VERSION
+            )));
+        }
+        Ok(export)
+    }
+}
+
+pub(crate) fn now_epoch() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map(|d| d.as_secs())
         .unwrap_or(0)
 }
+
+/// Formats epoch seconds as an RFC 3339 UTC timestamp, e.g. `2024-05-01T12:00:00Z`.
+pub fn format_rfc3339(secs: u64) -> String {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| secs.to_string())
+}
+
+/// Rough age of `secs` relative to `now`, e.g. `45s ago`, `2h ago`, `3d ago`.
+pub fn format_age(secs: u64, now: u64) -> String {
+    let age = now.saturating_sub(secs);
+    match age {
+        0..=59 => format!("{}s ago", age),
+        60..=3_599 => format!("{}m ago", age / 60),
+        3_600..=86_399 => format!("{}h ago", age / 3_600),
+        _ => format!("{}d ago", age / 86_400),
+    }
+}
+
+/// Task timestamps are kept as epoch seconds but written as RFC 3339 strings.
+/// Plain integers from older payloads are still accepted.
+mod rfc3339_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use time::format_description::well_known::Rfc3339;
+    use time::OffsetDateTime;
+
+    pub fn serialize<S: Serializer>(secs: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_rfc3339(*secs))
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Secs(u64),
+        Text(String),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match Raw::deserialize(deserializer)? {
+            Raw::Secs(secs) => Ok(secs),
+            Raw::Text(text) => OffsetDateTime::parse(&text, &Rfc3339)
+                .ok()
+                .and_then(|time| u64::try_from(time.unix_timestamp()).ok())
+                .or_else(|| text.parse().ok())
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp: {}", text))),
+        }
+    }
+}
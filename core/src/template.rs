@@ -0,0 +1,136 @@
+//! Output filename templates in the style of `%(host)s-%(name)s.%(ext)s`.
+
+use time::format_description::well_known::Iso8601;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::error::{CoreError, CoreResult};
+use crate::task::TaskId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// Hostname of the URL the file was downloaded from.
+    Host,
+    /// Suggested filename without its extension.
+    Name,
+    /// Extension of the suggested filename, without the dot.
+    Ext,
+    /// UTC date the name was chosen, `YYYY-MM-DD`.
+    Date,
+    Id,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+/// A parsed template; `%%` is a literal `%`. Placeholders: `host`, `name`, `ext`,
+/// `date`, `id`. An empty `ext` also drops a `.` right before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate {
+    pieces: Vec<Piece>,
+}
+
+/// What a template is rendered against.
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateVars<'a> {
+    pub url: &'a str,
+    /// Unsanitized name from `Content-Disposition` or the URL.
+    pub filename: &'a str,
+    pub task_id: &'a TaskId,
+    /// Seconds since the Unix epoch, for `%(date)s`.
+    pub now: u64,
+}
+
+impl OutputTemplate {
+    pub fn parse(template: &str) -> CoreResult<Self> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(pos) = rest.find('%') {
+            literal.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            if let Some(after) = rest.strip_prefix('%') {
+                literal.push('%');
+                rest = after;
+                continue;
+            }
+            let (name, after) = rest
+                .strip_prefix('(')
+                .and_then(|inner| inner.split_once(")s"))
+                .ok_or_else(|| invalid(template, "expected %(name)s"))?;
+            let field = match name {
+                "host" => Field::Host,
+                "name" => Field::Name,
+                "ext" => Field::Ext,
+                "date" => Field::Date,
+                "id" => Field::Id,
+                _ => return Err(invalid(template, &format!("unknown placeholder {}", name))),
+            };
+            if !literal.is_empty() {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+            }
+            pieces.push(Piece::Field(field));
+            rest = after;
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        if pieces.is_empty() {
+            return Err(invalid(template, "template is empty"));
+        }
+        Ok(Self { pieces })
+    }
+
+    /// Renders the filename; the caller still sanitizes it.
+    pub fn render(&self, vars: &TemplateVars<'_>) -> String {
+        let (name, ext) = split_extension(vars.filename);
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(text) => out.push_str(text),
+                Piece::Field(Field::Host) => {
+                    let host = Url::parse(vars.url)
+                        .ok()
+                        .and_then(|url| url.host_str().map(str::to_string))
+                        .unwrap_or_default();
+                    out.push_str(&host);
+                }
+                Piece::Field(Field::Name) => out.push_str(name),
+                // No extension: drop the dot that would have separated it.
+                Piece::Field(Field::Ext) if ext.is_empty() => {
+                    if out.ends_with('.') {
+                        out.pop();
+                    }
+                }
+                Piece::Field(Field::Ext) => out.push_str(ext),
+                Piece::Field(Field::Date) => out.push_str(&format_date(vars.now)),
+                Piece::Field(Field::Id) => out.push_str(&vars.task_id.to_string()),
+            }
+        }
+        out
+    }
+}
+
+fn invalid(template: &str, reason: &str) -> CoreError {
+    CoreError::InvalidConfig(format!("output template {:?}: {}", template, reason))
+}
+
+/// Dotfiles like `.bashrc` have no extension.
+fn split_extension(filename: &str) -> (&str, &str) {
+    match filename.rfind('.').filter(|&dot| dot > 0) {
+        Some(dot) => (&filename[..dot], &filename[dot + 1..]),
+        None => (filename, ""),
+    }
+}
+
+fn format_date(epoch_secs: u64) -> String {
+    i64::try_from(epoch_secs)
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .and_then(|time| time.date().format(&Iso8601::DATE).ok())
+        .unwrap_or_else(|| epoch_secs.to_string())
+}
@@ -0,0 +1,344 @@
+//! Minimal HTTP/1.1 server for engine tests. Serves fixed bodies with `Range`
+//! support and records every request so tests can assert on what was sent.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub ranges: bool,
+    pub delay: Option<Duration>,
+    pub stall_at: Option<usize>,
+    /// Serves at most this many bytes of any range, as servers that clamp ranges do.
+    pub max_range_len: Option<usize>,
+    /// Answers a range request with the range this many bytes later, as a misbehaving
+    /// server might.
+    pub range_shift: usize,
+    /// GETs without this header value get `403`; HEADs are always answered.
+    pub required_get_header: Option<(String, String)>,
+    /// Answers GETs instead of this route; HEADs still see this one.
+    pub get_override: Option<Box<Route>>,
+    /// Leaves out `Content-Length`; the body ends when the connection closes.
+    pub omit_length: bool,
+    /// Only a POST with exactly this body is answered; anything else gets `405`.
+    pub required_post_body: Option<Vec<u8>>,
+}
+
+impl Route {
+    /// A downloadable file that honors `Range` requests.
+    pub fn file(body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("Accept-Ranges".to_string(), "bytes".to_string())],
+            body: body.into(),
+            ranges: true,
+            delay: None,
+            stall_at: None,
+            max_range_len: None,
+            range_shift: 0,
+            required_get_header: None,
+            get_override: None,
+            omit_length: false,
+            required_post_body: None,
+        }
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self {
+            status: 302,
+            headers: vec![("Location".to_string(), location.to_string())],
+            body: Vec::new(),
+            ranges: false,
+            delay: None,
+            stall_at: None,
+            max_range_len: None,
+            range_shift: 0,
+            required_get_header: None,
+            get_override: None,
+            omit_length: false,
+            required_post_body: None,
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            ranges: false,
+            delay: None,
+            stall_at: None,
+            max_range_len: None,
+            range_shift: 0,
+            required_get_header: None,
+            get_override: None,
+            omit_length: false,
+            required_post_body: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Waits `delay` before answering, to simulate a slow host.
+    pub fn delayed(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Stops sending (without closing) when a response reaches body offset `offset`,
+    /// so only responses that start before it stall.
+    pub fn stalling_at(mut self, offset: usize) -> Self {
+        self.stall_at = Some(offset);
+        self
+    }
+
+    pub fn clamping_ranges_to(mut self, len: usize) -> Self {
+        self.max_range_len = Some(len);
+        self
+    }
+
+    pub fn shifting_ranges_by(mut self, shift: usize) -> Self {
+        self.range_shift = shift;
+        self
+    }
+
+    pub fn forbid_get_unless(mut self, name: &str, value: &str) -> Self {
+        self.required_get_header = Some((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+
+    pub fn on_get(mut self, route: Route) -> Self {
+        self.get_override = Some(Box::new(route));
+        self
+    }
+
+    pub fn post_only(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.required_post_body = Some(body.into());
+        self
+    }
+
+    pub fn without_length(mut self) -> Self {
+        self.omit_length = true;
+        self
+    }
+
+    pub fn without_ranges(mut self) -> Self {
+        self.ranges = false;
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("accept-ranges"));
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    pub fn start(routes: Vec<(&str, Route)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let addr = listener.local_addr().expect("mock server addr");
+        let routes: Arc<HashMap<String, Route>> = Arc::new(
+            routes
+                .into_iter()
+                .map(|(path, route)| (path.to_string(), route))
+                .collect(),
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let routes = Arc::clone(&routes);
+                let recorded = Arc::clone(&recorded);
+                thread::spawn(move || handle_connection(stream, &routes, &recorded));
+            }
+        });
+
+        Self { addr, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().map(|r| r.clone()).unwrap_or_default()
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    routes: &HashMap<String, Route>,
+    recorded: &Mutex<Vec<RecordedRequest>>,
+) {
+    let Ok(read_half) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(read_half);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let range = headers.get("range").cloned();
+    let route = match routes.get(&path) {
+        Some(route)
+            if route
+                .required_post_body
+                .as_ref()
+                .is_some_and(|expected| method != "POST" || *expected != body) =>
+        {
+            Route::status(405)
+        }
+        Some(route) => match &route.required_get_header {
+            Some((name, value)) if method == "GET" && headers.get(name) != Some(value) => {
+                Route::status(403)
+            }
+            _ => match &route.get_override {
+                Some(get_route) if method == "GET" => (**get_route).clone(),
+                _ => route.clone(),
+            },
+        },
+        None => Route::status(404),
+    };
+    if let Ok(mut recorded) = recorded.lock() {
+        recorded.push(RecordedRequest {
+            method: method.clone(),
+            path: path.clone(),
+            headers,
+            body,
+        });
+    }
+    if let Some(delay) = route.delay {
+        thread::sleep(delay);
+    }
+    let (status, extra_headers, body, body_start) = respond(&route, range.as_deref());
+
+    let mut out = stream;
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in route.headers.iter().chain(extra_headers.iter()) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !route.omit_length {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    let _ = out.write_all(head.as_bytes());
+    if method != "HEAD" {
+        match route.stall_at {
+            Some(offset) if offset > body_start && offset < body_start + body.len() => {
+                let _ = out.write_all(&body[..offset - body_start]);
+                let _ = out.flush();
+                thread::sleep(Duration::from_secs(5));
+                return;
+            }
+            _ => {
+                let _ = out.write_all(body);
+            }
+        }
+    }
+    let _ = out.flush();
+}
+
+/// Returns the status, extra headers, body slice, and the slice's offset in the full body.
+fn respond<'a>(
+    route: &'a Route,
+    range: Option<&str>,
+) -> (u16, Vec<(String, String)>, &'a [u8], usize) {
+    let len = route.body.len() as u64;
+    let requested = range
+        .filter(|_| route.ranges)
+        .and_then(|value| value.strip_prefix("bytes="))
+        .and_then(|value| value.split_once('-'));
+    let Some((start, end)) = requested else {
+        return (route.status, Vec::new(), &route.body, 0);
+    };
+
+    let start = start.parse::<u64>().unwrap_or(0) + route.range_shift as u64;
+    let mut end: u64 = end
+        .parse()
+        .unwrap_or(len.saturating_sub(1))
+        .min(len.saturating_sub(1));
+    if let Some(max) = route.max_range_len {
+        end = end.min(start.saturating_add(max as u64).saturating_sub(1));
+    }
+    if start >= len || start > end {
+        let headers = vec![("Content-Range".to_string(), format!("bytes */{}", len))];
+        return (416, headers, &[], 0);
+    }
+    let headers = vec![(
+        "Content-Range".to_string(),
+        format!("bytes {}-{}/{}", start, end, len),
+    )];
+    (
+        206,
+        headers,
+        &route.body[start as usize..=end as usize],
+        start as usize,
+    )
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        302 => "Found",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        _ => "Status",
+    }
+}
@@ -1,4 +1,5 @@
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
@@ -16,6 +17,9 @@ impl Default for ThrottleConfig {
     }
 }
 
+/// Credit an idle limiter may bank; bounds the burst after a stall.
+const MAX_BURST: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 struct ThrottleState {
     start: Instant,
@@ -33,11 +37,18 @@ impl ThrottleState {
     }
 
     fn reserve_sleep(&mut self, bytes: u64) -> Duration {
-        self.bytes = self.bytes.saturating_add(bytes);
         if self.limit_bytes_per_sec == 0 {
             return Duration::from_secs(0);
         }
         let expected = self.bytes as f64 / self.limit_bytes_per_sec as f64;
+        if self.start.elapsed().as_secs_f64() - expected > MAX_BURST.as_secs_f64() {
+            self.start = Instant::now()
+                .checked_sub(MAX_BURST)
+                .unwrap_or_else(Instant::now);
+            self.bytes = 0;
+        }
+        self.bytes = self.bytes.saturating_add(bytes);
+        let expected = self.bytes as f64 / self.limit_bytes_per_sec as f64;
         let elapsed = self.start.elapsed().as_secs_f64();
         if expected > elapsed {
             Duration::from_secs_f64(expected - elapsed)
@@ -45,29 +56,137 @@ impl ThrottleState {
             Duration::from_secs(0)
         }
     }
+
+    /// Switches to a new rate from now on, keeping any sleep already owed.
+    fn set_limit(&mut self, limit_bytes_per_sec: u64) {
+        let owed = if self.limit_bytes_per_sec == 0 {
+            0.0
+        } else {
+            let expected = self.bytes as f64 / self.limit_bytes_per_sec as f64;
+            (expected - self.start.elapsed().as_secs_f64()).max(0.0)
+        };
+        self.start = Instant::now();
+        self.bytes = (owed * limit_bytes_per_sec as f64) as u64;
+        self.limit_bytes_per_sec = limit_bytes_per_sec;
+    }
+}
+
+#[derive(Debug, Default)]
+struct FairState {
+    limit_bytes_per_sec: u64,
+    next_id: u64,
+    shares: HashMap<u64, ThrottleState>,
+}
+
+impl FairState {
+    fn rebalance(&mut self) {
+        let count = self.shares.len().max(1) as u64;
+        let share = match self.limit_bytes_per_sec {
+            0 => 0,
+            limit => (limit / count).max(1),
+        };
+        for state in self.shares.values_mut() {
+            state.set_limit(share);
+        }
+    }
+}
+
+/// Engine-wide bandwidth cap, split evenly between the tasks currently downloading so a
+/// task writing large chunks cannot starve the others. Shares are rebalanced whenever a
+/// task joins or leaves, or the limit changes.
+#[derive(Debug, Clone, Default)]
+pub struct GlobalThrottle {
+    state: Arc<Mutex<FairState>>,
+}
+
+impl GlobalThrottle {
+    /// `None` (or `0`) means unlimited.
+    pub fn new(limit_bytes_per_sec: Option<u64>) -> Self {
+        let throttle = Self::default();
+        throttle.set_limit(limit_bytes_per_sec);
+        throttle
+    }
+
+    /// Changes the cap for running and future tasks; `None` (or `0`) lifts it.
+    pub fn set_limit(&self, limit_bytes_per_sec: Option<u64>) {
+        if let Ok(mut guard) = self.state.lock() {
+            guard.limit_bytes_per_sec = limit_bytes_per_sec.unwrap_or(0);
+            guard.rebalance();
+        }
+    }
+
+    /// Registers a task; its share is given back when the returned value is dropped.
+    /// Tasks join even while unlimited so a later `set_limit` reaches them.
+    pub fn join(&self) -> Option<ThrottleShare> {
+        let mut guard = self.state.lock().ok()?;
+        let id = guard.next_id;
+        guard.next_id += 1;
+        guard.shares.insert(id, ThrottleState::new(0));
+        guard.rebalance();
+        Some(ThrottleShare {
+            state: Arc::clone(&self.state),
+            id,
+        })
+    }
 }
 
+/// One task's slice of a `GlobalThrottle`.
+#[derive(Debug)]
+pub struct ThrottleShare {
+    state: Arc<Mutex<FairState>>,
+    id: u64,
+}
+
+impl ThrottleShare {
+    fn reserve_sleep(&self, bytes: u64) -> Duration {
+        let Ok(mut guard) = self.state.lock() else {
+            return Duration::from_secs(0);
+        };
+        guard
+            .shares
+            .get_mut(&self.id)
+            .map(|state| state.reserve_sleep(bytes))
+            .unwrap_or_default()
+    }
+
+    pub fn throttle(&self, bytes: u64) {
+        let sleep = self.reserve_sleep(bytes);
+        if sleep.as_millis() > 0 {
+            std::thread::sleep(sleep);
+        }
+    }
+}
+
+impl Drop for ThrottleShare {
+    fn drop(&mut self) {
+        if let Ok(mut guard) = self.state.lock() {
+            guard.shares.remove(&self.id);
+            guard.rebalance();
+        }
+    }
+}
+
+/// A task's limiter: its share of the global cap plus the per-task cap. Clones share state,
+/// so every segment of a task draws from the same budget.
 #[derive(Clone)]
 pub struct Throttle {
-    global: Option<std::sync::Arc<Mutex<ThrottleState>>>,
-    per_task: Option<std::sync::Arc<Mutex<ThrottleState>>>,
+    global: Option<Arc<ThrottleShare>>,
+    per_task: Option<Arc<Mutex<ThrottleState>>>,
 }
 
 impl Throttle {
-    pub fn new(global_limit: Option<u64>, per_task_limit: Option<u64>) -> Self {
-        let global = global_limit.map(|limit| std::sync::Arc::new(Mutex::new(ThrottleState::new(limit))));
-        let per_task = per_task_limit.map(|limit| std::sync::Arc::new(Mutex::new(ThrottleState::new(limit))));
+    pub fn new(global: &GlobalThrottle, per_task_limit: Option<u64>) -> Self {
+        let global = global.join().map(Arc::new);
+        let per_task = per_task_limit.map(|limit| Arc::new(Mutex::new(ThrottleState::new(limit))));
         Self { global, per_task }
     }
 
     pub fn throttle(&self, bytes: u64) {
         let mut max_sleep = Duration::from_secs(0);
-        if let Some(state) = &self.global {
-            if let Ok(mut guard) = state.lock() {
-                let sleep = guard.reserve_sleep(bytes);
-                if sleep > max_sleep {
-                    max_sleep = sleep;
-                }
+        if let Some(share) = &self.global {
+            let sleep = share.reserve_sleep(bytes);
+            if sleep > max_sleep {
+                max_sleep = sleep;
             }
         }
         if let Some(state) = &self.per_task {
@@ -83,3 +202,25 @@ impl Throttle {
         }
     }
 }
+
+/// Parses a rate such as `1048576`, `500k`, `1m` or `2g` (binary multiples) into bytes per second.
+pub fn parse_rate(value: &str) -> Option<u64> {
+    let value = value.trim().to_ascii_lowercase();
+    let value = value.strip_suffix("/s").unwrap_or(&value);
+    let value = value.strip_suffix('b').unwrap_or(value);
+    let (digits, multiplier) = match value.chars().last()? {
+        'k' => (&value[..value.len() - 1], 1024u64),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    let digits = digits.trim();
+    if let Ok(whole) = digits.parse::<u64>() {
+        return whole.checked_mul(multiplier);
+    }
+    let fractional = digits.parse::<f64>().ok()?;
+    if !fractional.is_finite() || fractional < 0.0 {
+        return None;
+    }
+    Some((fractional * multiplier as f64) as u64)
+}
@@ -1,5 +1,19 @@
+//! `.torrent` metainfo files. Only the metadata is read; downloading from the swarm is
+//! not supported yet.
+
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use serde_bencode::value::Value;
+use sha1::{Digest, Sha1};
+use url::Url;
+
 use crate::error::{CoreError, CoreResult};
-use lava_torrent::torrent::v1::Torrent;
+
+/// Larger metainfo files are refused rather than buffered.
+pub const MAX_TORRENT_BYTES: u64 = 10 * 1024 * 1024;
+
+pub const TORRENT_CONTENT_TYPE: &str = "application/x-bittorrent";
 
 pub struct TorrentEngine;
 
@@ -9,22 +23,106 @@ impl TorrentEngine {
     }
 
     pub fn parse_file(path: &str) -> CoreResult<TorrentInfo> {
-        let torrent = Torrent::read_from_file(path)
-            .map_err(|e| CoreError::Io(format!("Invalid torrent file: {:?}", e)))?;
-        
-        // Calculate hash BEFORE moving fields (like torrent.name)
-        let hash = torrent.info_hash();
-
-        Ok(TorrentInfo {
-            name: torrent.name,
-            length: torrent.length,
-            info_hash: hash,
-        })
+        let bytes = fs::read(path).map_err(|err| CoreError::Io(err.to_string()))?;
+        parse_torrent(&bytes)
     }
 }
 
+/// What a task keeps from a `.torrent` file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TorrentInfo {
-    pub name: String,
-    pub length: i64,
+    /// SHA-1 of the bencoded `info` dictionary, lowercase hex.
     pub info_hash: String,
-}
\ No newline at end of file
+    /// File name, or the top-level directory of a multi-file torrent.
+    pub name: String,
+    /// Sum of all file lengths.
+    pub length: u64,
+    /// `announce` followed by the `announce-list` tiers, without duplicates.
+    #[serde(default)]
+    pub trackers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct MetaInfo {
+    #[serde(default)]
+    announce: Option<String>,
+    #[serde(default, rename = "announce-list")]
+    announce_list: Vec<Vec<String>>,
+    info: Value,
+}
+
+#[derive(Deserialize)]
+struct InfoDict {
+    name: String,
+    #[serde(rename = "piece length")]
+    piece_length: u64,
+    pieces: Value,
+    #[serde(default)]
+    length: Option<u64>,
+    #[serde(default)]
+    files: Option<Vec<FileEntry>>,
+}
+
+#[derive(Deserialize)]
+struct FileEntry {
+    length: u64,
+}
+
+/// Parses and validates a metainfo file.
+pub fn parse_torrent(bytes: &[u8]) -> CoreResult<TorrentInfo> {
+    let invalid = |reason: String| CoreError::Serialization(format!("invalid torrent: {}", reason));
+    let meta: MetaInfo =
+        serde_bencode::from_bytes(bytes).map_err(|err| invalid(err.to_string()))?;
+    // The encoder sorts keys, so this reproduces the canonical bytes the hash is over.
+    let info_bytes = serde_bencode::to_bytes(&meta.info).map_err(|err| invalid(err.to_string()))?;
+    let info: InfoDict =
+        serde_bencode::from_bytes(&info_bytes).map_err(|err| invalid(err.to_string()))?;
+
+    if info.name.trim().is_empty() {
+        return Err(invalid("empty name".to_string()));
+    }
+    if info.piece_length == 0 {
+        return Err(invalid("zero piece length".to_string()));
+    }
+    match &info.pieces {
+        Value::Bytes(pieces) if !pieces.is_empty() && pieces.len() % 20 == 0 => {}
+        _ => return Err(invalid("pieces must be a multiple of 20 bytes".to_string())),
+    }
+    let length = match (info.length, &info.files) {
+        (Some(length), None) => length,
+        (None, Some(files)) if !files.is_empty() => files
+            .iter()
+            .try_fold(0u64, |total, file| total.checked_add(file.length))
+            .ok_or_else(|| invalid("total length overflows".to_string()))?,
+        _ => return Err(invalid("expected either length or files".to_string())),
+    };
+
+    let mut trackers: Vec<String> = Vec::new();
+    for tracker in meta
+        .announce
+        .into_iter()
+        .chain(meta.announce_list.into_iter().flatten())
+    {
+        if !tracker.is_empty() && !trackers.contains(&tracker) {
+            trackers.push(tracker);
+        }
+    }
+
+    Ok(TorrentInfo {
+        info_hash: hex::encode(Sha1::digest(&info_bytes)),
+        name: info.name,
+        length,
+        trackers,
+    })
+}
+
+/// Whether `url` (by its path) or `content_type` names a `.torrent` file.
+pub fn is_torrent(url: &str, content_type: Option<&str>) -> bool {
+    let by_type = content_type
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case(TORRENT_CONTENT_TYPE));
+    let by_path = Url::parse(url)
+        .map(|url| url.path().to_ascii_lowercase().ends_with(".torrent"))
+        .unwrap_or(false);
+    by_type || by_path
+}
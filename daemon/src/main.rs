@@ -1,34 +1,260 @@
+use std::collections::HashMap;
 use std::env;
-use std::thread;
-use std::time::Duration;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use idm_core::config::EngineConfig;
+use serde::{Deserialize, Serialize};
+
+use idm_core::config::{EngineConfig, SegmentStrategy};
+use idm_core::ipc;
 use idm_core::storage::SqliteStorage;
-use idm_core::DownloadEngine;
+use idm_core::throttle::parse_rate;
+use idm_core::{DownloadEngine, EngineEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a, T: Serialize> {
+    ts: u64,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+#[derive(Serialize)]
+struct DaemonError {
+    event: &'static str,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct TaskRecovered {
+    event: &'static str,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct IdleExit {
+    event: &'static str,
+    idle_secs: u64,
+}
+
+#[derive(Serialize)]
+struct ConfigReloaded {
+    event: &'static str,
+    max_concurrent_tasks: usize,
+    global_speed_limit_bytes_per_sec: Option<u64>,
+}
+
+/// Engine settings read from `--config` (JSON) or flags. Unset fields keep the default;
+/// a `0` speed limit means unlimited.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigOverrides {
+    max_concurrent_tasks: Option<usize>,
+    max_segments_per_task: Option<u32>,
+    /// Splits every ranged download into exactly this many segments.
+    fixed_segments: Option<u32>,
+    max_connections_per_host: Option<usize>,
+    global_speed_limit_bytes_per_sec: Option<u64>,
+    per_task_speed_limit_bytes_per_sec: Option<u64>,
+    retry_count: Option<u32>,
+    max_task_restarts: Option<u32>,
+    task_restart_cooldown_secs: Option<u64>,
+    completed_dir: Option<PathBuf>,
+    temp_dir: Option<PathBuf>,
+    /// Sent with every request unless the task sets the same header.
+    default_headers: Option<HashMap<String, String>>,
+}
+
+impl ConfigOverrides {
+    fn apply(&self, config: &mut EngineConfig) {
+        if let Some(value) = self.max_concurrent_tasks {
+            config.max_concurrent_tasks = value.max(1);
+        }
+        if let Some(value) = self.max_segments_per_task {
+            config.max_segments_per_task = value.max(1);
+        }
+        if let Some(value) = self.fixed_segments {
+            config.segment_strategy = SegmentStrategy::Fixed(value.max(1));
+        }
+        if let Some(value) = self.max_connections_per_host {
+            config.max_connections_per_host = value;
+        }
+        if let Some(value) = self.global_speed_limit_bytes_per_sec {
+            config.global_speed_limit_bytes_per_sec = Some(value).filter(|v| *v > 0);
+        }
+        if let Some(value) = self.per_task_speed_limit_bytes_per_sec {
+            config.per_task_speed_limit_bytes_per_sec = Some(value).filter(|v| *v > 0);
+        }
+        if let Some(value) = self.retry_count {
+            config.retry_count = value;
+        }
+        if let Some(value) = self.max_task_restarts {
+            config.max_task_restarts = value;
+        }
+        if let Some(value) = self.task_restart_cooldown_secs {
+            config.task_restart_cooldown_secs = value;
+        }
+        if let Some(dir) = &self.completed_dir {
+            config.completed_dir = Some(dir.clone());
+        }
+        if let Some(dir) = &self.temp_dir {
+            config.temp_dir = Some(dir.clone());
+        }
+        if let Some(headers) = &self.default_headers {
+            config.default_headers = headers.clone();
+        }
+    }
+}
+
+struct DaemonArgs {
+    interval_secs: u64,
+    once: bool,
+    /// Exit once nothing has been queued, running or waiting to restart for this long.
+    idle_exit_secs: Option<u64>,
+    log_format: LogFormat,
+    config_path: Option<PathBuf>,
+    /// Unix socket to answer `idm-cli --daemon` on.
+    socket: Option<PathBuf>,
+    flags: ConfigOverrides,
+}
+
+impl DaemonArgs {
+    /// Defaults, then the config file, then command-line flags.
+    fn load_config(&self) -> Result<EngineConfig, idm_core::CoreError> {
+        let mut config = EngineConfig::default();
+        if let Some(path) = &self.config_path {
+            let text = fs::read_to_string(path)
+                .map_err(|err| idm_core::CoreError::Io(format!("{}: {}", path.display(), err)))?;
+            let file: ConfigOverrides = serde_json::from_str(&text).map_err(|err| {
+                idm_core::CoreError::InvalidConfig(format!("{}: {}", path.display(), err))
+            })?;
+            file.apply(&mut config);
+        }
+        self.flags.apply(&mut config);
+        Ok(config)
+    }
+}
 
 fn main() {
-    let config = EngineConfig::default();
-    let engine = match build_engine(config) {
-        Ok(engine) => engine,
+    let args = parse_args();
+    let log_format = args.log_format;
+
+    let engine = match args.load_config().and_then(build_engine) {
+        Ok(engine) => Arc::new(engine),
         Err(err) => {
-            eprintln!("error: {}", err);
+            log_error(log_format, &err);
             return;
         }
     };
 
-    let (interval_secs, once) = parse_args();
+    let printer =
+        (log_format == LogFormat::Json).then(|| Arc::new(EventPrinter::spawn(engine.subscribe())));
 
-    loop {
-        if let Err(err) = engine.enqueue_queued() {
-            eprintln!("error: {}", err);
+    let args = Arc::new(args);
+    watch_reload_signal(Arc::clone(&engine), Arc::clone(&args));
+    watch_stop_signals(Arc::clone(&engine), Arc::clone(&args), printer.clone());
+    if let Some(path) = &args.socket {
+        if let Err(err) = ipc::serve(Arc::clone(&engine), path) {
+            log_error(log_format, &err);
+            return;
+        }
+    }
+
+    match engine.reconcile_on_start() {
+        Ok(recovered) => {
+            for id in recovered {
+                log_recovered(log_format, &id);
+            }
         }
+        Err(err) => log_error(log_format, &err),
+    }
+    // Pick up tasks interrupted by the previous shutdown; user pauses are left alone.
+    if let Err(err) = engine.resume_all() {
+        log_error(log_format, &err);
+    }
+
+    let mut idle_since = Instant::now();
+    loop {
+        let busy = match engine.enqueue_queued_detailed() {
+            Ok(report) => {
+                report.queued + report.already_queued + report.skipped_active + report.cooling_down
+                    > 0
+            }
+            Err(err) => {
+                log_error(log_format, &err);
+                true
+            }
+        };
         if let Err(err) = engine.run() {
-            eprintln!("error: {}", err);
+            log_error(log_format, &err);
         }
-        if once {
+        if args.once {
             break;
         }
-        thread::sleep(Duration::from_secs(interval_secs));
+        if busy {
+            idle_since = Instant::now();
+        } else if let Some(limit) = args.idle_exit_secs {
+            if idle_since.elapsed() >= Duration::from_secs(limit) {
+                log_idle_exit(log_format, limit);
+                break;
+            }
+        }
+        thread::sleep(Duration::from_secs(args.interval_secs));
+    }
+
+    if let Err(err) = engine.shutdown() {
+        log_error(log_format, &err);
+    }
+    drop(engine);
+    if let Some(printer) = &printer {
+        printer.finish();
+    }
+    if let Some(path) = &args.socket {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Prints engine events as JSON lines on its own thread until `finish`.
+struct EventPrinter {
+    finishing: Arc<AtomicBool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl EventPrinter {
+    fn spawn(events: Receiver<EngineEvent>) -> Self {
+        let finishing = Arc::new(AtomicBool::new(false));
+        let done = Arc::clone(&finishing);
+        let handle = thread::spawn(move || loop {
+            match events.recv_timeout(Duration::from_millis(100)) {
+                Ok(event) => print_json(&event),
+                Err(RecvTimeoutError::Timeout) if !done.load(Ordering::SeqCst) => {}
+                Err(_) => break,
+            }
+        });
+        Self {
+            finishing,
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// Prints the events already sent, then stops the thread. The socket and signal
+    /// threads keep the engine, and so the bus, alive, so the channel never closes.
+    fn finish(&self) {
+        self.finishing.store(true, Ordering::SeqCst);
+        let handle = self.handle.lock().ok().and_then(|mut handle| handle.take());
+        if let Some(handle) = handle {
+            let _ = handle.join();
+        }
     }
 }
 
@@ -40,26 +266,247 @@ fn build_engine(config: EngineConfig) -> Result<DownloadEngine, idm_core::CoreEr
     Ok(engine)
 }
 
-fn parse_args() -> (u64, bool) {
-    let mut interval_secs = 2u64;
-    let mut once = false;
-    let mut args = env::args().skip(1);
+/// Re-reads the config on `SIGHUP` and applies it to the running engine. A config that
+/// fails to load is reported and the current one kept.
+#[cfg(unix)]
+fn watch_reload_signal(engine: Arc<DownloadEngine>, args: Arc<DaemonArgs>) {
+    use signal_hook::consts::SIGHUP;
+    use signal_hook::iterator::Signals;
+
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            log_error(args.log_format, &idm_core::CoreError::Io(err.to_string()));
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            let reloaded = args
+                .load_config()
+                .and_then(|config| reload(&engine, config, args.log_format));
+            if let Err(err) = reloaded {
+                log_error(args.log_format, &err);
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn watch_reload_signal(_engine: Arc<DownloadEngine>, _args: Arc<DaemonArgs>) {}
+
+/// Pauses running tasks on `SIGTERM`/`SIGINT` so their progress is saved, then exits.
+#[cfg(unix)]
+fn watch_stop_signals(
+    engine: Arc<DownloadEngine>,
+    args: Arc<DaemonArgs>,
+    printer: Option<Arc<EventPrinter>>,
+) {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+    use std::io::Write;
+
+    let mut signals = match Signals::new([SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            log_error(args.log_format, &idm_core::CoreError::Io(err.to_string()));
+            return;
+        }
+    };
+    thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            if let Err(err) = engine.shutdown() {
+                log_error(args.log_format, &err);
+            }
+            if let Some(printer) = &printer {
+                printer.finish();
+            }
+            if let Some(path) = &args.socket {
+                let _ = fs::remove_file(path);
+            }
+            let _ = std::io::stdout().flush();
+            std::process::exit(0);
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn watch_stop_signals(
+    _engine: Arc<DownloadEngine>,
+    _args: Arc<DaemonArgs>,
+    _printer: Option<Arc<EventPrinter>>,
+) {
+}
+
+#[cfg(unix)]
+fn reload(
+    engine: &DownloadEngine,
+    config: EngineConfig,
+    format: LogFormat,
+) -> Result<(), idm_core::CoreError> {
+    let reloaded = ConfigReloaded {
+        event: "config_reloaded",
+        max_concurrent_tasks: config.max_concurrent_tasks,
+        global_speed_limit_bytes_per_sec: config.global_speed_limit_bytes_per_sec,
+    };
+    engine.reconfigure(config)?;
+    match format {
+        LogFormat::Text => eprintln!(
+            "config reloaded: max_concurrent_tasks={} global_speed_limit={}",
+            reloaded.max_concurrent_tasks,
+            reloaded
+                .global_speed_limit_bytes_per_sec
+                .map(|limit| limit.to_string())
+                .unwrap_or_else(|| "unlimited".to_string())
+        ),
+        LogFormat::Json => print_json(&reloaded),
+    }
+    Ok(())
+}
+
+fn log_recovered(format: LogFormat, id: &idm_core::TaskId) {
+    match format {
+        LogFormat::Text => eprintln!("recovered interrupted task {}", id),
+        LogFormat::Json => print_json(&TaskRecovered {
+            event: "task_recovered",
+            id: id.to_string(),
+        }),
+    }
+}
+
+fn log_idle_exit(format: LogFormat, idle_secs: u64) {
+    match format {
+        LogFormat::Text => eprintln!("idle for {}s, exiting", idle_secs),
+        LogFormat::Json => print_json(&IdleExit {
+            event: "idle_exit",
+            idle_secs,
+        }),
+    }
+}
+
+fn log_error(format: LogFormat, err: &idm_core::CoreError) {
+    match format {
+        LogFormat::Text => eprintln!("error: {}", err),
+        LogFormat::Json => print_json(&DaemonError {
+            event: "error",
+            message: err.to_string(),
+        }),
+    }
+}
+
+fn print_json<T: Serialize>(body: &T) {
+    let line = JsonLine {
+        ts: now_epoch(),
+        body,
+    };
+    if let Ok(value) = serde_json::to_string(&line) {
+        println!("{}", value);
+    }
+}
+
+fn now_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn parse_args() -> DaemonArgs {
+    let mut args = DaemonArgs {
+        interval_secs: 2,
+        once: false,
+        idle_exit_secs: None,
+        log_format: LogFormat::Text,
+        config_path: None,
+        socket: None,
+        flags: ConfigOverrides::default(),
+    };
+    let mut argv = env::args().skip(1);
 
-    while let Some(arg) = args.next() {
+    while let Some(arg) = argv.next() {
         match arg.as_str() {
             "--interval" => {
-                if let Some(value) = args.next() {
+                if let Some(value) = argv.next() {
                     if let Ok(parsed) = value.parse::<u64>() {
-                        interval_secs = parsed.max(1);
+                        args.interval_secs = parsed.max(1);
                     }
                 }
             }
             "--once" => {
-                once = true;
+                args.once = true;
+            }
+            "--idle-exit" => {
+                args.idle_exit_secs = parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--log-format" => {
+                if let Some(value) = argv.next() {
+                    match value.as_str() {
+                        "json" => args.log_format = LogFormat::Json,
+                        "text" => args.log_format = LogFormat::Text,
+                        _ => eprintln!("unknown log format: {}", value),
+                    }
+                }
+            }
+            "--config" => {
+                args.config_path = argv.next().map(PathBuf::from);
+            }
+            "--socket" => {
+                args.socket = argv.next().map(PathBuf::from);
+            }
+            "--max-concurrent" => {
+                args.flags.max_concurrent_tasks = parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--max-segments" => {
+                args.flags.max_segments_per_task =
+                    parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--fixed-segments" => {
+                args.flags.fixed_segments = parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--max-per-host" => {
+                args.flags.max_connections_per_host =
+                    parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--speed-limit" => {
+                args.flags.global_speed_limit_bytes_per_sec =
+                    parse_flag(&arg, argv.next(), parse_rate);
+            }
+            "--task-speed-limit" => {
+                args.flags.per_task_speed_limit_bytes_per_sec =
+                    parse_flag(&arg, argv.next(), parse_rate);
+            }
+            "--retries" => {
+                args.flags.retry_count = parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--restarts" => {
+                args.flags.max_task_restarts = parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--restart-cooldown" => {
+                args.flags.task_restart_cooldown_secs =
+                    parse_flag(&arg, argv.next(), |v| v.parse().ok());
+            }
+            "--completed-dir" => {
+                args.flags.completed_dir = argv.next().map(PathBuf::from);
+            }
+            "--temp-dir" => {
+                args.flags.temp_dir = argv.next().map(PathBuf::from);
             }
             _ => {}
         }
     }
 
-    (interval_secs, once)
+    args
+}
+
+fn parse_flag<T>(
+    flag: &str,
+    value: Option<String>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Option<T> {
+    let value = value?;
+    let parsed = parse(&value);
+    if parsed.is_none() {
+        eprintln!("invalid value for {}: {}", flag, value);
+    }
+    parsed
 }
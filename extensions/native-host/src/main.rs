@@ -6,11 +6,15 @@ use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 
 use idm_core::config::EngineConfig;
+use idm_core::data_url::is_data_url;
 use idm_core::storage::SqliteStorage;
-use idm_core::DownloadEngine;
+use idm_core::{default_download_dir, DownloadEngine, TaskSummary};
 
 #[derive(Debug, Deserialize)]
 struct NativeRequest {
+    /// `"add"` (the default) or `"find"`.
+    #[serde(default)]
+    action: Option<String>,
     url: String,
     dest_path: Option<String>,
 }
@@ -20,6 +24,9 @@ struct NativeResponse {
     ok: bool,
     id: Option<String>,
     error: Option<String>,
+    /// Matches for a `"find"` request, unfinished tasks first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tasks: Option<Vec<TaskSummary>>,
 }
 
 fn main() {
@@ -30,6 +37,7 @@ fn main() {
                 ok: false,
                 id: None,
                 error: Some(err),
+                tasks: None,
             });
             return;
         }
@@ -46,6 +54,7 @@ fn main() {
                         ok: false,
                         id: None,
                         error: Some(err),
+                        tasks: None,
                     });
                 }
             },
@@ -55,6 +64,7 @@ fn main() {
                     ok: false,
                     id: None,
                     error: Some(err.to_string()),
+                    tasks: None,
                 });
                 break;
             }
@@ -63,7 +73,14 @@ fn main() {
 }
 
 fn build_engine() -> Result<DownloadEngine, String> {
-    let mut engine = DownloadEngine::new(EngineConfig::default());
+    let mut config = EngineConfig::default();
+    if let Ok(value) = env::var("IDM_MAX_TASKS") {
+        let max_tasks = value
+            .parse::<usize>()
+            .map_err(|_| format!("invalid IDM_MAX_TASKS: {}", value))?;
+        config.max_tasks = Some(max_tasks).filter(|v| *v > 0);
+    }
+    let mut engine = DownloadEngine::new(config);
     let db_path = match env::var("IDM_DB") {
         Ok(path) => path,
         Err(_) => default_db_path().to_string_lossy().to_string(),
@@ -80,6 +97,21 @@ fn handle_message(engine: &DownloadEngine, bytes: &[u8]) -> Result<NativeRespons
     if request.url.trim().is_empty() {
         return Err("url is required".to_string());
     }
+    match request.action.as_deref().unwrap_or("add") {
+        "add" => {}
+        "find" => {
+            let tasks = engine
+                .find_by_url(&request.url)
+                .map_err(|err| err.to_string())?;
+            return Ok(NativeResponse {
+                ok: true,
+                id: None,
+                error: None,
+                tasks: Some(tasks.iter().map(TaskSummary::from).collect()),
+            });
+        }
+        other => return Err(format!("unknown action: {}", other)),
+    }
 
     let dest_path = request
         .dest_path
@@ -93,6 +125,7 @@ fn handle_message(engine: &DownloadEngine, bytes: &[u8]) -> Result<NativeRespons
         ok: true,
         id: Some(id.to_string()),
         error: None,
+        tasks: None,
     })
 }
 
@@ -122,42 +155,25 @@ fn write_response(resp: &NativeResponse) -> io::Result<()> {
 }
 
 fn default_db_path() -> PathBuf {
-    let home = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-    let dir = PathBuf::from(home).join(".idm-open");
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    let dir = home.join(".idm-open");
     let _ = fs::create_dir_all(&dir);
     dir.join("idm.db")
 }
 
 fn default_dest_path(url: &str) -> String {
+    let dir = default_download_dir();
+    // The engine names these after their media type.
+    if is_data_url(url) {
+        return format!("{}/", dir.to_string_lossy());
+    }
     let filename = filename_from_url(url);
-    let dir = download_dir();
     dir.join(filename).to_string_lossy().to_string()
 }
 
-fn download_dir() -> PathBuf {
-    if let Ok(dir) = env::var("IDM_DOWNLOAD_DIR") {
-        return PathBuf::from(dir);
-    }
-    let shared = PathBuf::from("/storage/emulated/0/Download");
-    if shared.exists() {
-        return shared;
-    }
-    let sdcard = PathBuf::from("/sdcard/Download");
-    if sdcard.exists() {
-        return sdcard;
-    }
-    let home = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-    let downloads = PathBuf::from(&home).join("Downloads");
-    if downloads.exists() {
-        return downloads;
-    }
-    let downloads_lower = PathBuf::from(&home).join("downloads");
-    if downloads_lower.exists() {
-        return downloads_lower;
-    }
-    PathBuf::from("/tmp")
-}
-
 fn filename_from_url(url: &str) -> String {
     let trimmed = url.split('?').next().unwrap_or(url);
     let name = trimmed.rsplit('/').next().unwrap_or("download.bin");
@@ -3,11 +3,145 @@ import 'dart:io';
 
 import 'package:ffi/ffi.dart';
 
+/// ABI revision these bindings were written against; must match `idm_abi_version()`.
+const int kIdmAbiVersion = 1;
+
+/// How a download's file is sized up front; see `idm_config_set_preallocate`.
+enum IdmPreallocate { always, never, sparse }
+
+/// When requests ask for `Accept-Encoding: identity`; see
+/// `idm_config_set_identity_encoding`.
+enum IdmIdentityEncoding { never, ranged, always }
+
+/// What a download does when its file already exists; see
+/// `idm_config_set_on_existing`.
+enum IdmOnExisting { overwrite, skip, rename }
+
+/// Engine settings for `IdmCore.init`; fields left null keep the engine's defaults.
+class IdmConfig {
+  const IdmConfig({
+    this.maxConcurrentTasks,
+    this.segmentsPerTask,
+    this.minSegmentSizeBytes,
+    this.globalLimitBytesPerSec,
+    this.perTaskLimitBytesPerSec,
+    this.userAgent,
+    this.retryCount,
+    this.retryBackoffSecs,
+    this.taskRetryCount,
+    this.taskRetryBackoffSecs,
+    this.preallocate,
+    this.stallTimeoutSecs,
+    this.maxConnectionsPerHost,
+    this.outputTemplate,
+    this.progressEventIntervalMs,
+    this.identityEncoding,
+    this.maxTasks,
+    this.autoReferer,
+    this.onExisting,
+  });
+
+  final int? maxConcurrentTasks;
+  final int? segmentsPerTask;
+  final int? minSegmentSizeBytes;
+  final int? globalLimitBytesPerSec;
+  final int? perTaskLimitBytesPerSec;
+  final String? userAgent;
+  final int? retryCount;
+  final int? retryBackoffSecs;
+  final int? taskRetryCount;
+  final int? taskRetryBackoffSecs;
+  final IdmPreallocate? preallocate;
+  /// `0` disables read-stall detection.
+  final int? stallTimeoutSecs;
+  /// `0` removes the per-host cap.
+  final int? maxConnectionsPerHost;
+  /// Filename template for directory destinations, e.g. `%(host)s-%(name)s.%(ext)s`.
+  final String? outputTemplate;
+  /// Minimum milliseconds between progress events for one task; `0` sends every update.
+  final int? progressEventIntervalMs;
+  final IdmIdentityEncoding? identityEncoding;
+  /// Cap on unfinished tasks; adds fail once it is reached. `0` means no cap.
+  final int? maxTasks;
+  /// Send the page a direct link was scraped from as its `Referer`.
+  final bool? autoReferer;
+  final IdmOnExisting? onExisting;
+
+  /// Applies the set fields to the `idm_config_new` handle `config`.
+  void _applyTo(DynamicLibrary lib, Pointer<Void> config) {
+    void setU64(String symbol, int? value) {
+      if (value == null) {
+        return;
+      }
+      final setter =
+          lib.lookupFunction<_ConfigSetU64Native, _ConfigSetInt>(symbol);
+      _checkConfig(symbol, setter(config, value));
+    }
+
+    void setU32(String symbol, int? value) {
+      if (value == null) {
+        return;
+      }
+      final setter =
+          lib.lookupFunction<_ConfigSetU32Native, _ConfigSetInt>(symbol);
+      _checkConfig(symbol, setter(config, value));
+    }
+
+    void setString(String symbol, String? value) {
+      if (value == null) {
+        return;
+      }
+      final setter =
+          lib.lookupFunction<_ConfigSetStringNative, _ConfigSetString>(symbol);
+      final valuePtr = value.toNativeUtf8();
+      final result = setter(config, valuePtr);
+      calloc.free(valuePtr);
+      _checkConfig(symbol, result);
+    }
+
+    void setBool(String symbol, bool? value) {
+      if (value == null) {
+        return;
+      }
+      final setter =
+          lib.lookupFunction<_ConfigSetBoolNative, _ConfigSetBool>(symbol);
+      _checkConfig(symbol, setter(config, value));
+    }
+
+    setU32('idm_config_set_max_concurrent', maxConcurrentTasks);
+    setU32('idm_config_set_segments', segmentsPerTask);
+    setU64('idm_config_set_min_segment_size', minSegmentSizeBytes);
+    setU64('idm_config_set_global_limit', globalLimitBytesPerSec);
+    setU64('idm_config_set_per_task_limit', perTaskLimitBytesPerSec);
+    setU32('idm_config_set_retry_count', retryCount);
+    setU64('idm_config_set_retry_backoff_secs', retryBackoffSecs);
+    setString('idm_config_set_user_agent', userAgent);
+    setU32('idm_config_set_task_retry_count', taskRetryCount);
+    setU64('idm_config_set_task_retry_backoff_secs', taskRetryBackoffSecs);
+    setU32('idm_config_set_preallocate', preallocate?.index);
+    setU64('idm_config_set_stall_timeout_secs', stallTimeoutSecs);
+    setU32('idm_config_set_max_connections_per_host', maxConnectionsPerHost);
+    setString('idm_config_set_output_template', outputTemplate);
+    setU64('idm_config_set_progress_event_interval_ms', progressEventIntervalMs);
+    setU32('idm_config_set_identity_encoding', identityEncoding?.index);
+    setU64('idm_config_set_max_tasks', maxTasks);
+    setBool('idm_config_set_auto_referer', autoReferer);
+    setU32('idm_config_set_on_existing', onExisting?.index);
+  }
+}
+
+void _checkConfig(String symbol, int result) {
+  if (result != 0) {
+    throw ArgumentError('$symbol rejected its value');
+  }
+}
+
 class IdmCore {
   IdmCore._(this._lib, this._engine);
 
   final DynamicLibrary _lib;
   final Pointer<Void> _engine;
+  NativeCallable<_EventCallbackNative>? _eventCallback;
 
   late final _EngineFree _engineFree =
       _lib.lookupFunction<_EngineFreeNative, _EngineFree>('idm_engine_free');
@@ -16,9 +150,15 @@ class IdmCore {
   late final _EngineListTasksJson _engineListTasksJson = _lib
       .lookupFunction<_EngineListTasksJsonNative, _EngineListTasksJson>(
           'idm_engine_list_tasks_json');
+  late final _EngineListTasksDeltaJson _engineListTasksDeltaJson = _lib
+      .lookupFunction<_EngineListTasksDeltaJsonNative, _EngineListTasksDeltaJson>(
+          'idm_engine_list_tasks_delta_json');
   late final _EngineGetTaskJson _engineGetTaskJson = _lib
       .lookupFunction<_EngineGetTaskJsonNative, _EngineGetTaskJson>(
           'idm_engine_get_task_json');
+  late final _EngineFindByUrlJson _engineFindByUrlJson =
+      _lib.lookupFunction<_EngineFindByUrlJsonNative, _EngineFindByUrlJson>(
+          'idm_engine_find_by_url_json');
   late final _EnginePause _enginePause =
       _lib.lookupFunction<_EnginePauseNative, _EnginePause>('idm_engine_pause_task');
   late final _EngineResume _engineResume =
@@ -33,26 +173,99 @@ class IdmCore {
   late final _EngineStartNext _engineStartNext =
       _lib.lookupFunction<_EngineStartNextNative, _EngineStartNext>(
           'idm_engine_start_next');
+  late final _EngineSpeedHistoryJson _engineSpeedHistoryJson = _lib
+      .lookupFunction<_EngineSpeedHistoryJsonNative, _EngineSpeedHistoryJson>(
+          'idm_engine_speed_history_json');
+  late final _EngineTaskEtaSecs _engineTaskEtaSecs =
+      _lib.lookupFunction<_EngineTaskEtaSecsNative, _EngineTaskEtaSecs>(
+          'idm_engine_task_eta_secs');
+  late final _EngineSetEventCallback _engineSetEventCallback =
+      _lib.lookupFunction<_EngineSetEventCallbackNative, _EngineSetEventCallback>(
+          'idm_engine_set_event_callback');
+  late final _EngineProbeJson _engineProbeJson =
+      _lib.lookupFunction<_EngineProbeJsonNative, _EngineProbeJson>(
+          'idm_engine_probe_json');
+  late final _LastErrorMessage _lastErrorMessage =
+      _lib.lookupFunction<_LastErrorMessageNative, _LastErrorMessage>(
+          'idm_last_error_message');
   late final _StringFree _stringFree =
       _lib.lookupFunction<_StringFreeNative, _StringFree>('idm_string_free');
 
-  static Future<IdmCore> init(String dbPath) async {
+  /// Opens the engine on the SQLite database at `dbPath`, configured by `config` when
+  /// given.
+  static Future<IdmCore> init(String dbPath, {IdmConfig? config}) async {
     final lib = _openLibrary();
-    final engineNewWithDb = lib.lookupFunction<_EngineNewWithDbNative,
-        _EngineNewWithDb>('idm_engine_new_with_db');
+    final abiVersion =
+        lib.lookupFunction<_AbiVersionNative, _AbiVersion>('idm_abi_version')();
+    if (abiVersion != kIdmAbiVersion) {
+      throw StateError(
+          'idm_core_ffi ABI $abiVersion does not match expected $kIdmAbiVersion');
+    }
     final pathPtr = dbPath.toNativeUtf8();
-    final engine = engineNewWithDb(pathPtr);
-    calloc.free(pathPtr);
+    final Pointer<Void> engine;
+    try {
+      engine = config == null
+          ? _openWithDb(lib, pathPtr)
+          : _openWithConfig(lib, config, pathPtr);
+    } finally {
+      calloc.free(pathPtr);
+    }
     if (engine == nullptr) {
       throw StateError('Failed to open SQLite database');
     }
     return IdmCore._(lib, engine);
   }
 
+  static Pointer<Void> _openWithDb(DynamicLibrary lib, Pointer<Utf8> path) {
+    final engineNewWithDb = lib.lookupFunction<_EngineNewWithDbNative,
+        _EngineNewWithDb>('idm_engine_new_with_db');
+    return engineNewWithDb(path);
+  }
+
+  static Pointer<Void> _openWithConfig(
+      DynamicLibrary lib, IdmConfig config, Pointer<Utf8> path) {
+    final configNew =
+        lib.lookupFunction<_ConfigNewNative, _ConfigNew>('idm_config_new');
+    final configFree =
+        lib.lookupFunction<_ConfigFreeNative, _ConfigFree>('idm_config_free');
+    final engineNewWithConfig = lib.lookupFunction<_EngineNewWithConfigNative,
+        _EngineNewWithConfig>('idm_engine_new_with_config');
+    final handle = configNew();
+    try {
+      config._applyTo(lib, handle);
+      return engineNewWithConfig(handle, path);
+    } finally {
+      configFree(handle);
+    }
+  }
+
   void dispose() {
+    setEventListener(null);
     _engineFree(_engine);
   }
 
+  /// Calls `onEvent` with each engine event as JSON, replacing any earlier listener;
+  /// null removes it. Progress is coalesced per task, so intermediate byte counts may
+  /// be skipped; state changes always arrive.
+  void setEventListener(void Function(String eventJson)? onEvent) {
+    final previous = _eventCallback;
+    _eventCallback = null;
+    if (onEvent == null) {
+      _engineSetEventCallback(_engine, nullptr, nullptr);
+    } else {
+      final callback = NativeCallable<_EventCallbackNative>.listener(
+          (Pointer<Utf8> json, Pointer<Void> _) {
+        final value = _consumeString(json);
+        if (value != null) {
+          onEvent(value);
+        }
+      });
+      _eventCallback = callback;
+      _engineSetEventCallback(_engine, callback.nativeFunction, nullptr);
+    }
+    previous?.close();
+  }
+
   String? addTask(String url, String dest) {
     final urlPtr = url.toNativeUtf8();
     final destPtr = dest.toNativeUtf8();
@@ -76,6 +289,13 @@ class IdmCore {
     return _consumeString(result);
   }
 
+  /// Tasks updated after `sinceEpoch` (seconds); pass the previous poll time minus one,
+  /// as timestamps have one-second resolution.
+  String? listTasksDeltaJson(int sinceEpoch) {
+    final result = _engineListTasksDeltaJson(_engine, sinceEpoch);
+    return _consumeString(result);
+  }
+
   String? getTaskJson(String id) {
     final idPtr = id.toNativeUtf8();
     final result = _engineGetTaskJson(_engine, idPtr);
@@ -83,6 +303,45 @@ class IdmCore {
     return _consumeString(result);
   }
 
+  /// `[epoch_ms, downloaded_bytes]` pairs for the task's last minute, oldest first.
+  String? speedHistoryJson(String id) {
+    final idPtr = id.toNativeUtf8();
+    final result = _engineSpeedHistoryJson(_engine, idPtr);
+    calloc.free(idPtr);
+    return _consumeString(result);
+  }
+
+  /// Seconds left at the task's smoothed speed, or null without an estimate.
+  int? taskEtaSecs(String id) {
+    final idPtr = id.toNativeUtf8();
+    final result = _engineTaskEtaSecs(_engine, idPtr);
+    calloc.free(idPtr);
+    return result < 0 ? null : result;
+  }
+
+  /// Resolves `url` without adding a task; null on failure, see `lastErrorMessage`.
+  /// Blocks on the network, so call it from a background isolate.
+  String? probeJson(String url) {
+    final urlPtr = url.toNativeUtf8();
+    final result = _engineProbeJson(_engine, urlPtr);
+    calloc.free(urlPtr);
+    return _consumeString(result);
+  }
+
+  /// Why the last failed call on this thread failed, if it recorded a reason.
+  String? lastErrorMessage() {
+    return _consumeString(_lastErrorMessage());
+  }
+
+  /// Tasks stored for `url` as their URL or a mirror, unfinished ones first; null on
+  /// failure, see `lastErrorMessage`.
+  String? findByUrlJson(String url) {
+    final urlPtr = url.toNativeUtf8();
+    final result = _engineFindByUrlJson(_engine, urlPtr);
+    calloc.free(urlPtr);
+    return _consumeString(result);
+  }
+
   bool pauseTask(String id) => _controlTask(id, _enginePause);
   bool resumeTask(String id) => _controlTask(id, _engineResume);
   bool cancelTask(String id) => _controlTask(id, _engineCancel);
@@ -115,9 +374,33 @@ DynamicLibrary _openLibrary() {
   return DynamicLibrary.process();
 }
 
+typedef _AbiVersionNative = Uint32 Function();
+typedef _AbiVersion = int Function();
+
 typedef _EngineNewWithDbNative = Pointer<Void> Function(Pointer<Utf8>);
 typedef _EngineNewWithDb = Pointer<Void> Function(Pointer<Utf8>);
 
+typedef _EngineNewWithConfigNative = Pointer<Void> Function(
+    Pointer<Void>, Pointer<Utf8>);
+typedef _EngineNewWithConfig = Pointer<Void> Function(
+    Pointer<Void>, Pointer<Utf8>);
+
+typedef _ConfigNewNative = Pointer<Void> Function();
+typedef _ConfigNew = Pointer<Void> Function();
+
+typedef _ConfigFreeNative = Void Function(Pointer<Void>);
+typedef _ConfigFree = void Function(Pointer<Void>);
+
+typedef _ConfigSetU32Native = Int32 Function(Pointer<Void>, Uint32);
+typedef _ConfigSetU64Native = Int32 Function(Pointer<Void>, Uint64);
+typedef _ConfigSetInt = int Function(Pointer<Void>, int);
+
+typedef _ConfigSetBoolNative = Int32 Function(Pointer<Void>, Bool);
+typedef _ConfigSetBool = int Function(Pointer<Void>, bool);
+
+typedef _ConfigSetStringNative = Int32 Function(Pointer<Void>, Pointer<Utf8>);
+typedef _ConfigSetString = int Function(Pointer<Void>, Pointer<Utf8>);
+
 typedef _EngineFreeNative = Void Function(Pointer<Void>);
 typedef _EngineFree = void Function(Pointer<Void>);
 
@@ -129,11 +412,27 @@ typedef _EngineAddTask = Pointer<Utf8> Function(
 typedef _EngineListTasksJsonNative = Pointer<Utf8> Function(Pointer<Void>);
 typedef _EngineListTasksJson = Pointer<Utf8> Function(Pointer<Void>);
 
+typedef _EngineListTasksDeltaJsonNative = Pointer<Utf8> Function(
+    Pointer<Void>, Uint64);
+typedef _EngineListTasksDeltaJson = Pointer<Utf8> Function(Pointer<Void>, int);
+
 typedef _EngineGetTaskJsonNative = Pointer<Utf8> Function(
     Pointer<Void>, Pointer<Utf8>);
 typedef _EngineGetTaskJson = Pointer<Utf8> Function(
     Pointer<Void>, Pointer<Utf8>);
 
+typedef _EventCallbackNative = Void Function(Pointer<Utf8>, Pointer<Void>);
+
+typedef _EngineSetEventCallbackNative = Int32 Function(Pointer<Void>,
+    Pointer<NativeFunction<_EventCallbackNative>>, Pointer<Void>);
+typedef _EngineSetEventCallback = int Function(Pointer<Void>,
+    Pointer<NativeFunction<_EventCallbackNative>>, Pointer<Void>);
+
+typedef _EngineFindByUrlJsonNative = Pointer<Utf8> Function(
+    Pointer<Void>, Pointer<Utf8>);
+typedef _EngineFindByUrlJson = Pointer<Utf8> Function(
+    Pointer<Void>, Pointer<Utf8>);
+
 typedef _EnginePauseNative = Int32 Function(Pointer<Void>, Pointer<Utf8>);
 typedef _EnginePause = int Function(Pointer<Void>, Pointer<Utf8>);
 
@@ -154,5 +453,21 @@ typedef _EngineEnqueueQueued = int Function(Pointer<Void>);
 typedef _EngineStartNextNative = Pointer<Utf8> Function(Pointer<Void>);
 typedef _EngineStartNext = Pointer<Utf8> Function(Pointer<Void>);
 
+typedef _EngineSpeedHistoryJsonNative = Pointer<Utf8> Function(
+    Pointer<Void>, Pointer<Utf8>);
+typedef _EngineSpeedHistoryJson = Pointer<Utf8> Function(
+    Pointer<Void>, Pointer<Utf8>);
+
+typedef _EngineTaskEtaSecsNative = Int64 Function(Pointer<Void>, Pointer<Utf8>);
+typedef _EngineTaskEtaSecs = int Function(Pointer<Void>, Pointer<Utf8>);
+
+typedef _EngineProbeJsonNative = Pointer<Utf8> Function(
+    Pointer<Void>, Pointer<Utf8>);
+typedef _EngineProbeJson = Pointer<Utf8> Function(
+    Pointer<Void>, Pointer<Utf8>);
+
+typedef _LastErrorMessageNative = Pointer<Utf8> Function();
+typedef _LastErrorMessage = Pointer<Utf8> Function();
+
 typedef _StringFreeNative = Void Function(Pointer<Void>);
 typedef _StringFree = void Function(Pointer<Void>);
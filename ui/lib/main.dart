@@ -1179,7 +1179,7 @@ class _IdmAppState extends State<IdmApp> {
             Wrap(
               spacing: 8,
               children: [
-                if (status == 'active')
+                if (status == 'active' || status == 'resolving')
                   _buildTaskAction(
                     icon: Icons.pause,
                     color: kNeonYellow,
@@ -1578,7 +1578,7 @@ class _CyberTaskCard extends StatelessWidget {
               Row(
                 mainAxisAlignment: MainAxisAlignment.end,
                 children: [
-                  if (status == 'active')
+                  if (status == 'active' || status == 'resolving')
                     _CyberMiniButton(icon: Icons.pause, color: kNeonYellow, onPressed: onPause),
                   if (status == 'paused' || status == 'failed')
                     _CyberMiniButton(icon: Icons.play_arrow, color: kNeonCyan, onPressed: onResume),
@@ -36,12 +36,23 @@ class Task {
       status: json['status']?.toString() ?? '',
       totalBytes: _asInt(json['total_bytes']),
       downloadedBytes: _asInt(json['downloaded_bytes']),
-      createdAt: _asInt(json['created_at']),
-      updatedAt: _asInt(json['updated_at']),
+      createdAt: _asEpoch(json['created_at']),
+      updatedAt: _asEpoch(json['updated_at']),
       error: json['error']?.toString(),
     );
   }
 
+  /// Epoch seconds from an RFC 3339 string, or from a plain number in older payloads.
+  static int _asEpoch(dynamic value) {
+    if (value is String) {
+      final parsed = DateTime.tryParse(value);
+      if (parsed != null) {
+        return parsed.millisecondsSinceEpoch ~/ 1000;
+      }
+    }
+    return _asInt(value);
+  }
+
   static int _asInt(dynamic value) {
     if (value is int) {
       return value;